@@ -0,0 +1,498 @@
+use crate::encoding;
+use crate::inline::{self, InlineKind};
+use crate::issue::{line_column, Issue, IssueSource, Severity};
+use crate::vendor::VendorDetector;
+use lightningcss::declaration::DeclarationBlock;
+use lightningcss::properties::Property;
+use lightningcss::rules::{CssRule, CssRuleList};
+use lightningcss::stylesheet::{ParserOptions, PrinterOptions, StyleAttribute, StyleSheet};
+use lightningcss::traits::ToCss;
+use lightningcss::values::color::{CssColor, RGBA};
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// The minimum contrast ratio for normal text, as required by WCAG 2.1 level AA.
+const MINIMUM_CONTRAST: f64 = 4.5;
+
+/// Function to audit every HTML and CSS file in a deliverable for common accessibility problems.
+pub fn check_accessibility(
+    student_dir: &Path,
+    vendor: &VendorDetector,
+) -> Result<Vec<Issue>, Box<dyn std::error::Error>> {
+    let mut issues = Vec::new();
+
+    for entry in WalkDir::new(student_dir) {
+        let entry = entry?;
+        let path = entry.path();
+        let relative_path = path.strip_prefix(student_dir).unwrap_or(path);
+
+        let content = match path.extension().and_then(|s| s.to_str()) {
+            Some("html") | Some("css") => match encoding::read_to_string(path) {
+                Ok(content) => content,
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+        // The colours of a library aren't the student's choice
+        if vendor.detect(relative_path, &fs::read(path)?).is_some() {
+            continue;
+        }
+
+        if path.extension().and_then(|s| s.to_str()) == Some("css") {
+            issues.extend(check_contrast_in_stylesheet(relative_path, &content));
+            continue;
+        }
+
+        issues.extend(check_document(relative_path, &content));
+
+        // Colour pairs in <style> elements and style attributes
+        for block in inline::extract_inline_blocks(&content) {
+            let block_issues = match block.kind {
+                InlineKind::Style => check_contrast_in_stylesheet(relative_path, &block.content),
+                InlineKind::StyleAttribute => {
+                    check_contrast_in_attribute(relative_path, &block.content)
+                }
+                InlineKind::Script => continue,
+            };
+            issues.extend(
+                block_issues
+                    .into_iter()
+                    .map(|issue| inline::map_to_host(issue, relative_path, &content, &block)),
+            );
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Function to audit the structure of a single HTML document.
+fn check_document(file: &Path, html: &str) -> Vec<Issue> {
+    let document = Html::parse_document(html);
+    let locator = ElementLocator::new(html);
+    let mut issues = Vec::new();
+
+    let report = |issues: &mut Vec<Issue>,
+                  severity,
+                  location: (Option<u32>, Option<u32>),
+                  message: String| {
+        issues.push(
+            Issue::new(IssueSource::Accessibility, severity, file, message)
+                .at(location.0, location.1),
+        );
+    };
+
+    // The language of the page, used by screen readers for pronunciation
+    let has_lang = document
+        .root_element()
+        .value()
+        .attr("lang")
+        .is_some_and(|lang| !lang.trim().is_empty());
+    if !has_lang {
+        report(
+            &mut issues,
+            Severity::Error,
+            locator.locate("html", 0),
+            "The “html” element is missing a “lang” attribute, e.g. <html lang=\"no\">".to_string(),
+        );
+    }
+
+    let labelled_ids: HashSet<&str> = document
+        .select(&Selector::parse("label[for]").unwrap())
+        .filter_map(|label| label.value().attr("for"))
+        .collect();
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut previous_heading: Option<u32> = None;
+    let mut h1_count = 0;
+
+    for element in document
+        .root_element()
+        .descendants()
+        .filter_map(ElementRef::wrap)
+    {
+        let tag = element.value().name();
+        let index = *counts
+            .entry(tag)
+            .and_modify(|count| *count += 1)
+            .or_insert(0);
+        let location = || locator.locate(tag, index);
+        let attr = |name: &str| element.value().attr(name);
+
+        match tag {
+            "img" if attr("alt").is_none() => report(
+                &mut issues,
+                Severity::Error,
+                location(),
+                format!(
+                    "Image “{}” is missing an “alt” attribute. Describe the image, or use alt=\"\" if it is purely decorative",
+                    attr("src").unwrap_or("")
+                ),
+            ),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = tag[1..].parse::<u32>().unwrap();
+                if level == 1 {
+                    h1_count += 1;
+                }
+                match previous_heading {
+                    None if level != 1 => report(
+                        &mut issues,
+                        Severity::Warning,
+                        location(),
+                        format!("The first heading on the page is “{}”, but should be “h1”", tag),
+                    ),
+                    Some(previous) if level > previous + 1 => report(
+                        &mut issues,
+                        Severity::Warning,
+                        location(),
+                        format!(
+                            "Heading level skipped from “h{}” to “{}”. Headings should not skip levels",
+                            previous, tag
+                        ),
+                    ),
+                    _ => {}
+                }
+                previous_heading = Some(level);
+            }
+            "input" | "select" | "textarea" => {
+                let input_type = attr("type").unwrap_or("text").to_ascii_lowercase();
+                if tag == "input"
+                    && ["hidden", "submit", "button", "reset", "image"].contains(&input_type.as_str())
+                {
+                    continue;
+                }
+
+                let is_labelled = attr("id").is_some_and(|id| labelled_ids.contains(id))
+                    || element
+                        .ancestors()
+                        .filter_map(ElementRef::wrap)
+                        .any(|ancestor| ancestor.value().name() == "label")
+                    || has_aria_name(&element);
+
+                if !is_labelled {
+                    report(
+                        &mut issues,
+                        Severity::Error,
+                        location(),
+                        format!(
+                            "Form control “{}” has no label. Add a <label for=\"...\"> or an “aria-label”",
+                            attr("name").or(attr("id")).unwrap_or(tag)
+                        ),
+                    );
+                }
+            }
+            "a" | "button" if !has_accessible_name(&element) => {
+                if tag == "a" && attr("href").is_none() {
+                    continue;
+                }
+                let kind = if tag == "a" { "Link" } else { "Button" };
+                report(
+                    &mut issues,
+                    Severity::Error,
+                    location(),
+                    format!(
+                        "{} has no text, so screen readers can't tell what it does. Add text or an “aria-label”",
+                        kind
+                    ),
+                );
+            }
+            "table" => {
+                let has_headers = element
+                    .descendants()
+                    .filter_map(ElementRef::wrap)
+                    .any(|descendant| descendant.value().name() == "th");
+                if !has_headers {
+                    report(
+                        &mut issues,
+                        Severity::Warning,
+                        location(),
+                        "Table has no header cells. Use “th” for the column or row headings"
+                            .to_string(),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if h1_count > 1 {
+        report(
+            &mut issues,
+            Severity::Info,
+            locator.locate("h1", 1),
+            format!(
+                "The page has {} “h1” headings, but should only have one",
+                h1_count
+            ),
+        );
+    }
+
+    issues
+}
+
+fn has_aria_name(element: &ElementRef) -> bool {
+    ["aria-label", "aria-labelledby", "title"]
+        .iter()
+        .any(|name| {
+            element
+                .value()
+                .attr(name)
+                .is_some_and(|value| !value.trim().is_empty())
+        })
+}
+
+/// Function to determine whether a link or button has text that a screen reader can announce.
+fn has_accessible_name(element: &ElementRef) -> bool {
+    let has_text = element.text().any(|text| !text.trim().is_empty());
+    let has_image_alt = element
+        .descendants()
+        .filter_map(ElementRef::wrap)
+        .filter(|descendant| descendant.value().name() == "img")
+        .any(|image| {
+            image
+                .value()
+                .attr("alt")
+                .is_some_and(|alt| !alt.trim().is_empty())
+        });
+
+    has_text || has_image_alt || has_aria_name(element)
+}
+
+/// Finds the position of an element in the HTML source, as the parsed document doesn't keep it.
+struct ElementLocator<'a> {
+    html: &'a str,
+    comments: Vec<std::ops::Range<usize>>,
+}
+
+impl<'a> ElementLocator<'a> {
+    fn new(html: &'a str) -> Self {
+        let comment = Regex::new(r"(?s)<!--.*?-->").unwrap();
+        ElementLocator {
+            html,
+            comments: comment.find_iter(html).map(|m| m.range()).collect(),
+        }
+    }
+
+    /// Function to find the line and column of the `index`-th `<tag` in the source.
+    fn locate(&self, tag: &str, index: usize) -> (Option<u32>, Option<u32>) {
+        let start_tag = Regex::new(&format!(r"(?i)<{}\b", regex::escape(tag))).unwrap();
+
+        let location = start_tag
+            .find_iter(self.html)
+            .filter(|m| !self.comments.iter().any(|range| range.contains(&m.start())))
+            .nth(index)
+            .map(|m| {
+                let (line, column) = line_column(self.html, m.start());
+                (Some(line), Some(column))
+            })
+            .unwrap_or((None, None));
+        location
+    }
+}
+
+/// Function to check the colour contrast of every rule in a stylesheet that sets both a text and background colour.
+fn check_contrast_in_stylesheet(file: &Path, content: &str) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    if let Ok(stylesheet) = StyleSheet::parse(
+        content,
+        ParserOptions {
+            error_recovery: true,
+            ..ParserOptions::default()
+        },
+    ) {
+        check_contrast_in_rules(file, &stylesheet.rules, &mut issues);
+    }
+    issues
+}
+
+fn check_contrast_in_rules<R>(file: &Path, rules: &CssRuleList<'_, R>, issues: &mut Vec<Issue>) {
+    for rule in &rules.0 {
+        match rule {
+            CssRule::Style(style) => {
+                let selector = style.selectors.to_css_string(PrinterOptions::default());
+                if let Some(issue) =
+                    check_contrast(file, &style.declarations, &selector.unwrap_or_default())
+                {
+                    issues.push(issue.at(Some(style.loc.line + 1), None));
+                }
+                check_contrast_in_rules(file, &style.rules, issues);
+            }
+            CssRule::Media(media) => check_contrast_in_rules(file, &media.rules, issues),
+            _ => {}
+        }
+    }
+}
+
+fn check_contrast_in_attribute(file: &Path, content: &str) -> Vec<Issue> {
+    StyleAttribute::parse(content, ParserOptions::default())
+        .ok()
+        .and_then(|attribute| check_contrast(file, &attribute.declarations, "style=\"...\""))
+        .map(|issue| issue.at(Some(1), Some(1)))
+        .into_iter()
+        .collect()
+}
+
+/// Function to compute the contrast between the text and background colour set in a declaration block.
+fn check_contrast(
+    file: &Path,
+    declarations: &DeclarationBlock<'_>,
+    context: &str,
+) -> Option<Issue> {
+    let mut foreground = None;
+    let mut background = None;
+
+    for (property, _) in declarations.iter() {
+        match property {
+            Property::Color(color) => foreground = to_rgba(color),
+            Property::BackgroundColor(color) => background = to_rgba(color),
+            Property::Background(layers) => {
+                if let Some(layer) = layers.last() {
+                    background = to_rgba(&layer.color);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (foreground, background) = (foreground?, background?);
+    // Transparent colours depend on what is behind them, which we can't know
+    if foreground.alpha < 255 || background.alpha < 255 {
+        return None;
+    }
+
+    let ratio = contrast_ratio(&foreground, &background);
+    if ratio >= MINIMUM_CONTRAST {
+        return None;
+    }
+
+    Some(Issue::new(
+        IssueSource::Accessibility,
+        Severity::Warning,
+        file,
+        format!(
+            "Low colour contrast in “{}”: text rgb({}, {}, {}) on background rgb({}, {}, {}) has a ratio of {:.2}:1, but should be at least {}:1",
+            context,
+            foreground.red,
+            foreground.green,
+            foreground.blue,
+            background.red,
+            background.green,
+            background.blue,
+            ratio,
+            MINIMUM_CONTRAST
+        ),
+    ))
+}
+
+fn to_rgba(color: &CssColor) -> Option<RGBA> {
+    match color.to_rgb() {
+        Ok(CssColor::RGBA(rgba)) => Some(rgba),
+        _ => None,
+    }
+}
+
+/// Function to compute the WCAG contrast ratio between two colours.
+fn contrast_ratio(a: &RGBA, b: &RGBA) -> f64 {
+    let luminance = |color: &RGBA| {
+        let channel = |value: u8| {
+            let value = value as f64 / 255.0;
+            if value <= 0.03928 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        0.2126 * channel(color.red) + 0.7152 * channel(color.green) + 0.0722 * channel(color.blue)
+    };
+
+    let (lighter, darker) = {
+        let (a, b) = (luminance(a), luminance(b));
+        if a > b {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    };
+    (lighter + 0.05) / (darker + 0.05)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::VendorConfig;
+
+    const HTML: &str = r#"<html>
+<body>
+  <h1>Oppskrifter</h1>
+  <h3>Middag</h3>
+  <img src="pizza.jpg">
+  <img src="pynt.png" alt="">
+  <label for="navn">Navn</label> <input id="navn">
+  <input name="epost">
+  <label>Alder <input name="alder"></label>
+  <button></button>
+  <a href="/"><img src="logo.png" alt="Forsiden"></a>
+  <p style="color: #777; background-color: #888">Grå</p>
+  <table><tr><td>1</td></tr></table>
+</body>
+</html>
+"#;
+
+    #[test]
+    fn pages_and_stylesheets_are_audited() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("css")).unwrap();
+        fs::write(dir.path().join("index.html"), HTML).unwrap();
+        fs::write(
+            dir.path().join("css/style.css"),
+            "body {\n  color: black;\n  background: white;\n}\n.merke {\n  color: yellow;\n  background-color: white;\n}\n",
+        )
+        .unwrap();
+        // The colours of a library are left out
+        fs::write(
+            dir.path().join("css/bootstrap.min.css"),
+            ".text-muted{color:#eee;background-color:#fff}",
+        )
+        .unwrap();
+
+        let vendor = VendorDetector::new(&VendorConfig::default()).unwrap();
+        let mut issues = check_accessibility(dir.path(), &vendor).unwrap();
+        issues.sort_by_key(|issue| (issue.file.clone(), issue.line));
+
+        let expected = [
+            ("css/style.css", 5, "Low colour contrast in “.merke”"),
+            (
+                "index.html",
+                1,
+                "The “html” element is missing a “lang” attribute",
+            ),
+            ("index.html", 4, "Heading level skipped from “h1” to “h3”"),
+            (
+                "index.html",
+                5,
+                "Image “pizza.jpg” is missing an “alt” attribute",
+            ),
+            ("index.html", 8, "Form control “epost” has no label"),
+            ("index.html", 10, "Button has no text"),
+            ("index.html", 12, "Low colour contrast in “style=\"...\"”"),
+            ("index.html", 13, "Table has no header cells"),
+        ];
+        assert_eq!(issues.len(), expected.len(), "{:#?}", issues);
+        for (issue, (file, line, message)) in issues.iter().zip(expected) {
+            assert_eq!(issue.file, Path::new(file));
+            assert_eq!(issue.line, Some(line));
+            assert!(issue.message.starts_with(message), "{}", issue.message);
+        }
+    }
+
+    #[test]
+    fn contrast_follows_the_wcag_formula() {
+        let black = RGBA::new(0, 0, 0, 1.0);
+        let white = RGBA::new(255, 255, 255, 1.0);
+        assert!((contrast_ratio(&black, &white) - 21.0).abs() < 1e-9);
+        assert!((contrast_ratio(&white, &white) - 1.0).abs() < 1e-9);
+    }
+}
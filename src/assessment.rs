@@ -0,0 +1,318 @@
+use crate::prompts::{self, Phrases};
+use crate::rubric::Rubric;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How far the total may be from the sum of the criteria, to allow for rounding.
+const TOLERANCE: f64 = 0.01;
+
+/// Where in the deliverable a score is justified.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Evidence {
+    /// Path of the file, relative to the student directory.
+    pub file: PathBuf,
+    pub start_line: Option<u32>,
+    pub end_line: Option<u32>,
+}
+
+/// The score for a single grading criterion.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CriterionScore {
+    pub criterion: String,
+    pub points: f64,
+    pub max_points: f64,
+    pub justification: String,
+    #[serde(default)]
+    pub evidence: Vec<Evidence>,
+}
+
+/// The structured grading of a deliverable, stored as `grade.json` in the student directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Assessment {
+    pub criteria: Vec<CriterionScore>,
+    pub total: f64,
+    pub max_total: f64,
+    /// Overall feedback to the student.
+    pub summary: String,
+}
+
+impl Assessment {
+    /// The JSON schema the model's response must follow. With a rubric, only its criteria are allowed.
+    pub fn schema(rubric: Option<&Rubric>, phrases: &Phrases) -> Value {
+        let criterion = match rubric {
+            Some(rubric) => {
+                let names: Vec<&str> = rubric
+                    .all_criteria()
+                    .map(|(_, criterion)| criterion.name.as_str())
+                    .collect();
+                json!({ "type": "string", "enum": names })
+            }
+            None => json!({ "type": "string" }),
+        };
+
+        let evidence = json!({
+            "type": "object",
+            "properties": {
+                "file": { "type": "string", "description": phrases.schema_file },
+                "start_line": { "type": ["integer", "null"] },
+                "end_line": { "type": ["integer", "null"] }
+            },
+            "required": ["file", "start_line", "end_line"],
+            "additionalProperties": false
+        });
+
+        json!({
+            "type": "object",
+            "properties": {
+                "criteria": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "criterion": criterion,
+                            "points": { "type": "number", "minimum": 0 },
+                            "max_points": { "type": "number", "exclusiveMinimum": 0 },
+                            "justification": { "type": "string" },
+                            "evidence": { "type": "array", "items": evidence }
+                        },
+                        "required": ["criterion", "points", "max_points", "justification", "evidence"],
+                        "additionalProperties": false
+                    }
+                },
+                "total": { "type": "number", "description": phrases.schema_total },
+                "max_total": { "type": "number", "description": phrases.schema_max_total },
+                "summary": { "type": "string", "description": phrases.schema_summary }
+            },
+            "required": ["criteria", "total", "max_total", "summary"],
+            "additionalProperties": false
+        })
+    }
+
+    /// Function to parse the model's response, and check it against the schema, the expected maximum score and
+    /// the rubric. Returns every violation found, so they can be sent back to the model.
+    pub fn parse(
+        response: &str,
+        expected_max_total: f64,
+        rubric: Option<&Rubric>,
+        phrases: &Phrases,
+    ) -> Result<Assessment, Vec<String>> {
+        // Models often wrap JSON in a code block, or add a sentence before it
+        let json = match (response.find('{'), response.rfind('}')) {
+            (Some(start), Some(end)) if start < end => &response[start..=end],
+            _ => return Err(vec![phrases.no_json.to_string()]),
+        };
+        let assessment: Assessment = serde_json::from_str(json)
+            .map_err(|e| vec![phrases.not_schema.replace("{error}", &e.to_string())])?;
+
+        let mut violations = assessment.violations(expected_max_total, phrases);
+        if let Some(rubric) = rubric {
+            violations.extend(rubric.violations(&assessment, phrases));
+        }
+        if violations.is_empty() {
+            Ok(assessment)
+        } else {
+            Err(violations)
+        }
+    }
+
+    fn violations(&self, expected_max_total: f64, phrases: &Phrases) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        if self.criteria.is_empty() {
+            violations.push(phrases.no_criteria.to_string());
+        }
+        for score in &self.criteria {
+            let about = |phrase: &str| phrase.replace("{criterion}", &score.criterion);
+            if score.max_points <= 0.0 {
+                violations.push(about(phrases.max_points_not_positive));
+            }
+            if score.points < 0.0 || score.points > score.max_points {
+                violations.push(
+                    about(phrases.points_out_of_range)
+                        .replace("{points}", &score.points.to_string())
+                        .replace("{max_points}", &score.max_points.to_string()),
+                );
+            }
+            if score.justification.trim().is_empty() {
+                violations.push(about(phrases.no_justification));
+            }
+            for evidence in &score.evidence {
+                if let (Some(start), Some(end)) = (evidence.start_line, evidence.end_line) {
+                    if start > end {
+                        violations.push(
+                            about(phrases.lines_reversed)
+                                .replace("{start}", &start.to_string())
+                                .replace("{end}", &end.to_string())
+                                .replace("{file}", &evidence.file.display().to_string()),
+                        );
+                    }
+                }
+            }
+        }
+
+        if (self.total - self.points()).abs() > TOLERANCE {
+            violations.push(
+                phrases
+                    .total_not_sum
+                    .replace("{total}", &self.total.to_string())
+                    .replace("{sum}", &self.points().to_string()),
+            );
+        }
+        if (self.max_total - self.max_points()).abs() > TOLERANCE {
+            violations.push(
+                phrases
+                    .max_total_not_sum
+                    .replace("{max_total}", &self.max_total.to_string())
+                    .replace("{sum}", &self.max_points().to_string()),
+            );
+        }
+        if (self.max_points() - expected_max_total).abs() > TOLERANCE {
+            violations.push(
+                phrases
+                    .wrong_max_total
+                    .replace("{sum}", &self.max_points().to_string())
+                    .replace("{expected}", &expected_max_total.to_string()),
+            );
+        }
+        if self.summary.trim().is_empty() {
+            violations.push(phrases.no_summary.to_string());
+        }
+
+        violations
+    }
+
+    pub fn points(&self) -> f64 {
+        self.criteria.iter().map(|score| score.points).sum()
+    }
+
+    pub fn max_points(&self) -> f64 {
+        self.criteria.iter().map(|score| score.max_points).sum()
+    }
+
+    pub fn write(&self, project_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(
+            project_dir.join("grade.json"),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        println!(
+            "> Grade for {}: {} of {} points",
+            prompts::student_name(project_dir),
+            self.total,
+            self.max_total
+        );
+        Ok(())
+    }
+
+    /// Function to remove the grade from an earlier run, so it isn't taken for the grade of this one.
+    pub fn remove(project_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let grade_file_path = project_dir.join("grade.json");
+        if grade_file_path.exists() {
+            fs::remove_file(&grade_file_path)?;
+        }
+        Ok(())
+    }
+
+    /// Function to render the grading as prose feedback for the student. With a rubric, the criteria are
+    /// laid out in the order of the rubric, under the heading of their section.
+    pub fn to_feedback(&self, rubric: Option<&Rubric>, phrases: &Phrases) -> String {
+        let mut scores: Vec<&CriterionScore> = self.criteria.iter().collect();
+        if let Some(rubric) = rubric {
+            let position = |name: &str| {
+                rubric
+                    .all_criteria()
+                    .position(|(_, criterion)| criterion.name == name)
+            };
+            scores.sort_by_key(|score| position(&score.criterion));
+        }
+
+        let mut output = String::new();
+        let mut current_section = None;
+        for score in scores {
+            let section = rubric
+                .and_then(|rubric| rubric.section_of(&score.criterion))
+                .map(|section| section.name.as_str());
+            if section.is_some() && section != current_section {
+                output.push_str(&format!("## {}\n\n", section.unwrap_or_default()));
+                current_section = section;
+            }
+            output.push_str(&format!(
+                "{} ({} {} {} {})\n{}\n\n",
+                score.criterion,
+                score.points,
+                phrases.of,
+                score.max_points,
+                phrases.points,
+                score.justification.trim()
+            ));
+        }
+        output.push_str(self.summary.trim());
+        output.push_str(&format!(
+            "\n\n{}: {} {} {}",
+            phrases.score, self.total, phrases.of, self.max_total
+        ));
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompts::Language;
+
+    const VALID: &str = r#"{"criteria": [
+        {"criterion": "HTML", "points": 4, "max_points": 6, "justification": "Semantisk.", "evidence": [{"file": "index.html", "start_line": 1, "end_line": 3}]},
+        {"criterion": "CSS", "points": 4, "max_points": 4, "justification": "Ryddig.", "evidence": []}
+    ], "total": 8, "max_total": 10, "summary": "Bra jobbet."}"#;
+
+    fn parse(response: &str, max_total: f64) -> Result<Assessment, Vec<String>> {
+        Assessment::parse(response, max_total, None, Language::Norwegian.phrases())
+    }
+
+    #[test]
+    fn json_is_found_around_text_and_code_blocks() {
+        let response = format!("Her er vurderingen:\n```json\n{}\n```\nLykke til!", VALID);
+        let assessment = parse(&response, 10.0).unwrap();
+        assert_eq!(assessment.total, 8.0);
+        assert_eq!(assessment.criteria[0].evidence[0].end_line, Some(3));
+    }
+
+    #[test]
+    fn responses_without_json_or_outside_the_schema_are_rejected() {
+        assert_eq!(
+            parse("Beklager, jeg kan ikke vurdere dette.", 10.0).unwrap_err(),
+            ["Svaret inneholder ikke et JSON-objekt"]
+        );
+        let violations = parse(r#"{"criteria": [], "total": 0}"#, 10.0).unwrap_err();
+        assert!(violations[0].starts_with("Svaret følger ikke skjemaet"));
+    }
+
+    #[test]
+    fn scores_must_add_up() {
+        let response = VALID
+            .replace(
+                r#""points": 4, "max_points": 4"#,
+                r#""points": 5, "max_points": 4"#,
+            )
+            .replace(
+                r#""start_line": 1, "end_line": 3"#,
+                r#""start_line": 3, "end_line": 1"#,
+            )
+            .replace(r#""total": 8"#, r#""total": 7"#);
+        let violations =
+            Assessment::parse(&response, 12.0, None, Language::English.phrases()).unwrap_err();
+        assert_eq!(
+            violations,
+            [
+                "“HTML”: start_line (3) is after end_line (1) in index.html",
+                "“CSS”: points (5) must be between 0 and max_points (4)",
+                "total (7) isn't the sum of points (9)",
+                "The sum of max_points (10) must be 12",
+            ]
+        );
+    }
+}
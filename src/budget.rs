@@ -0,0 +1,338 @@
+use crate::llm::ModelParams;
+use crate::prompts::{self, Phrases};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tiktoken_rs::CoreBPE;
+
+/// Tokens kept free for the model's response, when `max_tokens` isn't set.
+const DEFAULT_RESPONSE_TOKENS: usize = 2000;
+/// The share of the space for files that a single file may take, before it is truncated.
+const MAX_FILE_SHARE: f64 = 0.3;
+
+/// Counts tokens the way the grading model does. Other models, e.g. on-prem, are counted as GPT-4,
+/// which is close enough for budgeting.
+pub struct TokenCounter {
+    bpe: &'static CoreBPE,
+}
+
+impl TokenCounter {
+    pub fn new(model: &str) -> Self {
+        let bpe = if model.starts_with("gpt-4o") || model.starts_with("o1") {
+            tiktoken_rs::o200k_base_singleton()
+        } else {
+            tiktoken_rs::cl100k_base_singleton()
+        };
+        TokenCounter { bpe }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+}
+
+/// Function to find how many tokens the prompt may use: the context window of the model, minus the response.
+pub fn prompt_budget(params: &ModelParams) -> (usize, usize) {
+    let context_window = params
+        .context_window
+        .unwrap_or_else(|| tiktoken_rs::model::get_context_size(&params.model));
+    (
+        context_window,
+        context_window.saturating_sub(response_tokens(params)),
+    )
+}
+
+/// Function to find how many tokens the response may take: `max_tokens`, or a default if that isn't set.
+pub fn response_tokens(params: &ModelParams) -> usize {
+    params
+        .max_tokens
+        .map(|max_tokens| max_tokens.max(0) as usize)
+        .unwrap_or(DEFAULT_RESPONSE_TOKENS)
+}
+
+/// How relevant a file is for grading. When grading in parts, the most relevant files come first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Relevance {
+    /// A library or minified file, which the student didn't write. Only its name is shown.
+    Library,
+    /// A file listed as unchanged from the starter code.
+    Unchanged,
+    /// A script or stylesheet written or changed by the student.
+    Code,
+    /// An HTML page written or changed by the student.
+    Page,
+}
+
+/// A file of the deliverable, formatted for the grading prompt.
+#[derive(Debug, Clone)]
+pub struct PromptFile {
+    /// Path relative to the deliverable.
+    pub path: PathBuf,
+    pub relevance: Relevance,
+    /// The file as it is shown in the prompt, with its name and a code block.
+    pub text: String,
+    pub tokens: usize,
+}
+
+/// A file that was truncated to make the deliverable fit the prompt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TruncatedFile {
+    pub file: PathBuf,
+    pub tokens: usize,
+    pub kept_tokens: usize,
+}
+
+/// How the deliverable was graded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// The whole deliverable in a single prompt.
+    Single,
+    /// Parts of the deliverable graded separately, and then merged.
+    MapReduce,
+}
+
+/// The files of a deliverable, fitted into one or more prompts.
+pub struct FittedFiles {
+    pub parts: Vec<Vec<PromptFile>>,
+    pub truncated: Vec<TruncatedFile>,
+}
+
+/// Function to fit the files of a deliverable into prompts with room for `available` tokens of files.
+/// If they don't fit, files taking more than their share are truncated.
+/// If that isn't enough, the files are split into parts, with the most relevant files first.
+pub fn fit_files(
+    mut files: Vec<PromptFile>,
+    available: usize,
+    counter: &TokenCounter,
+    phrases: &Phrases,
+) -> FittedFiles {
+    let mut truncated = Vec::new();
+    let total = |files: &[PromptFile]| files.iter().map(|file| file.tokens).sum::<usize>();
+
+    if total(&files) > available {
+        let max_tokens = (available as f64 * MAX_FILE_SHARE) as usize;
+        for file in files.iter_mut().filter(|file| file.tokens > max_tokens) {
+            let text = truncate(&file.text, max_tokens, counter, phrases);
+            let kept_tokens = counter.count(&text);
+            truncated.push(TruncatedFile {
+                file: file.path.clone(),
+                tokens: file.tokens,
+                kept_tokens,
+            });
+            file.text = text;
+            file.tokens = kept_tokens;
+        }
+    }
+
+    if total(&files) <= available {
+        return FittedFiles {
+            parts: vec![files],
+            truncated,
+        };
+    }
+
+    // Pack the most relevant files first, so the first part holds what matters most
+    let mut ranked = files;
+    ranked.sort_by(|a, b| b.relevance.cmp(&a.relevance).then(a.path.cmp(&b.path)));
+    let mut parts: Vec<Vec<PromptFile>> = Vec::new();
+    let mut used = 0;
+    for file in ranked {
+        if parts.is_empty() || used + file.tokens > available {
+            parts.push(Vec::new());
+            used = 0;
+        }
+        used += file.tokens;
+        parts.last_mut().unwrap().push(file);
+    }
+    for part in &mut parts {
+        part.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    FittedFiles { parts, truncated }
+}
+
+/// Function to keep the lines at the start of a formatted file that fit in `max_tokens`, and close its code block.
+fn truncate(text: &str, max_tokens: usize, counter: &TokenCounter, phrases: &Phrases) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut kept = String::new();
+    let mut tokens = 0;
+    let mut kept_lines = 0;
+    for line in &lines {
+        let line_tokens = counter.count(line) + 1;
+        if tokens + line_tokens > max_tokens {
+            break;
+        }
+        kept.push_str(line);
+        kept.push('\n');
+        tokens += line_tokens;
+        kept_lines += 1;
+    }
+
+    let in_code_block = kept.lines().filter(|line| line.starts_with("```")).count() % 2 == 1;
+    if in_code_block {
+        kept.push_str("```\n");
+    }
+    kept.push_str(&format!(
+        "\n{}\n\n",
+        phrases.lines_truncated.replace(
+            "{lines}",
+            &lines[kept_lines..]
+                .iter()
+                .filter(|line| !line.starts_with("```"))
+                .count()
+                .to_string()
+        )
+    ));
+    kept
+}
+
+/// What was sent to the model when grading a deliverable, stored as `grade-metadata.json` in the student directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GradingMetadata {
+    pub model: String,
+    pub context_window: usize,
+    /// Tokens available for the prompt, after leaving room for the response.
+    pub prompt_budget: usize,
+    /// Tokens of the largest prompt sent.
+    pub prompt_tokens: usize,
+    pub strategy: Strategy,
+    /// How many parts the deliverable was graded in.
+    pub parts: usize,
+    /// How many rounds of merging the gradings of the parts took. More than one if they didn't fit in one prompt.
+    #[serde(default)]
+    pub merge_rounds: usize,
+    /// Files that were truncated to fit the prompt.
+    pub truncated: Vec<TruncatedFile>,
+}
+
+impl GradingMetadata {
+    pub fn write(&self, project_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(
+            project_dir.join("grade-metadata.json"),
+            serde_json::to_string_pretty(self)?,
+        )?;
+
+        let student = prompts::student_name(project_dir);
+        if self.strategy == Strategy::MapReduce {
+            println!(
+                "> Graded {} in {} parts, since the deliverable doesn't fit in one prompt",
+                student, self.parts
+            );
+        }
+        for file in &self.truncated {
+            eprintln!(
+                "> Truncated {}/{} ({} of {} tokens kept)",
+                student,
+                file.file.display(),
+                file.kept_tokens,
+                file.tokens
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompts::Language;
+
+    fn file(path: &str, relevance: Relevance, lines: usize, counter: &TokenCounter) -> PromptFile {
+        let code: Vec<String> = (1..=lines)
+            .map(|line| format!("<p>Linje {} av filen {}</p>", line, path))
+            .collect();
+        let text = format!("{}:\n```html\n{}\n```\n", path, code.join("\n"));
+        PromptFile {
+            path: PathBuf::from(path),
+            relevance,
+            tokens: counter.count(&text),
+            text,
+        }
+    }
+
+    #[test]
+    fn files_that_fit_are_kept_as_they_are() {
+        let counter = TokenCounter::new("gpt-4");
+        let files = vec![
+            file("index.html", Relevance::Page, 10, &counter),
+            file("js/app.js", Relevance::Code, 10, &counter),
+        ];
+        let fitted = fit_files(files, 1000, &counter, Language::Norwegian.phrases());
+        assert_eq!(fitted.parts.len(), 1);
+        assert_eq!(fitted.parts[0].len(), 2);
+        assert!(fitted.truncated.is_empty());
+    }
+
+    #[test]
+    fn a_large_file_is_truncated() {
+        let counter = TokenCounter::new("gpt-4");
+        let files = vec![
+            file("index.html", Relevance::Page, 10, &counter),
+            file("stor.html", Relevance::Page, 200, &counter),
+        ];
+        let fitted = fit_files(files, 1000, &counter, Language::Norwegian.phrases());
+
+        assert_eq!(fitted.parts.len(), 1);
+        assert_eq!(fitted.truncated.len(), 1);
+        assert_eq!(fitted.truncated[0].file, PathBuf::from("stor.html"));
+        assert!(fitted.truncated[0].kept_tokens <= 300 + 30);
+        let text = &fitted.parts[0][1].text;
+        assert!(text.contains("linjer til er utelatt"));
+        assert_eq!(text.matches("```").count() % 2, 0);
+    }
+
+    #[test]
+    fn files_that_still_dont_fit_are_split_with_pages_first() {
+        let counter = TokenCounter::new("gpt-4");
+        let mut files = Vec::new();
+        for index in 1..=4 {
+            files.push(file(
+                &format!("js/{}.js", index),
+                Relevance::Code,
+                25,
+                &counter,
+            ));
+            files.push(file(
+                &format!("{}.html", index),
+                Relevance::Page,
+                25,
+                &counter,
+            ));
+        }
+        // Room for four files, so none of them is truncated
+        let largest = files.iter().map(|file| file.tokens).max().unwrap();
+        let fitted = fit_files(files, largest * 4, &counter, Language::Norwegian.phrases());
+
+        assert!(fitted.truncated.is_empty());
+        let parts: Vec<Vec<String>> = fitted
+            .parts
+            .iter()
+            .map(|part| {
+                part.iter()
+                    .map(|file| file.path.display().to_string())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            parts,
+            [
+                ["1.html", "2.html", "3.html", "4.html"],
+                ["js/1.js", "js/2.js", "js/3.js", "js/4.js"]
+            ]
+        );
+    }
+
+    #[test]
+    fn truncate_closes_the_code_block_and_counts_what_was_left_out() {
+        let counter = TokenCounter::new("gpt-4");
+        let text = "index.html:\n```html\n<p>1</p>\n<p>2</p>\n<p>3</p>\n<p>4</p>\n```\n";
+        let max_tokens = counter.count("index.html:\n```html\n<p>1</p>\n") + 3;
+
+        let truncated = truncate(text, max_tokens, &counter, Language::English.phrases());
+        assert_eq!(
+            truncated,
+            "index.html:\n```html\n<p>1</p>\n```\n\n[… 3 more lines are left out, since the file is too large]\n\n"
+        );
+    }
+}
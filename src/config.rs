@@ -0,0 +1,258 @@
+use crate::issue::{IssueSource, Severity};
+use crate::llm::LlmBackendKind;
+use crate::prompts::Language;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The config file that is picked up from the current directory when `--config` isn't given.
+pub const DEFAULT_CONFIG_FILE: &str = "webtek-grader.toml";
+
+/// Configuration loaded from a TOML file. Every section is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub validation: ValidationConfig,
+    pub explanations: ExplanationsConfig,
+    pub similarity: SimilarityConfig,
+    pub llm: LlmConfig,
+    pub prompts: PromptConfig,
+    pub vendor: VendorConfig,
+}
+
+/// The `[validation]` section, controlling which issues are reported.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationConfig {
+    /// Issues below this severity are not reported.
+    pub min_severity: Severity,
+    /// Overrides of `min_severity` per checker, e.g. `css = "error"`.
+    pub severity: HashMap<IssueSource, Severity>,
+    /// Issues matching any of these rules are not reported.
+    pub suppress: Vec<SuppressionRule>,
+    /// Extra rules per assignment, keyed by the name of the destination directory.
+    pub assignments: HashMap<String, AssignmentConfig>,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        ValidationConfig {
+            min_severity: Severity::Warning,
+            severity: HashMap::new(),
+            suppress: Vec::new(),
+            assignments: HashMap::new(),
+        }
+    }
+}
+
+/// A rule suppressing every issue whose message matches a regular expression.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SuppressionRule {
+    /// Name of the rule in the report. Defaults to the pattern.
+    pub name: Option<String>,
+    pub pattern: String,
+    /// Only suppress issues from this checker.
+    pub source: Option<IssueSource>,
+}
+
+/// The `[validation.assignments.<name>]` section.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssignmentConfig {
+    /// Issues that are expected, and therefore allowed, in this assignment.
+    pub allow: Vec<SuppressionRule>,
+    /// The files and folders every deliverable must, or must not, contain.
+    pub structure: Option<StructureConfig>,
+    /// Prompts for this assignment, overriding the `[prompts]` section.
+    pub prompts: Option<PromptConfig>,
+}
+
+/// The `[validation.assignments.<name>.structure]` section. Paths are relative to the project root,
+/// and may be globs. Paths ending with `/` are folders.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StructureConfig {
+    pub required: Vec<String>,
+    pub forbidden: Vec<String>,
+    /// Accept required paths that only differ in case.
+    pub case_insensitive: bool,
+}
+
+/// The `[explanations]` section, controlling the cache of AI explanations for validator messages.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExplanationsConfig {
+    pub cache_file: PathBuf,
+}
+
+impl Default for ExplanationsConfig {
+    fn default() -> Self {
+        ExplanationsConfig {
+            cache_file: PathBuf::from(".webtek-grader/explanations.json"),
+        }
+    }
+}
+
+/// The `[llm]` section, choosing the language model that explains validation messages and grades deliverables.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LlmConfig {
+    pub backend: LlmBackendKind,
+    /// Base URL of the API, e.g. `http://localhost:8000/v1`. Required for `openai-compatible`.
+    pub base_url: Option<String>,
+    /// The environment variable holding the API key.
+    pub api_key_env: String,
+    /// The response of the `mock` backend.
+    pub mock_response: Option<String>,
+    /// How many deliverables are graded at the same time.
+    pub concurrency: usize,
+    /// How many times a request failing with a rate limit, timeout or server error is sent again.
+    pub max_retries: usize,
+    /// The most a run may cost, in USD. No more requests are sent once it is reached.
+    pub budget: Option<f64>,
+    /// The model grading deliverables. Defaults to `gpt-4`.
+    pub grading: ModelConfig,
+    /// The model explaining validation messages. Defaults to `gpt-4o-mini`.
+    pub explanations: ModelConfig,
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        LlmConfig {
+            backend: LlmBackendKind::default(),
+            base_url: None,
+            api_key_env: "OPENAI_API_KEY".to_string(),
+            mock_response: None,
+            concurrency: 4,
+            max_retries: 5,
+            budget: None,
+            grading: ModelConfig::default(),
+            explanations: ModelConfig::default(),
+        }
+    }
+}
+
+/// The `[llm.grading]` and `[llm.explanations]` sections.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelConfig {
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<i64>,
+    /// Tokens the model can handle, prompt and response together. Known OpenAI models default to their size.
+    pub context_window: Option<usize>,
+    /// Price in USD per million prompt tokens. Known OpenAI models have a default price.
+    pub input_price: Option<f64>,
+    /// Price in USD per million completion tokens.
+    pub output_price: Option<f64>,
+}
+
+/// The `[prompts]` and `[validation.assignments.<name>.prompts]` sections. Each template is a file with
+/// variables written as `{{name}}`. Templates that aren't given use the default for the language.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromptConfig {
+    /// `nb` or `en`. Defaults to `nb`.
+    pub language: Option<Language>,
+    pub grading: Option<PathBuf>,
+    pub retry: Option<PathBuf>,
+    pub merge: Option<PathBuf>,
+    pub explanation: Option<PathBuf>,
+    pub feedback: Option<PathBuf>,
+    pub validation_feedback: Option<PathBuf>,
+}
+
+impl PromptConfig {
+    /// Function to combine the config with overrides, e.g. for a single assignment.
+    pub fn merge(&self, overrides: &PromptConfig) -> PromptConfig {
+        PromptConfig {
+            language: overrides.language.or(self.language),
+            grading: overrides.grading.clone().or_else(|| self.grading.clone()),
+            retry: overrides.retry.clone().or_else(|| self.retry.clone()),
+            merge: overrides.merge.clone().or_else(|| self.merge.clone()),
+            explanation: overrides
+                .explanation
+                .clone()
+                .or_else(|| self.explanation.clone()),
+            feedback: overrides.feedback.clone().or_else(|| self.feedback.clone()),
+            validation_feedback: overrides
+                .validation_feedback
+                .clone()
+                .or_else(|| self.validation_feedback.clone()),
+        }
+    }
+}
+
+/// The `[vendor]` section, controlling which files are taken as third-party code, and neither validated nor graded.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VendorConfig {
+    /// Detect libraries and minified files by their name, folder, license banner, line length and the hashes of
+    /// common library builds.
+    pub detect: bool,
+    /// Globs of files to leave out, relative to the student's directory, e.g. `**/lib/**`.
+    pub ignore: Vec<String>,
+    /// Hashes of known library files, as SHA-256, SHA-384 or SHA-512 hex, or as a subresource integrity value
+    /// like `sha384-<base64>`.
+    pub hashes: Vec<String>,
+}
+
+impl Default for VendorConfig {
+    fn default() -> Self {
+        VendorConfig {
+            detect: true,
+            ignore: Vec::new(),
+            hashes: Vec::new(),
+        }
+    }
+}
+
+/// The `[similarity]` section, controlling how deliverables are compared with each other.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimilarityConfig {
+    /// Whether deliverables are compared at all.
+    pub enabled: bool,
+    /// Pairs sharing less than this share of their fingerprints, from 0 to 1, are not reported.
+    pub min_similarity: f64,
+    /// How many consecutive tokens each fingerprint covers.
+    pub token_length: usize,
+    /// How many fingerprints each winnowing window covers. Matches of at least
+    /// `token_length + window - 1` tokens are always found.
+    pub window: usize,
+    /// Fingerprints found in more than this share of the deliverables are common boilerplate, and ignored.
+    pub max_share: f64,
+}
+
+impl Default for SimilarityConfig {
+    fn default() -> Self {
+        SimilarityConfig {
+            enabled: true,
+            min_similarity: 0.3,
+            token_length: 12,
+            window: 8,
+            max_share: 0.5,
+        }
+    }
+}
+
+impl Config {
+    /// Function to load the config from `path`, or from `webtek-grader.toml` in the current directory if it exists.
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn std::error::Error>> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Path::new(DEFAULT_CONFIG_FILE),
+            None => return Ok(Config::default()),
+        };
+
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config file {}: {}", path.display(), e))?;
+        let config = toml::from_str(&contents)
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+
+        println!("> Loaded config from {}", path.display());
+        Ok(config)
+    }
+}
@@ -0,0 +1,251 @@
+use crate::issue::{Issue, IssueSource, Severity};
+use crate::schemas::CssValidationResponse;
+use clap::ValueEnum;
+use lightningcss::declaration::DeclarationBlock;
+use lightningcss::properties::custom::CustomPropertyName;
+use lightningcss::properties::Property;
+use lightningcss::rules::{CssRule, CssRuleList};
+use lightningcss::stylesheet::{ParserOptions, PrinterOptions, StyleSheet};
+use reqwest::Client;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+/// The backend used to validate CSS files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CssBackend {
+    /// The W3C CSS Validator (Jigsaw)
+    W3c,
+    /// The built-in CSS parser, which works offline
+    Builtin,
+}
+
+/// Function to validate a stylesheet using the W3C CSS Validator (Jigsaw).
+/// Returns the issues together with the raw response, so it can be stored next to the file.
+pub async fn validate_css_w3c(
+    file: &Path,
+    content: &str,
+    client: &Client,
+) -> Result<(Vec<Issue>, String), Box<dyn std::error::Error>> {
+    println!("> Posting file to W3C CSS Validator: {}", file.display());
+
+    let response = client
+        .post("https://jigsaw.w3.org/css-validator/validator")
+        .header("User-Agent", "Mozilla/5.0 (compatible; Validator/1.0)")
+        .form(&[
+            ("text", content),
+            ("output", "json"),
+            ("profile", "css3svg"),
+            ("warning", "1"),
+        ])
+        .send()
+        .await?
+        .text()
+        .await?;
+
+    let validation: CssValidationResponse = serde_json::from_str(&response)?;
+
+    let mut issues = Vec::new();
+    for (messages, severity) in [
+        (validation.cssvalidation.errors, Severity::Error),
+        (validation.cssvalidation.warnings, Severity::Warning),
+    ] {
+        for message in messages {
+            let text = match message.context.as_deref().map(str::trim) {
+                Some(context) if !context.is_empty() => {
+                    format!("{} (in “{}”)", message.message.trim(), context)
+                }
+                _ => message.message.trim().to_string(),
+            };
+            issues.push(Issue::new(IssueSource::Css, severity, file, text).at(message.line, None));
+        }
+    }
+
+    Ok((issues, response))
+}
+
+/// Function to validate a stylesheet offline using the built-in CSS parser.
+/// Reports parse errors, unknown properties and invalid property values.
+pub fn validate_css_builtin(file: &Path, content: &str) -> Vec<Issue> {
+    let warnings = Arc::new(RwLock::new(Vec::new()));
+    let options = ParserOptions {
+        filename: file.display().to_string(),
+        error_recovery: true,
+        warnings: Some(warnings.clone()),
+        ..ParserOptions::default()
+    };
+
+    let mut issues = Vec::new();
+
+    match StyleSheet::parse(content, options) {
+        Ok(stylesheet) => check_rules(file, &stylesheet.rules, &mut issues),
+        Err(error) => {
+            let line = error.loc.as_ref().map(|loc| loc.line + 1);
+            let column = error.loc.as_ref().map(|loc| loc.column);
+            issues.push(
+                Issue::new(
+                    IssueSource::Css,
+                    Severity::Error,
+                    file,
+                    format!("Parse error: {}", error.kind),
+                )
+                .at(line, column),
+            );
+        }
+    }
+
+    if let Ok(warnings) = warnings.read() {
+        for warning in warnings.iter() {
+            let line = warning.loc.as_ref().map(|loc| loc.line + 1);
+            let column = warning.loc.as_ref().map(|loc| loc.column);
+            issues.push(
+                Issue::new(
+                    IssueSource::Css,
+                    Severity::Error,
+                    file,
+                    format!("Parse error: {}", warning.kind),
+                )
+                .at(line, column),
+            );
+        }
+    }
+
+    issues.sort_by_key(|issue| (issue.line, issue.column));
+    issues
+}
+
+/// Function to recursively check the declarations of every rule in a rule list.
+fn check_rules<R>(file: &Path, rules: &CssRuleList<'_, R>, issues: &mut Vec<Issue>) {
+    for rule in &rules.0 {
+        match rule {
+            CssRule::Style(style) => {
+                let line = Some(style.loc.line + 1);
+                check_declarations(file, &style.declarations, line, issues);
+                check_rules(file, &style.rules, issues);
+            }
+            CssRule::Media(media) => check_rules(file, &media.rules, issues),
+            CssRule::Supports(supports) => check_rules(file, &supports.rules, issues),
+            CssRule::Container(container) => check_rules(file, &container.rules, issues),
+            CssRule::LayerBlock(layer) => check_rules(file, &layer.rules, issues),
+            CssRule::Scope(scope) => check_rules(file, &scope.rules, issues),
+            CssRule::StartingStyle(starting) => check_rules(file, &starting.rules, issues),
+            CssRule::Nesting(nesting) => {
+                let line = Some(nesting.style.loc.line + 1);
+                check_declarations(file, &nesting.style.declarations, line, issues);
+                check_rules(file, &nesting.style.rules, issues);
+            }
+            CssRule::Keyframes(keyframes) => {
+                let line = Some(keyframes.loc.line + 1);
+                for keyframe in &keyframes.keyframes {
+                    check_declarations(file, &keyframe.declarations, line, issues);
+                }
+            }
+            CssRule::Page(page) => {
+                check_declarations(file, &page.declarations, Some(page.loc.line + 1), issues);
+            }
+            CssRule::Unknown(unknown) => {
+                issues.push(
+                    Issue::new(
+                        IssueSource::Css,
+                        Severity::Warning,
+                        file,
+                        format!("Unknown at-rule “@{}”", unknown.name),
+                    )
+                    .at(Some(unknown.loc.line + 1), Some(unknown.loc.column)),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Function to report unknown properties and values that could not be parsed for their property.
+fn check_declarations(
+    file: &Path,
+    declarations: &DeclarationBlock<'_>,
+    line: Option<u32>,
+    issues: &mut Vec<Issue>,
+) {
+    for property in declarations.iter().map(|(property, _)| property) {
+        match property {
+            Property::Custom(custom) => {
+                if let CustomPropertyName::Unknown(name) = &custom.name {
+                    let name = name.as_ref();
+                    // Vendor-prefixed properties are allowed, but not part of any standard
+                    let (severity, message) = if name.starts_with('-') {
+                        (
+                            Severity::Warning,
+                            format!("“{}” is an unknown vendor extension", name),
+                        )
+                    } else {
+                        (
+                            Severity::Error,
+                            format!("Property “{}” doesn't exist", name),
+                        )
+                    };
+                    issues
+                        .push(Issue::new(IssueSource::Css, severity, file, message).at(line, None));
+                }
+            }
+            Property::Unparsed(unparsed) => {
+                let value = property
+                    .value_to_css_string(PrinterOptions::default())
+                    .unwrap_or_default();
+
+                // Values using var() or env() can only be checked at runtime
+                if value.contains("var(") || value.contains("env(") {
+                    continue;
+                }
+
+                issues.push(
+                    Issue::new(
+                        IssueSource::Css,
+                        Severity::Error,
+                        file,
+                        format!(
+                            "Value error: “{}” is not a valid value for “{}”",
+                            value,
+                            unparsed.property_id.name()
+                        ),
+                    )
+                    .at(line, None),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_properties_and_invalid_values_are_reported_on_their_rule() {
+        let css = "body {\n  colr: red;\n  -moz-skrift: 1;\n}\n\
+                   p {\n  color: 12px;\n  margin: var(--luft);\n  display: flex;\n}\n\
+                   @media (max-width: 600px) {\n  h1 { widht: 50%; }\n}\n";
+        let issues = validate_css_builtin(Path::new("css/style.css"), css);
+        let found: Vec<(Severity, Option<u32>, &str)> = issues
+            .iter()
+            .map(|issue| (issue.severity, issue.line, issue.message.as_str()))
+            .collect();
+
+        assert_eq!(
+            found,
+            [
+                (Severity::Error, Some(1), "Property “colr” doesn't exist"),
+                (
+                    Severity::Warning,
+                    Some(1),
+                    "“-moz-skrift” is an unknown vendor extension"
+                ),
+                (
+                    Severity::Error,
+                    Some(5),
+                    "Value error: “12px” is not a valid value for “color”"
+                ),
+                (Severity::Error, Some(11), "Property “widht” doesn't exist"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,277 @@
+// A minimal stand-in for the browser, so student scripts can be loaded outside of one.
+// Elements remember what is assigned to them, but nothing is rendered, timers never fire,
+// and there is no network access.
+(function () {
+  const output = [];
+  const format = (args) =>
+    args
+      .map((arg) => {
+        if (typeof arg === "string") return arg;
+        try {
+          return JSON.stringify(arg);
+        } catch (e) {
+          return String(arg);
+        }
+      })
+      .join(" ");
+  const log = (...args) => output.push(format(args));
+
+  function createEventTarget(target) {
+    const listeners = {};
+    target.addEventListener = (type, listener) => {
+      (listeners[type] = listeners[type] || []).push(listener);
+    };
+    target.removeEventListener = (type, listener) => {
+      listeners[type] = (listeners[type] || []).filter((l) => l !== listener);
+    };
+    target.dispatchEvent = (event) => {
+      event.target = event.target || target;
+      event.currentTarget = target;
+      event.preventDefault = event.preventDefault || (() => {});
+      event.stopPropagation = event.stopPropagation || (() => {});
+      for (const listener of listeners[event.type] || []) {
+        if (typeof listener === "function") listener.call(target, event);
+        else if (listener && typeof listener.handleEvent === "function") listener.handleEvent(event);
+      }
+      const handler = target["on" + event.type];
+      if (typeof handler === "function") handler.call(target, event);
+      return true;
+    };
+    return target;
+  }
+
+  function createElement(tagName) {
+    const classes = new Set();
+    const attributes = {};
+    const element = createEventTarget({
+      tagName: String(tagName).toUpperCase(),
+      nodeName: String(tagName).toUpperCase(),
+      id: "",
+      textContent: "",
+      innerHTML: "",
+      innerText: "",
+      value: "",
+      checked: false,
+      disabled: false,
+      hidden: false,
+      src: "",
+      href: "",
+      style: {},
+      dataset: {},
+      children: [],
+      childNodes: [],
+      parentNode: null,
+      parentElement: null,
+      classList: {
+        add: (...names) => names.forEach((name) => classes.add(name)),
+        remove: (...names) => names.forEach((name) => classes.delete(name)),
+        toggle: (name, force) => {
+          const on = force === undefined ? !classes.has(name) : force;
+          if (on) classes.add(name);
+          else classes.delete(name);
+          return on;
+        },
+        contains: (name) => classes.has(name),
+        replace: (from, to) => classes.delete(from) && !!classes.add(to),
+      },
+      get className() {
+        return [...classes].join(" ");
+      },
+      set className(value) {
+        classes.clear();
+        String(value)
+          .split(/\s+/)
+          .filter(Boolean)
+          .forEach((name) => classes.add(name));
+      },
+      get firstChild() {
+        return this.children[0] || null;
+      },
+      get lastChild() {
+        return this.children[this.children.length - 1] || null;
+      },
+      appendChild(child) {
+        this.children.push(child);
+        this.childNodes.push(child);
+        if (child && typeof child === "object") {
+          child.parentNode = this;
+          child.parentElement = this;
+        }
+        return child;
+      },
+      append(...children) {
+        children.forEach((child) => this.appendChild(child));
+      },
+      prepend(...children) {
+        this.children.unshift(...children);
+        this.childNodes.unshift(...children);
+      },
+      insertBefore(child) {
+        return this.appendChild(child);
+      },
+      removeChild(child) {
+        this.children = this.children.filter((c) => c !== child);
+        this.childNodes = this.childNodes.filter((c) => c !== child);
+        return child;
+      },
+      replaceChildren(...children) {
+        this.children = [];
+        this.childNodes = [];
+        this.append(...children);
+      },
+      remove() {
+        if (this.parentNode) this.parentNode.removeChild(this);
+      },
+      setAttribute(name, value) {
+        attributes[name] = String(value);
+        if (name === "id") this.id = String(value);
+        if (name === "class") this.className = value;
+      },
+      getAttribute(name) {
+        if (name === "id") return this.id || null;
+        if (name === "class") return this.className || null;
+        return name in attributes ? attributes[name] : null;
+      },
+      hasAttribute(name) {
+        return this.getAttribute(name) !== null;
+      },
+      removeAttribute(name) {
+        delete attributes[name];
+      },
+      click() {
+        this.dispatchEvent({ type: "click" });
+      },
+      focus() {},
+      blur() {},
+      scrollIntoView() {},
+      insertAdjacentHTML() {},
+      insertAdjacentElement(position, element) {
+        return element;
+      },
+      cloneNode() {
+        return createElement(tagName);
+      },
+      closest() {
+        return null;
+      },
+      matches() {
+        return false;
+      },
+      querySelector: (selector) => query(selector),
+      querySelectorAll: () => [],
+      getElementsByTagName: () => [],
+      getElementsByClassName: () => [],
+      getBoundingClientRect: () => ({ top: 0, left: 0, right: 0, bottom: 0, width: 0, height: 0 }),
+    });
+    return element;
+  }
+
+  // The same selector always gives the same element, so tests can inspect what the script wrote to it
+  const elements = {};
+  function query(selector) {
+    const key = String(selector);
+    if (!elements[key]) {
+      elements[key] = createElement("div");
+      const id = /^#([\w-]+)$/.exec(key);
+      if (id) elements[key].id = id[1];
+    }
+    return elements[key];
+  }
+
+  const document = createEventTarget({
+    title: "",
+    readyState: "loading",
+    cookie: "",
+    body: createElement("body"),
+    head: createElement("head"),
+    documentElement: createElement("html"),
+    createElement,
+    createTextNode: (text) => ({ nodeType: 3, textContent: String(text) }),
+    createDocumentFragment: () => createElement("fragment"),
+    getElementById: (id) => query("#" + id),
+    querySelector: query,
+    querySelectorAll: () => [],
+    getElementsByTagName: () => [],
+    getElementsByClassName: () => [],
+    getElementsByName: () => [],
+    write: (...args) => output.push(format(args)),
+    writeln: (...args) => output.push(format(args)),
+  });
+
+  function createStorage() {
+    const items = {};
+    return {
+      getItem: (key) => (key in items ? items[key] : null),
+      setItem: (key, value) => {
+        items[key] = String(value);
+      },
+      removeItem: (key) => {
+        delete items[key];
+      },
+      clear: () => Object.keys(items).forEach((key) => delete items[key]),
+      key: (index) => Object.keys(items)[index] || null,
+      get length() {
+        return Object.keys(items).length;
+      },
+    };
+  }
+
+  let timers = 0;
+  const noNetwork = () => {
+    throw new Error("Network access is not available when running tests");
+  };
+
+  createEventTarget(globalThis);
+  Object.assign(globalThis, {
+    window: globalThis,
+    self: globalThis,
+    document,
+    console: { log, info: log, warn: log, error: log, debug: log, table: log },
+    alert: (message) => output.push("alert: " + message),
+    confirm: (message) => (output.push("confirm: " + message), true),
+    prompt: (message) => (output.push("prompt: " + message), null),
+    localStorage: createStorage(),
+    sessionStorage: createStorage(),
+    navigator: { userAgent: "webtek-grader", language: "no" },
+    location: { href: "about:blank", pathname: "/", search: "", hash: "", reload() {} },
+    history: { pushState() {}, replaceState() {}, back() {} },
+    innerWidth: 1024,
+    innerHeight: 768,
+    setTimeout: () => ++timers,
+    setInterval: () => ++timers,
+    clearTimeout: () => {},
+    clearInterval: () => {},
+    requestAnimationFrame: () => ++timers,
+    cancelAnimationFrame: () => {},
+    matchMedia: (media) => ({ matches: false, media, addEventListener() {}, addListener() {} }),
+    getComputedStyle: (element) => element.style,
+    fetch: () => Promise.reject(new Error("Network access is not available when running tests")),
+    XMLHttpRequest: noNetwork,
+    WebSocket: noNetwork,
+    Event: function (type, init) {
+      return Object.assign({ type }, init);
+    },
+    CustomEvent: function (type, init) {
+      return Object.assign({ type, detail: init && init.detail }, init);
+    },
+  });
+
+  // Hooks used by the test runner
+  globalThis.__grader = {
+    output,
+    loaded() {
+      document.readyState = "complete";
+      document.dispatchEvent({ type: "DOMContentLoaded" });
+      globalThis.dispatchEvent({ type: "load" });
+    },
+    serialize(value) {
+      if (value === undefined) return "undefined";
+      try {
+        const json = JSON.stringify(value);
+        return json === undefined ? String(value) : json;
+      } catch (e) {
+        return String(value);
+      }
+    },
+  };
+})();
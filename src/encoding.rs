@@ -0,0 +1,201 @@
+use crate::issue::{Issue, IssueSource, Severity};
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use regex::bytes::Regex;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// How far into an HTML file browsers look for a `<meta charset>` declaration.
+const META_PRESCAN_BYTES: usize = 1024;
+
+/// A text file decoded to UTF-8, along with how its encoding was determined.
+pub struct DecodedText {
+    pub content: String,
+    /// The encoding the file was actually saved in.
+    pub encoding: &'static Encoding,
+    /// Whether the file starts with a byte order mark.
+    pub bom: bool,
+    /// The encoding declared with `<meta charset>` or `@charset`, and the line it was declared on.
+    pub declared: Option<(&'static Encoding, u32)>,
+}
+
+/// Function to read a text file in whatever encoding it was saved in, and transcode it to UTF-8.
+/// The encoding is determined by the byte order mark, then by whether the file is valid UTF-8,
+/// then by the declared charset, and finally by guessing from the bytes.
+pub fn read_text(path: &Path) -> io::Result<DecodedText> {
+    let bytes = fs::read(path)?;
+    let declared = declared_encoding(path, &bytes);
+
+    let (encoding, bom) = match Encoding::for_bom(&bytes) {
+        Some((encoding, _)) => (encoding, true),
+        None if std::str::from_utf8(&bytes).is_ok() => (UTF_8, false),
+        None => match declared {
+            Some((encoding, _)) if encoding != UTF_8 => (encoding, false),
+            _ => {
+                let mut detector = EncodingDetector::new();
+                detector.feed(&bytes, true);
+                (detector.guess(None, false), false)
+            }
+        },
+    };
+
+    let (content, _) = encoding.decode_with_bom_removal(&bytes);
+
+    Ok(DecodedText {
+        content: content.into_owned(),
+        encoding,
+        bom,
+        declared,
+    })
+}
+
+/// Function to read a text file as UTF-8, transcoding it if it was saved in another encoding.
+pub fn read_to_string(path: &Path) -> io::Result<String> {
+    read_text(path).map(|text| text.content)
+}
+
+/// Function to find the encoding declared in an HTML file's `<meta>` tag or a stylesheet's `@charset` rule.
+fn declared_encoding(path: &Path, bytes: &[u8]) -> Option<(&'static Encoding, u32)> {
+    let pattern = match path.extension().and_then(|s| s.to_str()) {
+        Some("html") => r#"(?i-u)<meta[^>]*?charset\s*=\s*["']?\s*([A-Za-z0-9_:.\-]+)"#,
+        Some("css") => r#"^(?-u:\xEF\xBB\xBF)?@charset\s+"([^"]+)""#,
+        _ => return None,
+    };
+
+    let prefix = &bytes[..bytes.len().min(META_PRESCAN_BYTES)];
+    let label = Regex::new(pattern).unwrap().captures(prefix)?.get(1)?;
+    let encoding = Encoding::for_label(label.as_bytes())?;
+
+    // A document can't declare itself as UTF-16 from within, so browsers treat that as UTF-8
+    let encoding = if encoding == UTF_16LE || encoding == UTF_16BE {
+        UTF_8
+    } else {
+        encoding
+    };
+
+    let line = prefix[..label.start()]
+        .iter()
+        .filter(|&&b| b == b'\n')
+        .count()
+        + 1;
+    Some((encoding, line as u32))
+}
+
+/// Function to report files that aren't saved as UTF-8, or whose declared charset doesn't match the actual encoding.
+pub fn check_encoding(file: &Path, text: &DecodedText) -> Option<Issue> {
+    let actual = text.encoding.name();
+
+    if text.encoding == UTF_8 {
+        let (declared, line) = text.declared.filter(|(declared, _)| *declared != UTF_8)?;
+        // Plain ASCII displays correctly in either encoding, until the first æ, ø or å is added
+        let severity = if text.content.is_ascii() {
+            Severity::Warning
+        } else {
+            Severity::Error
+        };
+        let message = format!(
+            "The file is saved as UTF-8, but declares the encoding “{}”. Characters like æ, ø and å will be displayed incorrectly. Declare the encoding as UTF-8 instead",
+            declared.name()
+        );
+        return Some(
+            Issue::new(IssueSource::Encoding, severity, file, message).at(Some(line), None),
+        );
+    }
+
+    let (severity, message, line) = match text.declared {
+        Some((declared, line)) if declared == text.encoding => (
+            Severity::Warning,
+            format!(
+                "The file is saved as “{}”. Save it as UTF-8, and declare the encoding as UTF-8",
+                actual
+            ),
+            Some(line),
+        ),
+        Some((declared, line)) => (
+            Severity::Error,
+            format!(
+                "The file is saved as “{}”, but declares the encoding “{}”. Characters like æ, ø and å will be displayed incorrectly. Save the file as UTF-8",
+                actual,
+                declared.name()
+            ),
+            Some(line),
+        ),
+        None if text.bom => (
+            Severity::Warning,
+            format!("The file is saved as “{}”. Save it as UTF-8 instead", actual),
+            None,
+        ),
+        None => (
+            Severity::Error,
+            format!(
+                "The file is saved as “{}”, not UTF-8, and doesn't declare its encoding. Characters like æ, ø and å may be displayed incorrectly. Save the file as UTF-8",
+                actual
+            ),
+            None,
+        ),
+    };
+
+    Some(Issue::new(IssueSource::Encoding, severity, file, message).at(line, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::WINDOWS_1252;
+
+    fn decode(name: &str, bytes: &[u8]) -> DecodedText {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        fs::write(&path, bytes).unwrap();
+        read_text(&path).unwrap()
+    }
+
+    #[test]
+    fn files_are_transcoded_from_the_encoding_they_were_saved_in() {
+        let text = decode(
+            "index.html",
+            b"<!DOCTYPE html>\n<meta charset=\"windows-1252\">\n<p>Bl\xE5b\xE6rsyltet\xF8y</p>",
+        );
+        assert_eq!(text.encoding, WINDOWS_1252);
+        assert!(text.content.contains("Blåbærsyltetøy"));
+        assert_eq!(text.declared, Some((WINDOWS_1252, 2)));
+
+        let text = decode("style.css", b"\xFF\xFEb\x00o\x00d\x00y\x00{\x00}\x00");
+        assert_eq!(text.encoding, UTF_16LE);
+        assert!(text.bom);
+        assert_eq!(text.content, "body{}");
+
+        let text = decode("index.html", "<meta charset=\"utf-16\"><p>ø</p>".as_bytes());
+        assert_eq!(text.encoding, UTF_8);
+        assert_eq!(text.declared, Some((UTF_8, 1)));
+    }
+
+    #[test]
+    fn mismatched_and_legacy_encodings_are_reported() {
+        let file = Path::new("index.html");
+        let issue = |bytes: &[u8]| check_encoding(file, &decode("index.html", bytes));
+
+        assert!(issue("<meta charset=\"utf-8\"><p>ø</p>".as_bytes()).is_none());
+
+        let declared_latin1 = issue("<meta charset=\"iso-8859-1\">\n<p>ø</p>".as_bytes()).unwrap();
+        assert_eq!(declared_latin1.severity, Severity::Error);
+        assert_eq!(declared_latin1.line, Some(1));
+
+        let ascii = issue(b"<meta charset=\"iso-8859-1\"><p>Hei</p>").unwrap();
+        assert_eq!(ascii.severity, Severity::Warning);
+
+        let saved_as_declared = issue(b"<meta charset=\"windows-1252\"><p>\xF8</p>").unwrap();
+        assert_eq!(saved_as_declared.severity, Severity::Warning);
+
+        let saved_as_latin1 = issue(b"<meta charset=\"utf-8\">\n<p>Bl\xE5b\xE6r</p>").unwrap();
+        assert_eq!(saved_as_latin1.severity, Severity::Error);
+        assert!(saved_as_latin1
+            .message
+            .contains("declares the encoding “UTF-8”"));
+
+        let undeclared = issue(b"<p>Bl\xE5b\xE6rsyltet\xF8y med fl\xF8te</p>").unwrap();
+        assert_eq!(undeclared.severity, Severity::Error);
+        assert_eq!(undeclared.line, None);
+    }
+}
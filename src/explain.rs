@@ -0,0 +1,172 @@
+use crate::llm::Llm;
+use crate::prompts::Template;
+use crate::usage::{BudgetExceeded, Purpose};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Function to normalize a validator message into a template, by replacing quoted values and numbers with placeholders.
+/// E.g. `Bad value “300px” for attribute “width”` becomes `Bad value “…” for attribute “…”`.
+pub fn template(message: &str) -> String {
+    let quoted = Regex::new(r#"“[^”]*”|"[^"]*""#).unwrap();
+    let number = Regex::new(r"\b\d+(?:\.\d+)?\b").unwrap();
+
+    let message = quoted.replace_all(message.trim(), "“…”");
+    number.replace_all(&message, "N").to_string()
+}
+
+/// Explanations of validator messages, cached by template so each distinct message is only explained once.
+pub struct ExplanationCache {
+    path: PathBuf,
+    entries: BTreeMap<String, String>,
+}
+
+impl ExplanationCache {
+    /// Function to load the cache from disk, starting with an empty cache if the file doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let entries = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(_) => BTreeMap::new(),
+        };
+
+        Ok(ExplanationCache {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.entries)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, template: &str) -> Option<&str> {
+        self.entries.get(template).map(String::as_str)
+    }
+
+    /// Function to ask the model to explain every template that isn't cached yet.
+    /// The cache is saved after each explanation, so progress isn't lost if a request fails.
+    pub async fn explain_missing(
+        &mut self,
+        llm: &Llm,
+        prompt: &Template,
+        templates: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut missing: Vec<&String> = templates
+            .iter()
+            .filter(|template| !self.entries.contains_key(*template))
+            .collect();
+        missing.sort();
+        missing.dedup();
+
+        println!(
+            "> {} distinct validation messages, {} already explained",
+            templates.len(),
+            templates.len() - missing.len()
+        );
+        if !missing.is_empty() {
+            let prompts: Vec<String> = missing
+                .iter()
+                .map(|template| prompt.render(&[("message", template)]))
+                .collect();
+            llm.usage
+                .print_estimate("explanations", &llm.explanations, &prompts);
+        }
+
+        for (index, template) in missing.iter().enumerate() {
+            println!(
+                "> Explaining message ({}/{}): {}",
+                index + 1,
+                missing.len(),
+                template
+            );
+            let explanation = match explain(llm, prompt, template).await {
+                Ok(explanation) => explanation,
+                // Messages that aren't explained are shown as they are
+                Err(e) if e.is::<BudgetExceeded>() => {
+                    eprintln!("> {}", e);
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            self.entries.insert(template.to_string(), explanation);
+            self.save()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Function to ask the model for a general explanation of a single message template.
+async fn explain(
+    llm: &Llm,
+    prompt: &Template,
+    template: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let prompt = prompt.render(&[("message", template)]);
+
+    let completion = llm
+        .complete(&llm.explanations, &prompt, Purpose::Explanation)
+        .await?;
+    let explanation = completion.trim();
+    if explanation.is_empty() {
+        return Ok(template.to_string());
+    }
+    Ok(explanation.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{LlmConfig, PromptConfig};
+    use crate::prompts::Prompts;
+
+    #[test]
+    fn messages_differing_only_in_values_share_a_template() {
+        assert_eq!(
+            template("Bad value “300px” for attribute “width” on element “img”."),
+            "Bad value “…” for attribute “…” on element “…”."
+        );
+        assert_eq!(
+            template("Heading level skipped from \"h1\" to \"h3\" on line 12"),
+            "Heading level skipped from “…” to “…” on line N"
+        );
+        assert_eq!(
+            template("  Property “colr” doesn't exist "),
+            template("Property “widht” doesn't exist")
+        );
+        // Digits inside words are kept
+        assert_eq!(template("Unknown element h7"), "Unknown element h7");
+    }
+
+    #[tokio::test]
+    async fn only_templates_missing_from_the_cache_are_explained() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache/explanations.json");
+        let llm = Llm::from_config(
+            &toml::from_str::<LlmConfig>("backend = \"mock\"\nmock_response = \"Forklaring\"")
+                .unwrap(),
+        )
+        .unwrap();
+        let prompts = Prompts::load(&PromptConfig::default()).unwrap();
+
+        let mut cache = ExplanationCache::load(&path).unwrap();
+        cache
+            .entries
+            .insert("Kjent “…”".to_string(), "Fra før".to_string());
+        let templates = ["Kjent “…”".to_string(), "Ny “…”".to_string()];
+        cache
+            .explain_missing(&llm, &prompts.explanation, &templates)
+            .await
+            .unwrap();
+
+        assert_eq!(llm.usage.report().total.requests, 1);
+        let cache = ExplanationCache::load(&path).unwrap();
+        assert_eq!(cache.get("Kjent “…”"), Some("Fra før"));
+        assert_eq!(cache.get("Ny “…”"), Some("Forklaring"));
+    }
+}
@@ -1,6 +1,6 @@
 use std::fs::{self, File};
 use std::io::{self, BufReader};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tar::Archive as TarArchive;
 use unrar::Archive as RarArchive;
 use zip::read::ZipArchive;
@@ -151,13 +151,52 @@ fn remove_macosx_directories(directory: &Path) -> Result<(), Box<dyn std::error:
     Ok(())
 }
 
+/// A file from a previous run, to put back after extracting again.
+struct PreservedFile {
+    /// Path relative to the destination directory.
+    path: PathBuf,
+    content: Vec<u8>,
+}
+
+/// Function to read the files named `names` at the top of the destination directory and in each student directory,
+/// so they can be put back after extracting again.
+fn read_preserved_files(
+    destination_dir: &Path,
+    names: &[&str],
+) -> Result<Vec<PreservedFile>, Box<dyn std::error::Error>> {
+    let mut dirs = vec![PathBuf::new()];
+    if let Ok(entries) = fs::read_dir(destination_dir.join("deliverables")) {
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(Path::new("deliverables").join(path.file_name().unwrap()));
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    for dir in dirs {
+        for name in names {
+            let path = dir.join(name);
+            if let Ok(content) = fs::read(destination_dir.join(&path)) {
+                files.push(PreservedFile { path, content });
+            }
+        }
+    }
+    Ok(files)
+}
+
 /// Function to extract the main archive file (ZIP, TAR, or RAR) and organize student deliverables.
+/// Files named in `preserve`, e.g. the gradings of a previous run, are kept for students that are still in the archive.
 pub fn extract_files(
     archive_file_path: &Path,
     destination_dir: &Path,
+    preserve: &[&str],
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // If destination dir already exists, remove it
+    // If destination dir already exists, remove it, keeping the files to preserve
+    let mut preserved = Vec::new();
     if destination_dir.exists() {
+        preserved = read_preserved_files(destination_dir, preserve)?;
         fs::remove_dir_all(destination_dir)?;
     }
 
@@ -230,6 +269,110 @@ pub fn extract_files(
         }
     }
 
+    // Put back the preserved files, unless the student is gone or the deliverable has a file by the same name
+    let mut kept = 0;
+    for file in preserved {
+        let path = destination_dir.join(&file.path);
+        if path.parent().is_some_and(Path::is_dir) && !path.exists() {
+            fs::write(&path, file.content)?;
+            kept += 1;
+        }
+    }
+    if kept > 0 {
+        println!("> Kept {} files from grading in a previous run", kept);
+    }
+
     println!("> Finished extracting files and cleaned up intermediary files!");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StructureConfig;
+    use crate::grade::GRADING_FILES;
+    use crate::starter::{FileStatus, StarterCode};
+    use crate::structure::{project_root, StructureManifest};
+    use std::io::{Cursor, Write};
+    use zip::write::{FileOptions, ZipWriter};
+
+    const FILES: &[(&str, &str)] = &[
+        ("index.html", "<!DOCTYPE html>\n<title>Hei</title>\n"),
+        ("css/style.css", "body { margin: 0; }\n"),
+    ];
+
+    fn zip(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// A class archive with one student, who zipped the folder containing the project.
+    fn class_archive(dir: &Path) -> PathBuf {
+        let deliverable = zip(&FILES
+            .iter()
+            .map(|(name, content)| (format!("prosjekt/{}", name), content.as_bytes().to_vec()))
+            .collect::<Vec<_>>());
+        let path = dir.join("class.zip");
+        fs::write(
+            &path,
+            zip(&[("oblig1_ola_attempt.zip".to_string(), deliverable)]),
+        )
+        .unwrap();
+        path
+    }
+
+    /// Function to validate the way `validate_directory` does, writing the reports to the student directory.
+    fn validate(student_dir: &Path, starter: &StarterCode, manifest: &StructureManifest) {
+        assert_eq!(
+            project_root(student_dir).unwrap(),
+            student_dir.join("prosjekt")
+        );
+        let issues = manifest.check(student_dir).unwrap();
+        assert!(issues.is_empty(), "{:?}", issues);
+        let diff = starter.diff(student_dir).unwrap();
+        assert!(diff
+            .files
+            .iter()
+            .all(|file| file.status == FileStatus::Unchanged));
+
+        diff.write(student_dir).unwrap();
+        for name in ["validation.json", "checklist.json", "js-tests.json"] {
+            fs::write(student_dir.join(name), "{}").unwrap();
+        }
+    }
+
+    #[test]
+    fn kept_grading_files_do_not_change_the_project_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = class_archive(dir.path());
+        let starter_dir = dir.path().join("starter");
+        for (name, content) in FILES {
+            let path = starter_dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let starter = StarterCode::load(&starter_dir).unwrap();
+        let manifest = StructureManifest::new(&StructureConfig {
+            required: vec!["index.html".to_string(), "css/".to_string()],
+            forbidden: vec!["*.json".to_string()],
+            case_insensitive: false,
+        })
+        .unwrap();
+
+        let destination = dir.path().join("out");
+        let student_dir = destination.join("deliverables").join("ola");
+        extract_files(&archive, &destination, GRADING_FILES).unwrap();
+        validate(&student_dir, &starter, &manifest);
+        for name in ["feedback.txt", "grade.json", "grade-metadata.json"] {
+            fs::write(student_dir.join(name), "{}").unwrap();
+        }
+
+        extract_files(&archive, &destination, GRADING_FILES).unwrap();
+        assert!(student_dir.join("grade.json").exists());
+        validate(&student_dir, &starter, &manifest);
+    }
+}
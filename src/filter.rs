@@ -0,0 +1,184 @@
+use crate::config::{SuppressionRule, ValidationConfig};
+use crate::issue::{Issue, IssueSource, Severity};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// A suppression rule with its pattern compiled.
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    name: String,
+    pattern: Regex,
+    source: Option<IssueSource>,
+}
+
+impl CompiledRule {
+    fn compile(rule: &SuppressionRule, prefix: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let pattern = Regex::new(&rule.pattern)
+            .map_err(|e| format!("Invalid suppression pattern “{}”: {}", rule.pattern, e))?;
+        Ok(CompiledRule {
+            name: format!("{}{}", prefix, rule.name.as_ref().unwrap_or(&rule.pattern)),
+            pattern,
+            source: rule.source,
+        })
+    }
+
+    fn matches(&self, issue: &Issue) -> bool {
+        self.source.is_none_or(|source| source == issue.source)
+            && self.pattern.is_match(&issue.message)
+    }
+}
+
+/// Decides which validation issues are reported, based on the `[validation]` config section.
+#[derive(Debug, Clone)]
+pub struct IssueFilter {
+    min_severity: Severity,
+    severity: HashMap<IssueSource, Severity>,
+    rules: Vec<CompiledRule>,
+}
+
+/// How many issues were suppressed, by the name of the rule that suppressed them.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SuppressionSummary(pub BTreeMap<String, usize>);
+
+impl SuppressionSummary {
+    pub fn total(&self) -> usize {
+        self.0.values().sum()
+    }
+}
+
+impl IssueFilter {
+    /// Function to build the filter for an assignment, including the assignment's own allowlist.
+    pub fn new(
+        config: &ValidationConfig,
+        assignment: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut rules = config
+            .suppress
+            .iter()
+            .map(|rule| CompiledRule::compile(rule, ""))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(assignment_config) = config.assignments.get(assignment) {
+            for rule in &assignment_config.allow {
+                rules.push(CompiledRule::compile(rule, &format!("{}: ", assignment))?);
+            }
+        }
+
+        Ok(IssueFilter {
+            min_severity: config.min_severity,
+            severity: config.severity.clone(),
+            rules,
+        })
+    }
+
+    /// Function to split issues into the ones to report and a summary of the suppressed ones.
+    pub fn apply(&self, issues: Vec<Issue>) -> (Vec<Issue>, SuppressionSummary) {
+        let mut summary = SuppressionSummary::default();
+        let mut reported = Vec::new();
+
+        for issue in issues {
+            let threshold = self
+                .severity
+                .get(&issue.source)
+                .copied()
+                .unwrap_or(self.min_severity);
+
+            let suppressed_by = if issue.severity < threshold {
+                Some(format!("{} below {}", issue.source, threshold))
+            } else {
+                self.rules
+                    .iter()
+                    .find(|rule| rule.matches(&issue))
+                    .map(|rule| rule.name.clone())
+            };
+
+            match suppressed_by {
+                Some(rule) => *summary.0.entry(rule).or_insert(0) += 1,
+                None => reported.push(issue),
+            }
+        }
+
+        (reported, summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    const CONFIG: &str = r#"
+        min_severity = "warning"
+        severity = { accessibility = "info" }
+
+        [[suppress]]
+        name = "Vendor prefixes"
+        pattern = "unknown vendor extension"
+        source = "css"
+
+        [assignments.oblig1]
+        allow = [{ pattern = "document\\.write" }]
+    "#;
+
+    fn issue(source: IssueSource, severity: Severity, message: &str) -> Issue {
+        Issue::new(
+            source,
+            severity,
+            Path::new("index.html"),
+            message.to_string(),
+        )
+    }
+
+    #[test]
+    fn issues_are_suppressed_by_severity_rules_and_assignment() {
+        let config: ValidationConfig = toml::from_str(CONFIG).unwrap();
+        let issues = vec![
+            issue(
+                IssueSource::Html,
+                Severity::Info,
+                "Consider adding a “lang” attribute",
+            ),
+            issue(
+                IssueSource::Accessibility,
+                Severity::Info,
+                "The page has 2 “h1” headings",
+            ),
+            issue(
+                IssueSource::Css,
+                Severity::Warning,
+                "“-moz-x” is an unknown vendor extension",
+            ),
+            issue(
+                IssueSource::Js,
+                Severity::Warning,
+                "“-moz-x” is an unknown vendor extension",
+            ),
+            issue(IssueSource::Js, Severity::Warning, "Avoid “document.write”"),
+        ];
+
+        let (reported, summary) = IssueFilter::new(&config, "oblig1")
+            .unwrap()
+            .apply(issues.clone());
+        let messages: Vec<&str> = reported
+            .iter()
+            .map(|issue| issue.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "The page has 2 “h1” headings",
+                "“-moz-x” is an unknown vendor extension"
+            ]
+        );
+        assert_eq!(reported[1].source, IssueSource::Js);
+        assert_eq!(summary.total(), 3);
+        assert_eq!(summary.0["html below warning"], 1);
+        assert_eq!(summary.0["Vendor prefixes"], 1);
+        assert_eq!(summary.0["oblig1: document\\.write"], 1);
+
+        // The allowlist only applies to its own assignment
+        let (reported, _) = IssueFilter::new(&config, "oblig2").unwrap().apply(issues);
+        assert_eq!(reported.len(), 3);
+    }
+}
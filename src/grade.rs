@@ -1,19 +1,71 @@
+use crate::assessment::Assessment;
+use crate::budget::{self, GradingMetadata, PromptFile, Relevance, Strategy, TokenCounter};
+use crate::encoding;
+use crate::issue::IssueSource;
+use crate::js_tests::TestReport;
+use crate::llm::Llm;
 use crate::pdf::parse_pdf;
-use openai_api_rs::v1::api::OpenAIClient;
-use openai_api_rs::v1::chat_completion::{
-    self, ChatCompletionMessage, ChatCompletionRequest, MessageRole,
-};
-use openai_api_rs::v1::common::GPT4;
-use std::env;
+use crate::prompts::{self, Phrases, Prompts};
+use crate::requirements::Checklist;
+use crate::rubric::Rubric;
+use crate::starter::{self, FileDiff, FileStatus, StarterDiff};
+use crate::status::{self, GradingState, Status, StudentState, STATE_FILE};
+use crate::usage::Purpose;
+use crate::validate::ValidationReport;
+use futures::stream::{self, StreamExt};
+use globset::{Glob, GlobSetBuilder};
 use std::error::Error;
-use std::fs::{self, OpenOptions};
-use std::io::Write;
-use std::path::Path;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
+/// The maximum score of an assignment graded from a criteria PDF.
+const DEFAULT_MAX_POINTS: f64 = 100.0;
+/// How many times the model is asked for a grading before giving up on a structured response.
+const MAX_ATTEMPTS: usize = 3;
+
+/// The grading criteria of an assignment: a rubric, or a PDF that is passed to the model as text.
+pub enum Criteria {
+    Rubric(Rubric),
+    Pdf(PathBuf),
+}
+
+impl Criteria {
+    /// Function to load the rubric, if the file is one. Anything else is treated as a criteria PDF.
+    pub fn load(path: &Path) -> Result<Criteria, Box<dyn Error>> {
+        if Rubric::is_rubric_file(path) {
+            Ok(Criteria::Rubric(Rubric::load(path)?))
+        } else {
+            Ok(Criteria::Pdf(path.to_path_buf()))
+        }
+    }
+
+    fn rubric(&self) -> Option<&Rubric> {
+        match self {
+            Criteria::Rubric(rubric) => Some(rubric),
+            Criteria::Pdf(_) => None,
+        }
+    }
+}
+
 /// Function to read relevant project files (HTML, CSS, and JS) and format them with filename, extension, and content.
-async fn format_project_files(project_dir: &Path) -> Result<String, Box<dyn Error>> {
-    let mut output = String::new();
+/// If the deliverable has been compared to the starter code, unchanged files are only listed, and modified files are
+/// shown as a diff against the starter code. Returns an introduction to the files, and the files ranked by relevance.
+fn collect_project_files(
+    project_dir: &Path,
+    phrases: &Phrases,
+    counter: &TokenCounter,
+) -> Result<(String, Vec<PromptFile>), Box<dyn Error>> {
+    let mut introduction = String::new();
+    let mut files = Vec::new();
+    let starter_diff = StarterDiff::read(project_dir);
+    let excluded = ValidationReport::read(project_dir)
+        .map(|report| report.excluded)
+        .unwrap_or_default();
+    if starter_diff.is_some() {
+        introduction.push_str(&format!("{}\n\n", phrases.starter_code));
+    }
 
     // Walk through the directory and process only HTML, CSS, and JS files recursively
     for entry in WalkDir::new(project_dir) {
@@ -28,104 +80,790 @@ async fn format_project_files(project_dir: &Path) -> Result<String, Box<dyn Erro
 
         // Only process files with extensions "html", "css", or "js"
         if extension == "html" || extension == "css" || extension == "js" {
-            let filename = path.display().to_string();
-            let contents = match fs::read_to_string(&path) {
-                Ok(contents) => contents,
-                Err(_) => continue, // Skip files that cannot be read
+            let relative_path = path.strip_prefix(project_dir).unwrap_or(path);
+            // Paths relative to the deliverable, so the model can refer to them as evidence
+            let filename = relative_path.display().to_string();
+            let file_diff = starter_diff
+                .as_ref()
+                .and_then(|diff| diff.get(relative_path));
+            // Libraries are only listed, so the model knows they are used
+            if excluded.iter().any(|file| file.file == relative_path) {
+                let text = format!("`{}`: {}\n\n", filename, phrases.library_excluded);
+                files.push(PromptFile {
+                    path: relative_path.to_path_buf(),
+                    relevance: Relevance::Library,
+                    tokens: counter.count(&text),
+                    text,
+                });
+                continue;
+            }
+
+            let mut relevance = if extension == "html" {
+                Relevance::Page
+            } else {
+                Relevance::Code
+            };
+
+            let text = match file_diff {
+                Some(diff) if diff.status == FileStatus::Unchanged => {
+                    relevance = Relevance::Unchanged;
+                    format!("`{}`: {}\n\n", filename, phrases.unchanged)
+                }
+                Some(FileDiff {
+                    status: FileStatus::Modified,
+                    patch: Some(patch),
+                    changed_lines,
+                    ..
+                }) => {
+                    let lines = if changed_lines.is_empty() {
+                        phrases.lines_removed.to_string()
+                    } else {
+                        format!("{} {}", phrases.line, starter::format_ranges(changed_lines))
+                    };
+                    format!(
+                        "`{}`: {} ({})\n\n```diff\n{}\n```\n\n",
+                        filename, phrases.modified, lines, patch
+                    )
+                }
+                _ => {
+                    let contents = match encoding::read_to_string(path) {
+                        Ok(contents) => contents,
+                        Err(e) => {
+                            eprintln!("> Could not read {}: {}", path.display(), e);
+                            continue;
+                        }
+                    };
+                    format!("`{}`\n\n```{}\n{}\n```\n\n", filename, extension, contents)
+                }
             };
 
-            let formatted = format!("`{}`\n\n```{}\n{}\n```\n\n", filename, extension, contents);
-            output.push_str(&formatted);
+            files.push(PromptFile {
+                path: relative_path.to_path_buf(),
+                relevance,
+                tokens: counter.count(&text),
+                text,
+            });
+        }
+    }
+
+    Ok((introduction, files))
+}
+
+/// Function to merge the gradings of the parts of a deliverable into one. If they don't fit in a single merge
+/// prompt, as many as fit are merged at a time, and the merged gradings are merged again.
+async fn merge_gradings(
+    context: &GradingContext<'_>,
+    student: &str,
+    assignment: &str,
+    mut gradings: Vec<String>,
+    metadata: &mut GradingMetadata,
+) -> Result<(Option<Assessment>, String), Box<dyn Error>> {
+    let merge_prompt = |gradings: &[String]| {
+        let numbered: Vec<String> = gradings
+            .iter()
+            .enumerate()
+            .map(|(index, grading)| format!("### {}/{}\n\n{}", index + 1, gradings.len(), grading))
+            .collect();
+        context.prompts.merge.render(&[
+            ("student", student),
+            ("assignment", assignment),
+            ("max_points", &context.max_points.to_string()),
+            ("schema", &context.schema),
+            ("description", &context.description_text),
+            ("criteria", &context.criteria_text),
+            ("gradings", &numbered.join("\n\n")),
+        ])
+    };
+    let fits = |gradings: &[String]| {
+        context.counter.count(&merge_prompt(gradings)) <= context.prompt_budget
+    };
+
+    loop {
+        metadata.merge_rounds += 1;
+        if fits(&gradings) {
+            println!(
+                "> Merging the gradings of {} parts for {}",
+                gradings.len(),
+                student
+            );
+            let prompt = merge_prompt(&gradings);
+            metadata.prompt_tokens = metadata.prompt_tokens.max(context.counter.count(&prompt));
+            return request_assessment(
+                context.llm,
+                context.prompts,
+                student,
+                &prompt,
+                context.max_points,
+                context.rubric,
+            )
+            .await;
+        }
+
+        // Group neighbouring parts, as many as fit in a merge prompt
+        let mut groups: Vec<Vec<String>> = Vec::new();
+        let mut group = Vec::new();
+        for grading in gradings {
+            group.push(grading);
+            if group.len() > 1 && !fits(&group) {
+                let last = group.pop().unwrap_or_default();
+                groups.push(std::mem::replace(&mut group, vec![last]));
+            }
+        }
+        groups.push(group);
+        if groups.iter().all(|group| group.len() == 1) {
+            return Err(format!(
+                "The gradings of the parts of {}'s deliverable don't fit in the context window, not even two at a time",
+                student
+            )
+            .into());
+        }
+
+        println!(
+            "> The gradings of {} parts for {} don't fit in one prompt, merging them {} at a time",
+            groups.iter().map(Vec::len).sum::<usize>(),
+            student,
+            groups.iter().map(Vec::len).max().unwrap_or_default()
+        );
+        gradings = Vec::new();
+        for group in groups {
+            if group.len() == 1 {
+                gradings.extend(group);
+                continue;
+            }
+            let prompt = merge_prompt(&group);
+            metadata.prompt_tokens = metadata.prompt_tokens.max(context.counter.count(&prompt));
+            let (assessment, response) = request_assessment(
+                context.llm,
+                context.prompts,
+                student,
+                &prompt,
+                context.max_points,
+                context.rubric,
+            )
+            .await?;
+            gradings.push(match assessment {
+                Some(assessment) => serde_json::to_string_pretty(&assessment)?,
+                None => response,
+            });
+        }
+    }
+}
+
+/// Function to send a prompt to the model, and ask again until the response follows the schema.
+/// Returns the grading, if the model gave a valid one, and the last response.
+async fn request_assessment(
+    llm: &Llm,
+    prompts: &Prompts,
+    student: &str,
+    prompt: &str,
+    max_points: f64,
+    rubric: Option<&Rubric>,
+) -> Result<(Option<Assessment>, String), Box<dyn Error>> {
+    let mut request = prompt.to_string();
+    let mut response = String::new();
+    for attempt in 1..=MAX_ATTEMPTS {
+        response = llm
+            .complete(&llm.grading, &request, Purpose::Grading { student })
+            .await?;
+        match Assessment::parse(&response, max_points, rubric, prompts.phrases()) {
+            Ok(assessment) => return Ok((Some(assessment), response)),
+            Err(violations) => {
+                eprintln!(
+                    "> Invalid grading response for {} (attempt {} of {}): {}",
+                    student,
+                    attempt,
+                    MAX_ATTEMPTS,
+                    violations.join("; ")
+                );
+                request = prompts.retry.render(&[
+                    ("prompt", prompt),
+                    ("response", &response),
+                    ("violations", &format!("- {}", violations.join("\n- "))),
+                ]);
+            }
         }
     }
+    Ok((None, response))
+}
+
+/// Function to read the findings of a checker from the student's validation report, formatted for the prompt.
+fn format_findings(project_dir: &Path, source: IssueSource, phrases: &Phrases) -> String {
+    let report = ValidationReport::read(project_dir).unwrap_or_default();
+
+    let findings: Vec<String> = report
+        .issues
+        .iter()
+        .filter(|issue| issue.source == source)
+        .map(|issue| issue.to_string())
+        .collect();
+
+    if findings.is_empty() {
+        phrases.no_findings.to_string()
+    } else {
+        findings.join("\n")
+    }
+}
+
+/// Function to read the formal errors from the student's validation report, formatted for the prompt: what the
+/// validators found in the HTML, CSS and JS, and broken references and encoding problems.
+fn format_issues(project_dir: &Path, phrases: &Phrases) -> String {
+    let report = ValidationReport::read(project_dir).unwrap_or_default();
+
+    let issues: Vec<String> = report
+        .issues
+        .iter()
+        .filter(|issue| {
+            !matches!(
+                issue.source,
+                IssueSource::Structure | IssueSource::Accessibility
+            )
+        })
+        .map(|issue| issue.to_string())
+        .collect();
 
-    Ok(output)
+    if issues.is_empty() {
+        phrases.no_issues.to_string()
+    } else {
+        issues.join("\n")
+    }
+}
+
+/// Function to read the student's requirement checklist, formatted for the prompt.
+fn format_requirement_checklist(project_dir: &Path, phrases: &Phrases) -> String {
+    match Checklist::read(project_dir) {
+        Some(checklist) => checklist.to_markdown(phrases),
+        None => phrases.no_requirement_checks.to_string(),
+    }
+}
+
+/// Function to read the student's JS test results, formatted for the prompt.
+fn format_js_test_results(project_dir: &Path, phrases: &Phrases) -> String {
+    match TestReport::read(project_dir) {
+        Some(report) => report.to_markdown(phrases),
+        None => phrases.no_js_tests.to_string(),
+    }
+}
+
+/// Files written by grading. They are kept when the deliverables are extracted again, so unchanged students
+/// don't have to be graded again.
+pub const GRADING_FILES: &[&str] = &[
+    STATE_FILE,
+    "grade.json",
+    "grade-metadata.json",
+    "feedback.txt",
+];
+
+/// What is the same for every deliverable of the assignment.
+struct GradingContext<'a> {
+    llm: &'a Llm,
+    prompts: &'a Prompts,
+    rubric: Option<&'a Rubric>,
+    description_text: String,
+    criteria_text: String,
+    max_points: f64,
+    schema: String,
+    counter: TokenCounter,
+    context_window: usize,
+    prompt_budget: usize,
+    prompt_version: String,
+}
+
+/// Which students to grade.
+pub struct Selection<'a> {
+    /// Only grade the students that failed last time.
+    pub retry_failed: bool,
+    /// Grade the students matching these patterns again, even if nothing changed.
+    pub regrade: &'a [String],
 }
 
 /// Function to process each deliverable, combining the assignment description, grading criteria, and the student's project files.
+/// Up to `llm.concurrency` deliverables are graded at the same time. A student that can't be graded doesn't stop the others,
+/// but is marked as failed in the grading state. Students graded last time are skipped, unless they failed, their inputs,
+/// the model or the prompts changed, or they are selected for regrading.
 pub async fn grade_directory(
     destination_dir: &Path,
     description_pdf: &Path,
-    criteria_pdf: &Path,
+    criteria: &Criteria,
+    llm: &Llm,
+    prompts: &Prompts,
+    selection: &Selection<'_>,
 ) -> Result<(), Box<dyn Error>> {
+    let phrases = prompts.phrases();
+
     // Parse the assignment description PDF
     let description_text = parse_pdf(description_pdf)?;
 
-    // Parse the grading criteria PDF
-    let criteria_text = parse_pdf(criteria_pdf)?;
+    // Format the rubric, or parse the grading criteria PDF if there is none
+    let rubric = criteria.rubric();
+    let (criteria_text, max_points) = match criteria {
+        Criteria::Rubric(rubric) => (rubric.to_markdown(phrases), rubric.max_points()),
+        Criteria::Pdf(path) => (parse_pdf(path)?, DEFAULT_MAX_POINTS),
+    };
 
-    // Create an OpenAI client using the API key from the environment
-    let api_key = env::var("OPENAI_API_KEY")?;
-    let client = OpenAIClient::new(api_key);
+    let (context_window, prompt_budget) = budget::prompt_budget(&llm.grading);
+    let context = GradingContext {
+        llm,
+        prompts,
+        rubric,
+        description_text,
+        criteria_text,
+        max_points,
+        schema: serde_json::to_string_pretty(&Assessment::schema(rubric, prompts.phrases()))?,
+        counter: TokenCounter::new(&llm.grading.model),
+        context_window,
+        prompt_budget,
+        prompt_version: prompts.version(),
+    };
+
+    let mut state = match GradingState::read(destination_dir) {
+        Some(state) => state,
+        None if selection.retry_failed => {
+            return Err(format!(
+                "No grading state in {}, so there are no failed students to grade again",
+                destination_dir.display()
+            )
+            .into())
+        }
+        None => GradingState::default(),
+    };
+
+    let mut regrade = GlobSetBuilder::new();
+    for pattern in selection.regrade {
+        regrade.add(
+            Glob::new(pattern)
+                .map_err(|e| format!("Invalid --regrade pattern “{}”: {}", pattern, e))?,
+        );
+    }
+    let regrade = regrade.build()?;
 
-    // Process each deliverable directory
     let deliverables_dir = destination_dir.join("deliverables");
+    let mut students = Vec::new();
     for entry in fs::read_dir(&deliverables_dir)? {
-        let entry = entry?;
-        let student_dir = entry.path();
-
+        let student_dir = entry?.path();
         if student_dir.is_dir() {
-            println!("Processing deliverable for: {}", student_dir.display());
-
-            // Format the project files for the current student deliverable
-            let formatted_project_files = format_project_files(&student_dir).await?;
-
-            // Construct the AI prompt
-            let prompt = format!(
-                "Du har mottatt en studentinnlevering sammen med prosjektbeskrivelsen og vurderingskriteriene for et prosjekt i webteknologi (HTML, CSS, JS). \
-                Gå gjennom innleveringen og evaluer hvor godt den oppfyller følgende krav fra oppgavebeskrivelsen og vurderingskriteriene. \
-                For hver del, gi en kort og konsis forklaring på hvordan oppgaven tilfredsstiller eller ikke tilfredsstiller kravene. \
-                Hvis en del mangler, forklar hva som mangler og hvordan det bør implementeres. \
-                Forklaringen skal IKKE formuleres som 'Feilmeldingen indikerer at ...', men heller direkte og kort. \
-                Når du omtaler begreper innen HTML, CSS, og JS, som for eksempel 'table' etc., sørg for å bruke de engelske begrepene. \
-                IKKE list opp feilene som en punktliste, men skriv en sammenhengende tekst med nye linjer mellom feil. \
-                Hold eksemplene korte (maks 1-5 linjer). Svarene skal være på norsk.\n\n \
-                DERSOM studentene skal besvare spesifikke spørsmål, ta et øyeblikk og tenk over om deres avgitte svar er korrekte. \
-                Avslutt med å gi et forslag til antall poeng, BASERT PÅ vurderingskriteriene og hver dels oppfyllelse av kravene. \
-                Formuler forslaget slik: 'Poengsum: X av 100'.\n\n \
-                Bruk 'de' og 'dere' i flertall for å referere til studentene, ikke 'studentene'. \
-                Avslutt svaret ditt med følgende setning: '🚨 DETTE ER ET UTKAST TIL TILBAKEMELDING OG MÅ VERIFISERES FØR BRUK. 🚨'. \
-                Oppgavebeskrivelse:\n\n{}\n\n \
-                Vurderingskriterier:\n\n{}\n\n \
-                Studentens innlevering:\n\n{}",
-                description_text, criteria_text, formatted_project_files
+            students.push((prompts::student_name(&student_dir), student_dir));
+        }
+    }
+    students.sort();
+
+    for pattern in selection.regrade {
+        let matches = Glob::new(pattern)?.compile_matcher();
+        if !students
+            .iter()
+            .any(|(student, _)| matches.is_match(student))
+        {
+            eprintln!("> No student matches --regrade {}", pattern);
+        }
+    }
+
+    // Students that are no longer in the archive are forgotten
+    if !selection.retry_failed {
+        state
+            .students
+            .retain(|student, _| students.iter().any(|(name, _)| name == student));
+    }
+    let students: Vec<_> = students
+        .into_iter()
+        .filter(|(student, _)| !selection.retry_failed || state.is_failed(student))
+        .map(|(student, student_dir)| {
+            let previous = state.students.get(&student).cloned();
+            let regrade = regrade.is_match(&student);
+            (student, student_dir, previous, regrade)
+        })
+        .collect();
+
+    // Make every prompt before sending any, so the cost can be estimated
+    let total = students.len();
+    let mut unchanged = 0;
+    let failed_state = |e: &dyn Error| StudentState {
+        status: Status::Failed,
+        error: Some(e.to_string()),
+        inputs_hash: None,
+        model: llm.grading.model.clone(),
+        prompt_version: context.prompt_version.clone(),
+    };
+    let mut gradings = Vec::new();
+    for (student, student_dir, previous, regrade) in students {
+        match prepare_grading(&context, &student_dir, previous.as_ref(), regrade) {
+            Ok(Some(grading)) => gradings.push(grading),
+            Ok(None) => unchanged += 1,
+            Err(e) => {
+                eprintln!("> Could not grade {}: {}", student, e);
+                state.students.insert(student, failed_state(e.as_ref()));
+            }
+        }
+    }
+    state.write(destination_dir)?;
+
+    if !gradings.is_empty() {
+        let prompts: Vec<String> = gradings
+            .iter()
+            .flat_map(|grading| grading.prompts.iter().cloned())
+            .collect();
+        llm.usage.print_estimate("grading", &llm.grading, &prompts);
+    }
+    println!(
+        "> Grading {} deliverables with {} using {}, {} at a time",
+        gradings.len(),
+        llm.grading.model,
+        llm.backend.name(),
+        llm.concurrency
+    );
+
+    // Process each deliverable directory, updating the state and the usage as each one finishes
+    let mut results = stream::iter(gradings)
+        .map(|grading| {
+            let context = &context;
+            async move {
+                let student = grading.student.clone();
+                (student, grade_student(context, grading).await)
+            }
+        })
+        .buffer_unordered(llm.concurrency);
+    while let Some((student, result)) = results.next().await {
+        match result {
+            Ok(student_state) => {
+                state.students.insert(student, student_state);
+            }
+            Err(e) => {
+                eprintln!("> Could not grade {}: {}", student, e);
+                state.students.insert(student, failed_state(e.as_ref()));
+            }
+        }
+        state.write(destination_dir)?;
+        llm.usage.report().write(destination_dir)?;
+    }
+
+    if unchanged > 0 {
+        println!(
+            "> Skipped {} of {} deliverables, which are unchanged since they were graded. Use --regrade to grade them again.",
+            unchanged, total
+        );
+    }
+    let failed = state.failed();
+    if failed > 0 {
+        eprintln!(
+            "> {} deliverables could not be graded. Run again, or with --retry-failed to grade only those.",
+            failed
+        );
+    }
+
+    Ok(())
+}
+
+/// A deliverable to grade, with the prompts to send.
+struct PreparedGrading {
+    student: String,
+    student_dir: PathBuf,
+    assignment: String,
+    /// The state of the student once graded.
+    state: StudentState,
+    previous_feedback: Option<String>,
+    /// The grading prompt, or one for each part if the deliverable is graded in parts.
+    prompts: Vec<String>,
+    metadata: GradingMetadata,
+}
+
+/// Function to make the grading prompts for a deliverable, fitted into the context window.
+/// Returns `None` if it is skipped because nothing changed since it was graded as `previous`.
+fn prepare_grading(
+    context: &GradingContext<'_>,
+    student_dir: &Path,
+    previous: Option<&StudentState>,
+    regrade: bool,
+) -> Result<Option<PreparedGrading>, Box<dyn Error>> {
+    let GradingContext {
+        llm,
+        prompts,
+        max_points,
+        ref counter,
+        prompt_budget,
+        ..
+    } = *context;
+    let phrases = prompts.phrases();
+
+    let (introduction, files) = collect_project_files(student_dir, phrases, counter)?;
+    let student = prompts::student_name(student_dir);
+    let assignment = prompts::assignment_name(student_dir);
+    let structure_findings = format_findings(student_dir, IssueSource::Structure, phrases);
+    let issues = format_issues(student_dir, phrases);
+    let accessibility_findings = format_findings(student_dir, IssueSource::Accessibility, phrases);
+    let requirement_checklist = format_requirement_checklist(student_dir, phrases);
+    let js_test_results = format_js_test_results(student_dir, phrases);
+
+    // Skip the student if the grading from last time was made from the same prompt
+    let files_text: String = files.iter().map(|file| file.text.as_str()).collect();
+    let student_state = StudentState {
+        status: Status::Graded,
+        error: None,
+        inputs_hash: Some(status::hash(&[
+            &assignment,
+            &max_points.to_string(),
+            &context.schema,
+            &context.description_text,
+            &context.criteria_text,
+            &structure_findings,
+            &issues,
+            &accessibility_findings,
+            &requirement_checklist,
+            &js_test_results,
+            &introduction,
+            &files_text,
+        ])),
+        model: llm.grading.model.clone(),
+        prompt_version: context.prompt_version.clone(),
+    };
+    let previous_feedback = fs::read_to_string(student_dir.join("feedback.txt")).ok();
+    match (previous, &previous_feedback) {
+        (Some(previous), Some(feedback)) if !regrade => match previous.changes(&student_state) {
+            None => {
+                println!("> Skipping {}, unchanged since it was graded", student);
+                append_feedback(student_dir, None, feedback)?;
+                return Ok(None);
+            }
+            Some(reason) => println!("> Grading {} again, since {}", student, reason),
+        },
+        _ => {}
+    }
+
+    // Construct the AI prompt
+    let grading_prompt = |files: &str| {
+        prompts.grading.render(&[
+            ("student", &student),
+            ("assignment", &assignment),
+            ("max_points", &max_points.to_string()),
+            ("schema", &context.schema),
+            ("description", &context.description_text),
+            ("criteria", &context.criteria_text),
+            ("structure_findings", &structure_findings),
+            ("issues", &issues),
+            ("accessibility_findings", &accessibility_findings),
+            ("requirements", &requirement_checklist),
+            ("js_tests", &js_test_results),
+            ("files", files),
+        ])
+    };
+
+    // Fit the files into what is left of the context window, leaving room for a note when grading in parts
+    let fixed_tokens = counter.count(&grading_prompt(&format!(
+        "{}{}",
+        phrases.partial_files, introduction
+    )));
+    if fixed_tokens >= prompt_budget {
+        return Err(format!(
+            "The grading prompt is {} tokens without the student's files, but {} only has room for {}",
+            fixed_tokens, llm.grading.model, prompt_budget
+        )
+        .into());
+    }
+    let fitted = budget::fit_files(files, prompt_budget - fixed_tokens, counter, phrases);
+    let join = |files: &[PromptFile]| {
+        files
+            .iter()
+            .map(|file| file.text.as_str())
+            .collect::<String>()
+    };
+
+    let mut metadata = GradingMetadata {
+        model: llm.grading.model.clone(),
+        context_window: context.context_window,
+        prompt_budget,
+        prompt_tokens: 0,
+        strategy: Strategy::Single,
+        parts: fitted.parts.len(),
+        merge_rounds: 0,
+        truncated: fitted.truncated,
+    };
+
+    let prompts = if fitted.parts.len() == 1 {
+        vec![grading_prompt(&format!(
+            "{}{}",
+            introduction,
+            join(&fitted.parts[0])
+        ))]
+    } else {
+        metadata.strategy = Strategy::MapReduce;
+        fitted
+            .parts
+            .iter()
+            .enumerate()
+            .map(|(index, part)| {
+                let note = phrases
+                    .partial_files
+                    .replace("{part}", &(index + 1).to_string())
+                    .replace("{parts}", &fitted.parts.len().to_string());
+                grading_prompt(&format!("{}\n\n{}{}", note, introduction, join(part)))
+            })
+            .collect()
+    };
+    metadata.prompt_tokens = prompts
+        .iter()
+        .map(|prompt| counter.count(prompt))
+        .max()
+        .unwrap_or_default();
+
+    Ok(Some(PreparedGrading {
+        student,
+        student_dir: student_dir.to_path_buf(),
+        assignment,
+        state: student_state,
+        previous_feedback,
+        prompts,
+        metadata,
+    }))
+}
+
+/// Function to grade a single deliverable, and write the grading and feedback to the student directory.
+/// Returns how it was graded.
+async fn grade_student(
+    context: &GradingContext<'_>,
+    grading: PreparedGrading,
+) -> Result<StudentState, Box<dyn Error>> {
+    let GradingContext {
+        llm,
+        prompts,
+        rubric,
+        max_points,
+        ..
+    } = *context;
+    let phrases = prompts.phrases();
+    let PreparedGrading {
+        student,
+        student_dir,
+        assignment,
+        mut state,
+        previous_feedback,
+        prompts: grading_prompts,
+        mut metadata,
+    } = grading;
+    let student_dir = student_dir.as_path();
+
+    println!("Processing deliverable for: {}", student_dir.display());
+
+    let (assessment, response) = if grading_prompts.len() == 1 {
+        request_assessment(
+            llm,
+            prompts,
+            &student,
+            &grading_prompts[0],
+            max_points,
+            rubric,
+        )
+        .await?
+    } else {
+        // Grade each part on its own, and then ask the model to merge the gradings
+        let mut gradings = Vec::new();
+        for (index, prompt) in grading_prompts.iter().enumerate() {
+            println!(
+                "> Grading part {} of {} for {}",
+                index + 1,
+                grading_prompts.len(),
+                student
             );
+            let (assessment, response) =
+                request_assessment(llm, prompts, &student, prompt, max_points, rubric).await?;
+            gradings.push(match assessment {
+                Some(assessment) => serde_json::to_string_pretty(&assessment)?,
+                None => response,
+            });
+        }
+
+        merge_gradings(context, &student, &assignment, gradings, &mut metadata).await?
+    };
+    metadata.write(student_dir)?;
 
-            // Create the chat completion request for GPT
-            let request = ChatCompletionRequest::new(
-                GPT4.to_string(),
-                vec![ChatCompletionMessage {
-                    role: MessageRole::user,
-                    content: chat_completion::Content::Text(prompt),
-                    name: None,
-                    tool_calls: None,
-                    tool_call_id: None,
-                }],
+    let feedback = match &assessment {
+        Some(assessment) => {
+            assessment.write(student_dir)?;
+            assessment.to_feedback(rubric, phrases)
+        }
+        // Keep whatever the model said, so the grading isn't lost, but grade the student again next run
+        None => {
+            eprintln!(
+                "> No valid structured grading for {} after {} attempts, keeping the last response as is",
+                student, MAX_ATTEMPTS
             );
+            Assessment::remove(student_dir)?;
+            state.status = Status::Unstructured;
+            state.error = Some(format!(
+                "No valid structured grading after {} attempts",
+                MAX_ATTEMPTS
+            ));
+            if response.trim().is_empty() {
+                phrases.no_feedback.to_string()
+            } else {
+                response
+            }
+        }
+    };
 
-            // Send the request to GPT and get the feedback
-            let result = client.chat_completion(request).await?;
-            let feedback = result
-                .choices
-                .get(0)
-                .and_then(|choice| choice.message.content.as_deref())
-                .unwrap_or("Ingen tilbakemelding generert.")
-                .to_string();
+    let formatted_feedback = prompts.feedback.render(&[
+        ("student", &student),
+        ("assignment", &assignment),
+        ("feedback", &feedback),
+    ]);
 
-            // Prepend the required string to the feedback
-            let formatted_feedback = format!("Tilbakemelding om prosjektet:\n\n{}", feedback);
+    // Save the feedback to a file
+    fs::write(student_dir.join("feedback.txt"), &formatted_feedback)?;
+    append_feedback(
+        student_dir,
+        previous_feedback.as_deref(),
+        &formatted_feedback,
+    )?;
 
-            // Save the feedback to a file
-            let feedback_file_path = student_dir.join("feedback.txt");
-            fs::write(&feedback_file_path, &formatted_feedback)?;
+    Ok(state)
+}
 
-            // Append the project feedback to final.txt
-            let final_file_path = student_dir.join("final.txt");
-            let mut final_file = OpenOptions::new().append(true).open(&final_file_path)?;
+/// Function to append the project feedback to final.txt, after the validation feedback. Feedback from an earlier
+/// grading is replaced, and feedback that is already there isn't added again.
+fn append_feedback(
+    student_dir: &Path,
+    previous_feedback: Option<&str>,
+    feedback: &str,
+) -> Result<(), Box<dyn Error>> {
+    let final_file_path = student_dir.join("final.txt");
+    // There is no validation feedback if validation didn't run with AI
+    let mut final_text = match fs::read_to_string(&final_file_path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
 
-            writeln!(final_file, "\n\n{}", formatted_feedback)?;
+    match previous_feedback {
+        Some(previous) if final_text.contains(previous) => {
+            final_text = final_text.replacen(previous, feedback, 1);
         }
+        _ if final_text.contains(feedback) => return Ok(()),
+        _ if final_text.is_empty() => final_text = format!("{}\n", feedback),
+        _ => final_text.push_str(&format!("\n\n{}\n", feedback)),
     }
-
+    fs::write(&final_file_path, final_text)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feedback_is_appended_once_and_replaced_when_graded_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let final_file_path = dir.path().join("final.txt");
+
+        // Without validation feedback, final.txt is made from the grading
+        append_feedback(dir.path(), None, "Bra jobbet.").unwrap();
+        append_feedback(dir.path(), None, "Bra jobbet.").unwrap();
+        assert_eq!(
+            fs::read_to_string(&final_file_path).unwrap(),
+            "Bra jobbet.\n"
+        );
+
+        fs::write(&final_file_path, "Valideringen fant 2 feil.").unwrap();
+        append_feedback(dir.path(), None, "Bra jobbet.").unwrap();
+        append_feedback(dir.path(), Some("Bra jobbet."), "Godt jobbet.").unwrap();
+        assert_eq!(
+            fs::read_to_string(&final_file_path).unwrap(),
+            "Valideringen fant 2 feil.\n\nGodt jobbet.\n"
+        );
+    }
+}
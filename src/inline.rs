@@ -0,0 +1,216 @@
+use crate::issue::{line_column, Issue};
+use regex::Regex;
+use std::ops::Range;
+use std::path::Path;
+
+/// The kind of code embedded in an HTML file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InlineKind {
+    /// A `<style>` element
+    Style,
+    /// A `<script>` element without a `src` attribute
+    Script,
+    /// A `style=""` attribute
+    StyleAttribute,
+}
+
+/// A block of CSS or JS embedded in an HTML file.
+#[derive(Debug, Clone)]
+pub struct InlineBlock {
+    pub kind: InlineKind,
+    /// Byte offset of the block's content within the host HTML file.
+    pub offset: usize,
+    pub content: String,
+}
+
+/// Function to extract every `<style>` element, inline `<script>` element and `style` attribute from an HTML file.
+pub fn extract_inline_blocks(html: &str) -> Vec<InlineBlock> {
+    let comment = Regex::new(r"(?s)<!--.*?-->").unwrap();
+    let style = Regex::new(r"(?is)<style\b[^>]*>(.*?)</style\s*>").unwrap();
+    let script = Regex::new(r"(?is)<script\b([^>]*)>(.*?)</script\s*>").unwrap();
+    let tag = Regex::new(r"(?s)<[a-zA-Z][^<>]*>").unwrap();
+    let style_attribute = Regex::new(r#"(?is)\sstyle\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    let src_attribute = Regex::new(r"(?i)\ssrc\s*=").unwrap();
+    let type_attribute = Regex::new(r#"(?i)\stype\s*=\s*["']?([^"'\s>]+)"#).unwrap();
+
+    let comments: Vec<Range<usize>> = comment.find_iter(html).map(|m| m.range()).collect();
+    let in_comment = |offset: usize| comments.iter().any(|range| range.contains(&offset));
+
+    let mut blocks = Vec::new();
+    let mut raw_text: Vec<Range<usize>> = Vec::new();
+
+    for captures in style.captures_iter(html) {
+        let element = captures.get(0).unwrap();
+        if in_comment(element.start()) {
+            continue;
+        }
+        raw_text.push(element.range());
+
+        let content = captures.get(1).unwrap();
+        blocks.push(InlineBlock {
+            kind: InlineKind::Style,
+            offset: content.start(),
+            content: content.as_str().to_string(),
+        });
+    }
+
+    for captures in script.captures_iter(html) {
+        let element = captures.get(0).unwrap();
+        if in_comment(element.start()) {
+            continue;
+        }
+        raw_text.push(element.range());
+
+        let attributes = captures.get(1).unwrap().as_str();
+        let is_javascript = type_attribute
+            .captures(attributes)
+            .map(|t| {
+                let t = t[1].to_ascii_lowercase();
+                t == "module" || t.contains("javascript") || t.contains("ecmascript")
+            })
+            .unwrap_or(true);
+
+        let content = captures.get(2).unwrap();
+        if src_attribute.is_match(attributes)
+            || !is_javascript
+            || content.as_str().trim().is_empty()
+        {
+            continue;
+        }
+
+        blocks.push(InlineBlock {
+            kind: InlineKind::Script,
+            offset: content.start(),
+            content: content.as_str().to_string(),
+        });
+    }
+
+    for element in tag.find_iter(html) {
+        let start = element.start();
+        if in_comment(start)
+            || raw_text
+                .iter()
+                .any(|range| range.contains(&start) && range.start != start)
+        {
+            continue;
+        }
+
+        for captures in style_attribute.captures_iter(element.as_str()) {
+            let value = captures.get(1).or_else(|| captures.get(2)).unwrap();
+            if value.as_str().trim().is_empty() {
+                continue;
+            }
+            blocks.push(InlineBlock {
+                kind: InlineKind::StyleAttribute,
+                offset: start + value.start(),
+                content: value.as_str().to_string(),
+            });
+        }
+    }
+
+    blocks.sort_by_key(|block| block.offset);
+    blocks
+}
+
+/// The rule each style attribute is wrapped in, since an attribute holds declarations only.
+const ATTRIBUTE_RULE_START: &str = "* { ";
+
+/// The `style` attributes of an HTML file, combined into one stylesheet so they are validated with a single request.
+/// Each attribute is wrapped in a rule of its own, starting on a new line.
+pub struct AttributeSheet<'a> {
+    pub css: String,
+    /// Each attribute, and the line its rule starts on in `css`.
+    rules: Vec<(u32, &'a InlineBlock)>,
+}
+
+impl<'a> AttributeSheet<'a> {
+    pub fn new(attributes: &'a [InlineBlock]) -> Self {
+        let mut css = String::new();
+        let mut rules = Vec::new();
+        let mut line = 1;
+        for attribute in attributes {
+            rules.push((line, attribute));
+            css.push_str(ATTRIBUTE_RULE_START);
+            css.push_str(&attribute.content);
+            css.push_str(" }\n");
+            line += attribute.content.matches('\n').count() as u32 + 1;
+        }
+        AttributeSheet { css, rules }
+    }
+
+    /// Function to find the attribute an issue in the combined stylesheet was reported for, with the issue's position
+    /// made relative to the attribute's value, ready for `map_to_host`.
+    pub fn locate(&self, mut issue: Issue) -> Option<(&'a InlineBlock, Issue)> {
+        let line = issue.line.unwrap_or(1);
+        let &(start, attribute) = self.rules.iter().rev().find(|(start, _)| *start <= line)?;
+
+        issue.line = issue.line.map(|line| line - start + 1);
+        if issue.line == Some(1) {
+            let prefix = ATTRIBUTE_RULE_START.len() as u32;
+            issue.column = issue
+                .column
+                .map(|column| column.saturating_sub(prefix).max(1));
+        }
+        Some((attribute, issue))
+    }
+}
+
+/// Function to map an issue reported for an inline block back to its position in the host HTML file.
+pub fn map_to_host(mut issue: Issue, host_file: &Path, html: &str, block: &InlineBlock) -> Issue {
+    let (block_line, block_column) = line_column(html, block.offset);
+
+    issue.file = host_file.to_path_buf();
+    issue.column = match (issue.line, issue.column) {
+        (Some(1), Some(column)) => Some(block_column + column - 1),
+        (_, column) => column,
+    };
+    issue.line = Some(block_line + issue.line.unwrap_or(1) - 1);
+    issue
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css;
+    use crate::issue::{IssueSource, Severity};
+
+    const HTML: &str = "<p style=\"color: red\">Hei</p>\n\
+                        <div>\n  <p style=\"margin: 0; colr: blue\">Hallo</p>\n</div>\n";
+
+    fn map(sheet: &AttributeSheet, issue: Issue) -> Option<Issue> {
+        let (attribute, issue) = sheet.locate(issue)?;
+        Some(map_to_host(issue, Path::new("index.html"), HTML, attribute))
+    }
+
+    #[test]
+    fn style_attributes_are_validated_together() {
+        let attributes = extract_inline_blocks(HTML);
+        let sheet = AttributeSheet::new(&attributes);
+        assert_eq!(sheet.css.lines().count(), 2);
+
+        let issues: Vec<Issue> = css::validate_css_builtin(Path::new("index.html"), &sheet.css)
+            .into_iter()
+            .filter_map(|issue| map(&sheet, issue))
+            .collect();
+        assert_eq!(issues.len(), 1, "{:?}", issues);
+        assert_eq!(issues[0].line, Some(3));
+    }
+
+    #[test]
+    fn columns_leave_out_the_wrapping_rule() {
+        let attributes = extract_inline_blocks(HTML);
+        let sheet = AttributeSheet::new(&attributes);
+        // “colr” is in column 16 of the second rule, “* { margin: 0; colr: blue }”
+        let issue = Issue::new(
+            IssueSource::Css,
+            Severity::Error,
+            Path::new("index.html"),
+            "Property “colr” doesn't exist".to_string(),
+        )
+        .at(Some(2), Some(16));
+
+        let issue = map(&sheet, issue).unwrap();
+        assert_eq!((issue.line, issue.column), (Some(3), Some(24)));
+        assert_eq!(&HTML.lines().nth(2).unwrap()[23..27], "colr");
+    }
+}
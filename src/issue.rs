@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::schemas::Message;
+
+/// Severity of a validation issue, ordered from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The checker that reported an issue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IssueSource {
+    Html,
+    Css,
+    Js,
+    References,
+    Accessibility,
+    Encoding,
+    Structure,
+}
+
+impl fmt::Display for IssueSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IssueSource::Html => write!(f, "html"),
+            IssueSource::Css => write!(f, "css"),
+            IssueSource::Js => write!(f, "js"),
+            IssueSource::References => write!(f, "references"),
+            IssueSource::Accessibility => write!(f, "accessibility"),
+            IssueSource::Encoding => write!(f, "encoding"),
+            IssueSource::Structure => write!(f, "structure"),
+        }
+    }
+}
+
+/// A single validation issue found in a student's deliverable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Issue {
+    pub source: IssueSource,
+    pub severity: Severity,
+    /// Path of the offending file, relative to the student directory.
+    pub file: PathBuf,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub message: String,
+    pub extract: Option<String>,
+}
+
+impl Issue {
+    pub fn new(source: IssueSource, severity: Severity, file: &Path, message: String) -> Self {
+        Issue {
+            source,
+            severity,
+            file: file.to_path_buf(),
+            line: None,
+            column: None,
+            message,
+            extract: None,
+        }
+    }
+
+    pub fn at(mut self, line: Option<u32>, column: Option<u32>) -> Self {
+        self.line = line;
+        self.column = column;
+        self
+    }
+
+    pub fn with_extract(mut self, extract: Option<String>) -> Self {
+        self.extract = extract;
+        self
+    }
+
+    /// Function to map a message from the W3C Nu HTML Checker into an issue.
+    pub fn from_nu_message(file: &Path, message: Message) -> Self {
+        let severity = match (message.message_type.as_str(), message.subtype.as_deref()) {
+            ("error", _) | ("non-document-error", _) => Severity::Error,
+            (_, Some("warning")) => Severity::Warning,
+            _ => Severity::Info,
+        };
+
+        Issue::new(IssueSource::Html, severity, file, message.message)
+            .at(message.firstLine.or(message.lastLine), message.firstColumn)
+            .with_extract(message.extract)
+    }
+}
+
+/// Function to convert a byte offset in `content` into a 1-based line and column.
+pub fn line_column(content: &str, offset: usize) -> (u32, u32) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line as u32, column as u32)
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        write!(f, ": [{}/{}] {}", self.source, self.severity, self.message)
+    }
+}
@@ -0,0 +1,378 @@
+use crate::issue::{line_column, Issue, IssueSource, Severity};
+use oxc_allocator::Allocator;
+use oxc_ast::ast::BinaryOperator;
+use oxc_ast::AstKind;
+use oxc_parser::Parser;
+use oxc_semantic::{Semantic, SemanticBuilder, SymbolFlags};
+use oxc_span::{GetSpan, SourceType, Span};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Globals provided by the browser and the JavaScript runtime that students may use without declaring them.
+const BROWSER_GLOBALS: &[&str] = &[
+    "window",
+    "document",
+    "console",
+    "alert",
+    "prompt",
+    "confirm",
+    "navigator",
+    "location",
+    "history",
+    "screen",
+    "localStorage",
+    "sessionStorage",
+    "performance",
+    "fetch",
+    "setTimeout",
+    "setInterval",
+    "clearTimeout",
+    "clearInterval",
+    "requestAnimationFrame",
+    "cancelAnimationFrame",
+    "getComputedStyle",
+    "matchMedia",
+    "structuredClone",
+    "queueMicrotask",
+    "globalThis",
+    "self",
+    "event",
+    "arguments",
+    "undefined",
+    "NaN",
+    "Infinity",
+    "Math",
+    "JSON",
+    "Date",
+    "Array",
+    "Object",
+    "String",
+    "Number",
+    "Boolean",
+    "Symbol",
+    "BigInt",
+    "RegExp",
+    "Error",
+    "TypeError",
+    "RangeError",
+    "SyntaxError",
+    "Promise",
+    "Map",
+    "Set",
+    "WeakMap",
+    "WeakSet",
+    "Proxy",
+    "Reflect",
+    "Intl",
+    "ArrayBuffer",
+    "Uint8Array",
+    "parseInt",
+    "parseFloat",
+    "isNaN",
+    "isFinite",
+    "encodeURIComponent",
+    "decodeURIComponent",
+    "encodeURI",
+    "decodeURI",
+    "Event",
+    "CustomEvent",
+    "Element",
+    "HTMLElement",
+    "Node",
+    "NodeList",
+    "FormData",
+    "URL",
+    "URLSearchParams",
+    "Image",
+    "Audio",
+    "XMLHttpRequest",
+    "IntersectionObserver",
+    "MutationObserver",
+    "ResizeObserver",
+    "Blob",
+    "File",
+    "FileReader",
+    "module",
+    "require",
+    "exports",
+    "$",
+    "jQuery",
+];
+
+/// Names known across a whole deliverable, so one script may use what another declares.
+#[derive(Debug, Default)]
+pub struct ScriptContext {
+    /// Top-level names declared by any script in the deliverable.
+    pub globals: HashSet<String>,
+    /// The HTML and JS sources of the deliverable, used to find functions called from elsewhere.
+    pub sources: Vec<String>,
+}
+
+impl ScriptContext {
+    /// Function to register a script, making its top-level declarations visible to other scripts.
+    /// The script's text must be registered separately with `add_source`.
+    pub fn add_script(&mut self, content: &str) {
+        let allocator = Allocator::default();
+        let parsed = Parser::new(&allocator, content, SourceType::unambiguous()).parse();
+        let semantic = SemanticBuilder::new().build(&parsed.program).semantic;
+        let scoping = semantic.scoping();
+        let root = scoping.root_scope_id();
+
+        for symbol_id in scoping.symbol_ids() {
+            if scoping.symbol_scope_id(symbol_id) == root {
+                self.globals
+                    .insert(scoping.symbol_name(symbol_id).to_string());
+            }
+        }
+
+        // Implicit globals, e.g. `x = 5` without a declaration
+        for (name, references) in scoping.root_unresolved_references() {
+            if references
+                .iter()
+                .any(|id| scoping.get_reference(*id).is_write())
+            {
+                self.globals.insert(name.to_string());
+            }
+        }
+    }
+
+    /// Function to register the text of a script or HTML file, used to find functions called from elsewhere.
+    pub fn add_source(&mut self, content: &str) {
+        self.sources.push(content.to_string());
+    }
+
+    fn is_referenced_elsewhere(&self, name: &str) -> bool {
+        let occurrences: usize = self
+            .sources
+            .iter()
+            .map(|source| count_word(source, name))
+            .sum();
+        // The declaration itself accounts for one occurrence
+        occurrences > 1
+    }
+}
+
+/// Function to check a script for syntax errors and a curated set of beginner-relevant lints.
+pub fn validate_js(file: &Path, content: &str, context: &ScriptContext) -> Vec<Issue> {
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, content, SourceType::unambiguous()).parse();
+
+    let mut issues = Vec::new();
+    let issue = |severity: Severity, span: Span, message: String| {
+        let (line, column) = line_column(content, span.start as usize);
+        Issue::new(IssueSource::Js, severity, file, message).at(Some(line), Some(column))
+    };
+
+    for error in &parsed.errors {
+        let span = error
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.first())
+            .map(|label| Span::new(label.offset() as u32, (label.offset() + label.len()) as u32))
+            .unwrap_or_default();
+        issues.push(issue(
+            Severity::Error,
+            span,
+            format!("Syntax error: {}", error.message),
+        ));
+    }
+
+    // Lints are meaningless when the parser could not make sense of the file
+    if parsed.panicked {
+        return issues;
+    }
+
+    let semantic = SemanticBuilder::new().build(&parsed.program).semantic;
+    check_undeclared(&semantic, context, &issue, &mut issues);
+    check_unused_functions(&semantic, context, &issue, &mut issues);
+
+    for node in semantic.nodes().iter() {
+        match node.kind() {
+            AstKind::BinaryExpression(expression) => {
+                let (found, suggested) = match expression.operator {
+                    BinaryOperator::Equality => ("==", "==="),
+                    BinaryOperator::Inequality => ("!=", "!=="),
+                    _ => continue,
+                };
+                issues.push(
+                    issue(
+                        Severity::Warning,
+                        expression.span,
+                        format!(
+                            "Use “{}” instead of “{}”, which converts the operands to the same type before comparing",
+                            suggested, found
+                        ),
+                    )
+                    .with_extract(Some(expression.span.source_text(content).to_string())),
+                );
+            }
+            AstKind::CallExpression(call)
+                if call.callee.is_specific_member_access("document", "write")
+                    || call.callee.is_specific_member_access("document", "writeln") =>
+            {
+                issues.push(
+                    issue(
+                        Severity::Warning,
+                        call.span,
+                        "Avoid “document.write”, which overwrites the page if called after loading. Use DOM methods such as “appendChild” or “textContent” instead".to_string(),
+                    )
+                    .with_extract(Some(call.callee.span().source_text(content).to_string())),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    issues.sort_by_key(|issue| (issue.line, issue.column));
+    issues
+}
+
+/// Function to report names that are used without ever being declared.
+fn check_undeclared(
+    semantic: &Semantic<'_>,
+    context: &ScriptContext,
+    issue: &impl Fn(Severity, Span, String) -> Issue,
+    issues: &mut Vec<Issue>,
+) {
+    let scoping = semantic.scoping();
+
+    for (name, references) in scoping.root_unresolved_references() {
+        let name: &str = name;
+        if BROWSER_GLOBALS.contains(&name) {
+            continue;
+        }
+
+        // Report each name once, at the first place it is used
+        let Some(reference) = references
+            .iter()
+            .map(|id| scoping.get_reference(*id))
+            .next()
+        else {
+            continue;
+        };
+        let span = semantic.nodes().get_node(reference.node_id()).kind().span();
+
+        if reference.is_write() {
+            issues.push(issue(
+                Severity::Warning,
+                span,
+                format!(
+                    "“{}” is assigned without being declared. Declare it with “let”, “const” or “var”",
+                    name
+                ),
+            ));
+        } else if !context.globals.contains(name) {
+            issues.push(issue(
+                Severity::Warning,
+                span,
+                format!("“{}” is used but never declared", name),
+            ));
+        }
+    }
+}
+
+/// Function to report function declarations that are never called.
+fn check_unused_functions(
+    semantic: &Semantic<'_>,
+    context: &ScriptContext,
+    issue: &impl Fn(Severity, Span, String) -> Issue,
+    issues: &mut Vec<Issue>,
+) {
+    let scoping = semantic.scoping();
+
+    for symbol_id in scoping.symbol_ids() {
+        if !scoping
+            .symbol_flags(symbol_id)
+            .contains(SymbolFlags::Function)
+            || !scoping.get_resolved_reference_ids(symbol_id).is_empty()
+        {
+            continue;
+        }
+
+        let declaration = semantic.nodes().kind(scoping.symbol_declaration(symbol_id));
+        let AstKind::Function(function) = declaration else {
+            continue;
+        };
+
+        let name = scoping.symbol_name(symbol_id);
+        if function.is_declaration() && !context.is_referenced_elsewhere(name) {
+            issues.push(issue(
+                Severity::Warning,
+                scoping.symbol_span(symbol_id),
+                format!("Function “{}” is declared but never used", name),
+            ));
+        }
+    }
+}
+
+/// Function to count the occurrences of `word` in `text` that are not part of a longer identifier.
+fn count_word(text: &str, word: &str) -> usize {
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+
+    text.match_indices(word)
+        .filter(|(index, _)| {
+            let before = text[..*index].chars().next_back();
+            let after = text[index + word.len()..].chars().next();
+            !before.is_some_and(is_identifier) && !after.is_some_and(is_identifier)
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MENU: &str = "function visMeny() {\n  document.querySelector('nav').hidden = false;\n}\n";
+    const APP: &str = "function klikk() {\n  visMeny();\n  teller = 1;\n  if (teller == '1') {\n    document.write(tellr);\n  }\n}\n\nfunction hjelper() {}\n";
+    const HTML: &str = "<button onclick=\"klikk()\">Meny</button>";
+
+    #[test]
+    fn beginner_mistakes_are_linted_across_the_deliverable() {
+        let mut context = ScriptContext::default();
+        for script in [MENU, APP] {
+            context.add_script(script);
+            context.add_source(script);
+        }
+        context.add_source(HTML);
+
+        let issues = validate_js(Path::new("js/app.js"), APP, &context);
+        let found: Vec<(Option<u32>, &str)> = issues
+            .iter()
+            .map(|issue| (issue.line, issue.message.as_str()))
+            .collect();
+
+        // visMeny is declared in another script, and klikk is called from the HTML
+        assert_eq!(
+            found,
+            [
+                (
+                    Some(3),
+                    "“teller” is assigned without being declared. Declare it with “let”, “const” or “var”"
+                ),
+                (
+                    Some(4),
+                    "Use “===” instead of “==”, which converts the operands to the same type before comparing"
+                ),
+                (
+                    Some(5),
+                    "Avoid “document.write”, which overwrites the page if called after loading. Use DOM methods such as “appendChild” or “textContent” instead"
+                ),
+                (Some(5), "“tellr” is used but never declared"),
+                (Some(9), "Function “hjelper” is declared but never used"),
+            ]
+        );
+    }
+
+    #[test]
+    fn syntax_errors_stop_the_lints() {
+        let issues = validate_js(
+            Path::new("js/app.js"),
+            "function ( {\n  x == 1\n",
+            &ScriptContext::default(),
+        );
+        assert!(!issues.is_empty());
+        assert!(issues
+            .iter()
+            .all(|issue| issue.message.starts_with("Syntax error:")));
+    }
+}
@@ -0,0 +1,568 @@
+use crate::encoding;
+use crate::prompts::Phrases;
+use crate::structure::project_root;
+use rquickjs::context::EvalOptions;
+use rquickjs::function::IntoArgs;
+use rquickjs::{CatchResultExt, Context, Ctx, Function, Object, Runtime, Value};
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+/// Browser globals the student scripts are loaded with. See the file for what is supported.
+const DOM_STUB: &str = include_str!("dom_stub.js");
+
+/// A file of test cases for the functions students are asked to implement, e.g.
+///
+/// ```toml
+/// [[test]]
+/// name = "isPalindrome recognises a palindrome"
+/// points = 1
+/// code = "isPalindrome('racecar')"
+/// expected = true
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestSuite {
+    /// The scripts to load, relative to the project root. Defaults to the scripts in `page`.
+    pub scripts: Option<Vec<PathBuf>>,
+    /// The page whose scripts are loaded, in order, relative to the project root.
+    /// If it doesn't exist, every JS file is loaded.
+    #[serde(default = "default_page")]
+    pub page: PathBuf,
+    /// How long each test, including loading the scripts, may run.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// How much memory each test may use.
+    #[serde(default = "default_memory_limit_mb")]
+    pub memory_limit_mb: usize,
+    #[serde(rename = "test")]
+    pub tests: Vec<JsTest>,
+}
+
+/// A single test case. The code is run after the student's scripts, and its result is compared to `expected`.
+/// Without `expected`, the code must evaluate to `true`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JsTest {
+    pub name: String,
+    pub points: f64,
+    pub code: String,
+    pub expected: Option<toml::Value>,
+}
+
+fn default_page() -> PathBuf {
+    PathBuf::from("index.html")
+}
+
+fn default_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_memory_limit_mb() -> usize {
+    64
+}
+
+/// A script loaded before the tests, either from a file or from an inline `<script>` element.
+struct Script {
+    name: String,
+    source: String,
+}
+
+impl TestSuite {
+    /// Function to load a test file.
+    pub fn load(path: &Path) -> Result<TestSuite, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read test file {}: {}", path.display(), e))?;
+        let suite: TestSuite = toml::from_str(&contents)
+            .map_err(|e| format!("Invalid test file {}: {}", path.display(), e))?;
+
+        println!(
+            "> Loaded {} JS tests from {}",
+            suite.tests.len(),
+            path.display()
+        );
+        Ok(suite)
+    }
+
+    /// Function to run every test against a student's scripts, each in a fresh sandbox. Scripts that can't be
+    /// loaded are reported as errors in every test, so one broken deliverable doesn't stop the others.
+    pub fn run(&self, student_dir: &Path) -> TestReport {
+        let (scripts, load_errors) = match self.collect_scripts(student_dir) {
+            Ok(collected) => collected,
+            Err(e) => (Vec::new(), vec![e.to_string()]),
+        };
+        for error in &load_errors {
+            eprintln!("> Could not load script {}", error);
+        }
+
+        let results = self
+            .tests
+            .iter()
+            .map(|test| {
+                let mut result = self.run_test(test, &scripts);
+                result
+                    .script_errors
+                    .splice(0..0, load_errors.iter().cloned());
+                result
+            })
+            .collect();
+
+        TestReport { results }
+    }
+
+    /// Function to find the student's scripts, in the order a browser would run them. Returns the scripts
+    /// that could be loaded, and an error for each that couldn't.
+    fn collect_scripts(
+        &self,
+        student_dir: &Path,
+    ) -> Result<(Vec<Script>, Vec<String>), Box<dyn std::error::Error>> {
+        let root = project_root(student_dir)?;
+        let load = |path: &Path| -> Result<Script, String> {
+            resolve(&root, Path::new(""), path)
+                .and_then(|file| encoding::read_to_string(&file).map_err(Into::into))
+                .map(|source| Script {
+                    name: path.display().to_string(),
+                    source,
+                })
+                .map_err(|e| format!("{}: {}", path.display(), e))
+        };
+
+        if let Some(paths) = &self.scripts {
+            return Ok(split(paths.iter().map(|path| load(path))));
+        }
+
+        let page = root.join(&self.page);
+        if page.is_file() {
+            let html = encoding::read_to_string(&page)?;
+            let document = Html::parse_document(&html);
+            let page_dir = self.page.parent().unwrap_or(Path::new(""));
+
+            let mut scripts = Vec::new();
+            for (index, element) in document
+                .select(&Selector::parse("script").unwrap())
+                .enumerate()
+            {
+                match element.value().attr("src") {
+                    Some(src) if !src.contains("://") && !src.starts_with("//") => {
+                        let src = src.split(['?', '#']).next().unwrap_or(src);
+                        scripts.push(match src.strip_prefix('/') {
+                            // Relative to the root of the site, which is the project root
+                            Some(src) => load(Path::new(src)),
+                            None => load(&normalize(&page_dir.join(src))),
+                        });
+                    }
+                    Some(_) => {}
+                    None => scripts.push(Ok(Script {
+                        name: format!("{} (inline script {})", self.page.display(), index + 1),
+                        source: element.text().collect(),
+                    })),
+                }
+            }
+            return Ok(split(scripts));
+        }
+
+        let mut paths = Vec::new();
+        for entry in WalkDir::new(&root) {
+            let entry = entry?;
+            let path = entry.path();
+            if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("js") {
+                paths.push(path.strip_prefix(&root).unwrap_or(path).to_path_buf());
+            }
+        }
+        paths.sort();
+        Ok(split(paths.iter().map(|path| load(path))))
+    }
+
+    fn run_test(&self, test: &JsTest, scripts: &[Script]) -> TestResult {
+        let started = Instant::now();
+        let expected = test
+            .expected
+            .as_ref()
+            .and_then(|value| serde_json::to_value(value).ok());
+
+        let mut result = TestResult {
+            name: test.name.clone(),
+            points: 0.0,
+            max_points: test.points,
+            passed: false,
+            expected: expected.as_ref().map(|value| value.to_string()),
+            actual: None,
+            error: None,
+            output: Vec::new(),
+            script_errors: Vec::new(),
+            duration_ms: 0,
+        };
+
+        let evaluation = self.evaluate(&test.code, scripts);
+        match evaluation.actual {
+            Ok(actual) => {
+                result.passed = match &expected {
+                    Some(expected) => serde_json::from_str(&actual)
+                        .is_ok_and(|actual: serde_json::Value| values_equal(&actual, expected)),
+                    None => actual == "true",
+                };
+                result.actual = Some(actual);
+            }
+            Err(error) => result.error = Some(error),
+        }
+        result.output = evaluation.output;
+        result.script_errors = evaluation.script_errors;
+
+        if result.passed {
+            result.points = test.points;
+        }
+        result.duration_ms = started.elapsed().as_millis() as u64;
+        result
+    }
+
+    /// Function to load the scripts into a fresh sandbox and evaluate the test code.
+    fn evaluate(&self, code: &str, scripts: &[Script]) -> Evaluation {
+        let mut evaluation = Evaluation {
+            actual: Err(String::new()),
+            output: Vec::new(),
+            script_errors: Vec::new(),
+        };
+
+        let runtime = match Runtime::new() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                evaluation.actual = Err(e.to_string());
+                return evaluation;
+            }
+        };
+        runtime.set_memory_limit(self.memory_limit_mb * 1024 * 1024);
+        runtime.set_max_stack_size(1024 * 1024);
+
+        // The engine checks the handler regularly, and aborts the script once it returns true
+        let deadline = Instant::now() + Duration::from_millis(self.timeout_ms);
+        runtime.set_interrupt_handler(Some(Box::new(move || Instant::now() > deadline)));
+        let timed_out = |error: String| {
+            if Instant::now() > deadline {
+                format!("Timed out after {} ms", self.timeout_ms)
+            } else {
+                error
+            }
+        };
+
+        let context = match Context::full(&runtime) {
+            Ok(context) => context,
+            Err(e) => {
+                evaluation.actual = Err(e.to_string());
+                return evaluation;
+            }
+        };
+
+        context.with(|ctx| {
+            let grader = eval(&ctx, "dom_stub.js", DOM_STUB).and_then(|_| {
+                ctx.globals()
+                    .get::<_, Object>("__grader")
+                    .map_err(|e| e.to_string())
+            });
+            let grader = match grader {
+                Ok(grader) => grader,
+                Err(e) => {
+                    evaluation.actual = Err(e);
+                    return;
+                }
+            };
+            // Like in a browser, an error in one script doesn't stop the others from running
+            for script in scripts {
+                if let Err(e) = eval(&ctx, &script.name, &script.source) {
+                    let error = format!("{}: {}", script.name, timed_out(e));
+                    evaluation.script_errors.push(error);
+                }
+            }
+            if let Err(e) = call(&ctx, &grader, "loaded", ()) {
+                let error = format!("load event handler: {}", timed_out(e));
+                evaluation.script_errors.push(error);
+            }
+
+            evaluation.actual = eval(&ctx, "test", code)
+                .and_then(|value| call(&ctx, &grader, "serialize", (value,)))
+                .and_then(|actual| actual.get::<String>().map_err(|e| e.to_string()))
+                .map_err(timed_out);
+            evaluation.output = grader.get("output").unwrap_or_default();
+        });
+
+        evaluation
+    }
+}
+
+/// Function to call one of the test runner's hooks in the DOM stub.
+fn call<'js, A: IntoArgs<'js>>(
+    ctx: &Ctx<'js>,
+    grader: &Object<'js>,
+    name: &str,
+    args: A,
+) -> Result<Value<'js>, String> {
+    let function: Function = grader.get(name).map_err(|e| e.to_string())?;
+    function
+        .call(args)
+        .catch(ctx)
+        .map_err(|e| e.to_string().trim().to_string())
+}
+
+/// The result of evaluating a test's code.
+struct Evaluation {
+    /// The value the code evaluated to as JSON, or the error it threw.
+    actual: Result<String, String>,
+    output: Vec<String>,
+    script_errors: Vec<String>,
+}
+
+/// Function to evaluate a script in the global scope, without strict mode, like a browser would.
+fn eval<'js>(ctx: &Ctx<'js>, name: &str, source: &str) -> Result<Value<'js>, String> {
+    let mut options = EvalOptions::default();
+    options.strict = false;
+    options.filename = Some(name.to_string());
+
+    ctx.eval_with_options(source, options)
+        .catch(ctx)
+        .map_err(|e| e.to_string().trim().to_string())
+}
+
+/// Function to compare a test result to the expected value. Numbers are compared with a small tolerance,
+/// so e.g. `0.1 + 0.2` equals `0.3`.
+fn values_equal(actual: &serde_json::Value, expected: &serde_json::Value) -> bool {
+    use serde_json::Value;
+    match (actual, expected) {
+        (Value::Number(a), Value::Number(b)) => match (a.as_f64(), b.as_f64()) {
+            (Some(a), Some(b)) => (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0),
+            _ => a == b,
+        },
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| values_equal(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| values_equal(a, b)))
+        }
+        _ => actual == expected,
+    }
+}
+
+/// Function to split the loaded scripts from the errors of those that couldn't be loaded.
+fn split(loaded: impl IntoIterator<Item = Result<Script, String>>) -> (Vec<Script>, Vec<String>) {
+    let mut scripts = Vec::new();
+    let mut errors = Vec::new();
+    for script in loaded {
+        match script {
+            Ok(script) => scripts.push(script),
+            Err(e) => errors.push(e),
+        }
+    }
+    (scripts, errors)
+}
+
+/// Function to resolve `.` and `..` in a relative path, without touching the filesystem.
+/// A `..` above the start is kept, so `resolve` can reject it.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Function to resolve a script path relative to `dir` in the project, refusing paths outside the project.
+fn resolve(root: &Path, dir: &Path, path: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = normalize(&dir.join(path));
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err("the path is outside the deliverable".into());
+    }
+    let file = root.join(path);
+    // Symbolic links could still point outside
+    if file.exists() && !file.canonicalize()?.starts_with(root.canonicalize()?) {
+        return Err("the path is outside the deliverable".into());
+    }
+    Ok(file)
+}
+
+/// Function to drop the stack trace from an error message.
+fn first_line(error: &str) -> &str {
+    error.lines().next().unwrap_or(error)
+}
+
+/// The outcome of a single test for a student.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TestResult {
+    pub name: String,
+    pub points: f64,
+    pub max_points: f64,
+    pub passed: bool,
+    /// The expected value, as JSON.
+    pub expected: Option<String>,
+    /// The value the test code evaluated to, as JSON.
+    pub actual: Option<String>,
+    /// The error thrown by the test code, or why it couldn't run.
+    pub error: Option<String>,
+    /// Everything logged with `console.log`, `alert` and `document.write`.
+    pub output: Vec<String>,
+    /// Errors thrown while loading the student's scripts.
+    #[serde(default)]
+    pub script_errors: Vec<String>,
+    pub duration_ms: u64,
+}
+
+/// The JS test results for a student, stored as `js-tests.json` and `js-tests.md` in the student directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TestReport {
+    pub results: Vec<TestResult>,
+}
+
+impl TestReport {
+    pub fn points(&self) -> f64 {
+        self.results.iter().map(|result| result.points).sum()
+    }
+
+    pub fn max_points(&self) -> f64 {
+        self.results.iter().map(|result| result.max_points).sum()
+    }
+
+    /// Function to read the JS test results of a student, if the tests have been run.
+    pub fn read(project_dir: &Path) -> Option<TestReport> {
+        let report = fs::read_to_string(project_dir.join("js-tests.json")).ok()?;
+        serde_json::from_str(&report).ok()
+    }
+
+    pub fn write(
+        &self,
+        project_dir: &Path,
+        phrases: &Phrases,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(
+            project_dir.join("js-tests.json"),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        fs::write(project_dir.join("js-tests.md"), self.to_markdown(phrases))?;
+        println!(
+            "> JS tests: {} of {} points",
+            self.points(),
+            self.max_points()
+        );
+        Ok(())
+    }
+
+    /// Function to format the results as Markdown, which is also used in the grading prompt.
+    pub fn to_markdown(&self, phrases: &Phrases) -> String {
+        let mut output = String::new();
+        for result in &self.results {
+            let mark = if result.passed { "x" } else { " " };
+            output.push_str(&format!(
+                "- [{}] {} ({} {} {} {})\n",
+                mark, result.name, result.points, phrases.of, result.max_points, phrases.points
+            ));
+            if !result.passed {
+                if let Some(error) = &result.error {
+                    output.push_str(&format!("  - {}\n", first_line(error)));
+                } else {
+                    let expected_actual = phrases
+                        .expected_actual
+                        .replace("{expected}", result.expected.as_deref().unwrap_or("true"))
+                        .replace(
+                            "{actual}",
+                            result.actual.as_deref().unwrap_or(phrases.nothing),
+                        );
+                    output.push_str(&format!("  - {}\n", expected_actual));
+                }
+                for error in &result.script_errors {
+                    output.push_str(&format!(
+                        "  - {}\n",
+                        phrases.loading_failed.replace("{error}", first_line(error))
+                    ));
+                }
+            }
+        }
+        output.push_str(&format!(
+            "\n{}: {} {} {} {}\n",
+            phrases.total,
+            self.points(),
+            phrases.of,
+            self.max_points(),
+            phrases.points
+        ));
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompts::Language;
+
+    fn suite() -> TestSuite {
+        toml::from_str(
+            r#"
+            [[test]]
+            name = "greet"
+            points = 1
+            code = "greet()"
+            expected = "hei"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn scripts_resolve_against_the_project_root_and_stay_inside_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let student_dir = dir.path().join("ola");
+        let project = student_dir.join("prosjekt");
+        fs::create_dir_all(project.join("js")).unwrap();
+        fs::write(dir.path().join("secret.js"), "var secret = 1;").unwrap();
+        fs::write(
+            project.join("js/app.js"),
+            "function greet() { return 'hei'; }",
+        )
+        .unwrap();
+        fs::write(
+            project.join("index.html"),
+            r#"<script src="/js/app.js"></script>
+            <script src="../../secret.js"></script>
+            <script src="js/missing.js"></script>"#,
+        )
+        .unwrap();
+
+        let report = suite().run(&student_dir);
+        let result = &report.results[0];
+        assert!(result.passed, "{:?}", result);
+        assert_eq!(result.script_errors.len(), 2);
+        assert!(result.script_errors[0].contains("outside the deliverable"));
+        assert!(result.script_errors[1].starts_with("js/missing.js"));
+    }
+
+    #[test]
+    fn failed_tests_are_reported_in_the_language_of_the_prompts() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("index.html"),
+            "<script>function greet() { return 'hallo'; }</script>",
+        )
+        .unwrap();
+
+        let report = suite().run(dir.path());
+        let markdown = report.to_markdown(Language::Norwegian.phrases());
+        assert_eq!(
+            markdown,
+            "- [ ] greet (0 av 1 poeng)\n  - Forventet \"hei\", fikk \"hallo\"\n\nTotalt: 0 av 1 poeng\n"
+        );
+    }
+}
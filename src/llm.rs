@@ -0,0 +1,458 @@
+use crate::budget::TokenCounter;
+use crate::config::{LlmConfig, ModelConfig};
+use crate::usage::{Price, Purpose, UsageLedger};
+use async_trait::async_trait;
+use clap::ValueEnum;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::cell::Cell;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+/// How long a single request may take, before it is given up and sent again.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+/// The wait before the first retry, doubled for each retry after it.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The kind of backend used to talk to a language model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LlmBackendKind {
+    /// The OpenAI API
+    #[default]
+    Openai,
+    /// A server with an OpenAI-compatible API, e.g. a model hosted on-prem
+    OpenaiCompatible,
+    /// A canned response, for testing without a model
+    Mock,
+}
+
+/// The model, and the parameters it is called with.
+#[derive(Debug, Clone)]
+pub struct ModelParams {
+    pub model: String,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<i64>,
+    pub context_window: Option<usize>,
+    /// Known for OpenAI models, or set in the config.
+    pub price: Option<Price>,
+}
+
+impl ModelParams {
+    fn new(config: &ModelConfig, default_model: &str) -> Self {
+        let model = config
+            .model
+            .clone()
+            .unwrap_or_else(|| default_model.to_string());
+        let price = match (config.input_price, config.output_price) {
+            (Some(input), Some(output)) => Some(Price { input, output }),
+            _ => Price::of_model(&model),
+        };
+        ModelParams {
+            model,
+            temperature: config.temperature,
+            max_tokens: config.max_tokens,
+            context_window: config.context_window,
+            price,
+        }
+    }
+}
+
+/// Tokens used by a request.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+/// A response from the model, and the tokens it used, if the API reports them.
+pub struct Completion {
+    pub text: String,
+    pub usage: Option<TokenUsage>,
+}
+
+/// A language model that completes prompts.
+#[async_trait(?Send)]
+pub trait LlmBackend {
+    /// Name of the backend, for progress output.
+    fn name(&self) -> String;
+
+    /// Function to send a single prompt to the model, and return its response.
+    async fn complete(
+        &self,
+        params: &ModelParams,
+        prompt: &str,
+    ) -> Result<Completion, Box<dyn Error>>;
+}
+
+/// An error from the model API, telling whether the request may succeed if it is sent again.
+#[derive(Debug)]
+pub struct LlmError {
+    pub message: String,
+    /// Rate limits, timeouts and server errors, which tend to pass.
+    pub transient: bool,
+    /// How long the server asked us to wait before sending the request again.
+    pub retry_after: Option<Duration>,
+}
+
+impl LlmError {
+    fn from_response(status: StatusCode, body: &str, retry_after: Option<Duration>) -> Self {
+        let message = serde_json::from_str::<serde_json::Value>(body)
+            .ok()
+            .and_then(|body| body["error"]["message"].as_str().map(str::to_string))
+            .unwrap_or_else(|| body.trim().chars().take(500).collect());
+        // OpenAI answers 429 when the account is out of credits too, which waiting won't fix
+        let out_of_quota = body.contains("insufficient_quota");
+        LlmError {
+            message: format!("{} from the model API: {}", status, message),
+            transient: !out_of_quota
+                && (status == StatusCode::REQUEST_TIMEOUT
+                    || status == StatusCode::CONFLICT
+                    || status == StatusCode::TOO_MANY_REQUESTS
+                    || status.is_server_error()),
+            retry_after,
+        }
+    }
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for LlmError {}
+
+/// Function to read how long the server asks us to wait, from `retry-after-ms` (sent by OpenAI) or `Retry-After`,
+/// which is either seconds or a date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if let Some(millis) = header("retry-after-ms").and_then(|value| value.parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(millis.max(0.0) / 1000.0));
+    }
+    let value = header("retry-after")?;
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Some(Duration::from_secs_f64(seconds.max(0.0)));
+    }
+    httpdate::parse_http_date(value)
+        .ok()
+        .map(|date| date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// The OpenAI API, or any server with an OpenAI-compatible API.
+pub struct OpenAiBackend {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: String,
+}
+
+impl OpenAiBackend {
+    pub fn new(base_url: Option<&str>, api_key: String) -> Result<Self, Box<dyn Error>> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        Ok(OpenAiBackend {
+            client,
+            base_url: base_url
+                .unwrap_or(OPENAI_BASE_URL)
+                .trim_end_matches('/')
+                .to_string(),
+            api_key,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionResponse {
+    choices: Vec<ChatCompletionChoice>,
+    /// Left out by some OpenAI-compatible servers.
+    usage: Option<TokenUsage>,
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionChoice {
+    message: ChatCompletionMessage,
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionMessage {
+    content: Option<String>,
+}
+
+#[async_trait(?Send)]
+impl LlmBackend for OpenAiBackend {
+    fn name(&self) -> String {
+        self.base_url.clone()
+    }
+
+    async fn complete(
+        &self,
+        params: &ModelParams,
+        prompt: &str,
+    ) -> Result<Completion, Box<dyn Error>> {
+        let mut body = json!({
+            "model": params.model,
+            "messages": [{ "role": "user", "content": prompt }],
+        });
+        if let Some(temperature) = params.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(max_tokens) = params.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
+        }
+
+        let url = format!("{}/chat/completions", self.base_url);
+        let mut request = self.client.post(&url).json(&body);
+        // Servers running on-prem often don't require a key
+        if !self.api_key.is_empty() {
+            request = request.bearer_auth(&self.api_key);
+        }
+
+        let response = request.send().await.map_err(|e| LlmError {
+            message: format!("Request to {} failed: {}", url, e),
+            transient: e.is_timeout() || e.is_connect() || e.is_request(),
+            retry_after: None,
+        })?;
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = retry_after(response.headers());
+            let body = response.text().await.unwrap_or_default();
+            return Err(LlmError::from_response(status, &body, retry_after).into());
+        }
+
+        let result: ChatCompletionResponse = response.json().await?;
+        Ok(Completion {
+            text: result
+                .choices
+                .into_iter()
+                .next()
+                .and_then(|choice| choice.message.content)
+                .unwrap_or_default(),
+            usage: result.usage,
+        })
+    }
+}
+
+/// A backend that answers every prompt with the same response, without calling a model.
+pub struct MockBackend {
+    response: String,
+    /// Requests left to fail with a rate limit, to test retries.
+    failures: Cell<usize>,
+}
+
+impl MockBackend {
+    pub fn new(response: Option<&str>) -> Self {
+        MockBackend {
+            response: response
+                .unwrap_or("Dette er et testsvar uten språkmodell.")
+                .to_string(),
+            failures: Cell::new(0),
+        }
+    }
+
+    /// Function to create a backend whose first requests fail with a rate limit.
+    #[cfg(test)]
+    pub fn failing(response: &str, failures: usize) -> Self {
+        MockBackend {
+            response: response.to_string(),
+            failures: Cell::new(failures),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl LlmBackend for MockBackend {
+    fn name(&self) -> String {
+        "mock".to_string()
+    }
+
+    async fn complete(
+        &self,
+        _params: &ModelParams,
+        _prompt: &str,
+    ) -> Result<Completion, Box<dyn Error>> {
+        if self.failures.get() > 0 {
+            self.failures.set(self.failures.get() - 1);
+            return Err(LlmError {
+                message: "429 Too Many Requests from the mock backend".to_string(),
+                transient: true,
+                retry_after: Some(Duration::from_secs(1)),
+            }
+            .into());
+        }
+        Ok(Completion {
+            text: self.response.clone(),
+            usage: None,
+        })
+    }
+}
+
+/// The backend, and the models used for each task.
+pub struct Llm {
+    pub backend: Box<dyn LlmBackend>,
+    /// The model grading deliverables.
+    pub grading: ModelParams,
+    /// The model explaining validation messages.
+    pub explanations: ModelParams,
+    /// How many deliverables are graded at the same time.
+    pub concurrency: usize,
+    /// How many times a request failing with a transient error is sent again.
+    pub max_retries: usize,
+    /// The tokens used so far, and the budget.
+    pub usage: UsageLedger,
+}
+
+impl Llm {
+    /// Function to create the backend described by the config. The API key is read from the environment.
+    pub fn from_config(config: &LlmConfig) -> Result<Self, Box<dyn Error>> {
+        let api_key = env::var(&config.api_key_env).ok();
+
+        let backend: Box<dyn LlmBackend> = match config.backend {
+            LlmBackendKind::Openai => {
+                let api_key = api_key.ok_or_else(|| {
+                    format!("{} environment variable is not set.", config.api_key_env)
+                })?;
+                Box::new(OpenAiBackend::new(config.base_url.as_deref(), api_key)?)
+            }
+            LlmBackendKind::OpenaiCompatible => {
+                let base_url = config
+                    .base_url
+                    .as_deref()
+                    .ok_or("The openai-compatible backend requires a base URL")?;
+                // Servers running on-prem often don't require a key
+                Box::new(OpenAiBackend::new(
+                    Some(base_url),
+                    api_key.unwrap_or_default(),
+                )?)
+            }
+            LlmBackendKind::Mock => Box::new(MockBackend::new(config.mock_response.as_deref())),
+        };
+
+        Ok(Llm {
+            backend,
+            grading: ModelParams::new(&config.grading, "gpt-4"),
+            explanations: ModelParams::new(&config.explanations, "gpt-4o-mini"),
+            concurrency: config.concurrency.max(1),
+            max_retries: config.max_retries,
+            usage: UsageLedger::new(config.budget),
+        })
+    }
+
+    /// Function to send a prompt to the model, and record the tokens it used for `purpose`. Rate limits, timeouts and
+    /// server errors are retried with exponential backoff, waiting as long as the server asks for if it says so.
+    /// Other errors are returned right away, and so is a prompt that could take the run over budget.
+    pub async fn complete(
+        &self,
+        params: &ModelParams,
+        prompt: &str,
+        purpose: Purpose<'_>,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut retries = 0;
+        loop {
+            let reservation = self.usage.check(params, prompt)?;
+            let error = match self.backend.complete(params, prompt).await {
+                Ok(completion) => {
+                    // Counted the way the model does, for servers that don't report usage
+                    let usage = completion.usage.unwrap_or_else(|| {
+                        let counter = TokenCounter::new(&params.model);
+                        TokenUsage {
+                            prompt_tokens: counter.count(prompt),
+                            completion_tokens: counter.count(&completion.text),
+                        }
+                    });
+                    self.usage.record(params, purpose, &usage, reservation);
+                    return Ok(completion.text);
+                }
+                Err(error) => {
+                    self.usage.release(reservation);
+                    error
+                }
+            };
+            let retry_after = match error.downcast_ref::<LlmError>() {
+                Some(error) if error.transient && retries < self.max_retries => error.retry_after,
+                _ => return Err(error),
+            };
+
+            let backoff = INITIAL_BACKOFF
+                .saturating_mul(1 << retries.min(16))
+                .min(MAX_BACKOFF);
+            let wait = retry_after.unwrap_or(backoff) + jitter();
+            retries += 1;
+            eprintln!(
+                "> {}, retrying in {:.1} s (retry {} of {})",
+                error,
+                wait.as_secs_f64(),
+                retries,
+                self.max_retries
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Function to pick a wait of up to a second, so requests that failed together aren't retried together.
+fn jitter() -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    Duration::from_millis((nanos % 1000) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mock(failures: usize, max_retries: usize) -> Llm {
+        let config = LlmConfig::default();
+        Llm {
+            backend: Box::new(MockBackend::failing("Hei", failures)),
+            grading: ModelParams::new(&config.grading, "gpt-4"),
+            explanations: ModelParams::new(&config.explanations, "gpt-4o-mini"),
+            concurrency: 1,
+            max_retries,
+            usage: UsageLedger::new(None),
+        }
+    }
+
+    // The clock is paused, so the waits between retries pass at once
+    #[tokio::test(start_paused = true)]
+    async fn transient_errors_are_retried() {
+        let llm = mock(2, 3);
+        let started = tokio::time::Instant::now();
+        let response = llm
+            .complete(
+                &llm.grading,
+                "Vurder dette",
+                Purpose::Grading { student: "ola" },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response, "Hei");
+        // Waited at least the second the server asked for, twice
+        assert!(started.elapsed() >= Duration::from_secs(2));
+        let report = llm.usage.report();
+        assert_eq!(report.total.requests, 1);
+        assert_eq!(report.students["ola"].requests, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_error_is_returned_once_the_retries_are_used_up() {
+        let llm = mock(3, 2);
+        let error = llm
+            .complete(&llm.explanations, "Forklar", Purpose::Explanation)
+            .await
+            .unwrap_err();
+
+        assert!(error.to_string().starts_with("429 Too Many Requests"));
+        assert_eq!(llm.usage.report().total.requests, 0);
+    }
+}
@@ -1,22 +1,174 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use dotenv::dotenv;
-use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+mod accessibility;
+mod assessment;
+mod budget;
+mod config;
+mod css;
+mod encoding;
+mod explain;
 mod extract;
+mod filter;
 mod grade;
+mod inline;
+mod issue;
+mod js;
+mod js_tests;
+mod llm;
 mod pdf;
+mod prompts;
+mod references;
+mod requirements;
+mod rubric;
+mod rubric_extract;
 mod schemas;
+mod similarity;
+mod starter;
+mod stats;
+mod status;
+mod structure;
+mod stylesheets;
+mod usage;
 mod validate;
+mod vendor;
 
 /// CLI structure using `clap`
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Cli {
+    /// Path to the config file [default: webtek-grader.toml, if it exists]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
     #[command(subcommand)]
     command: Commands,
 }
 
+/// Options shared by every command that validates deliverables
+#[derive(Args, Debug)]
+struct ValidationArgs {
+    /// The backend used to validate CSS files
+    #[arg(long, value_enum, default_value = "w3c")]
+    css_validator: css::CssBackend,
+    /// TOML file with requirement rules, checked into a scored checklist per student
+    #[arg(long)]
+    requirements: Option<PathBuf>,
+    /// TOML file with test cases for the students' JavaScript, run in a sandboxed JS engine
+    #[arg(long)]
+    js_tests: Option<PathBuf>,
+    /// Directory with the starter code handed out with the assignment. Unchanged files are treated as template code
+    #[arg(long)]
+    starter: Option<PathBuf>,
+}
+
+impl ValidationArgs {
+    fn to_options(
+        &self,
+        config: &config::Config,
+        destination_dir: &Path,
+    ) -> Result<validate::ValidationOptions, Box<dyn std::error::Error>> {
+        // Assignments are identified by the name of their destination directory
+        let assignment = destination_dir
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("");
+        let assignment_config = config.validation.assignments.get(assignment);
+
+        let prompts = prompts::Prompts::load(&match assignment_config
+            .and_then(|assignment| assignment.prompts.as_ref())
+        {
+            Some(overrides) => config.prompts.merge(overrides),
+            None => config.prompts.clone(),
+        })?;
+
+        Ok(validate::ValidationOptions {
+            css_backend: self.css_validator,
+            filter: filter::IssueFilter::new(&config.validation, assignment)?,
+            structure: match assignment_config.and_then(|assignment| assignment.structure.as_ref())
+            {
+                Some(structure) => Some(structure::StructureManifest::new(structure)?),
+                None => None,
+            },
+            explanation_cache: prompts.explanation_cache(&config.explanations.cache_file),
+            similarity: config.similarity.clone(),
+            vendor: vendor::VendorDetector::new(&config.vendor)?,
+            requirements: match &self.requirements {
+                Some(path) => Some(requirements::RuleSet::load(path)?),
+                None => None,
+            },
+            js_tests: match &self.js_tests {
+                Some(path) => Some(js_tests::TestSuite::load(path)?),
+                None => None,
+            },
+            starter: match &self.starter {
+                Some(path) => Some(starter::StarterCode::load(path)?),
+                None => None,
+            },
+            prompts,
+        })
+    }
+}
+
+/// Options choosing the language model, overriding the `[llm]` section of the config
+#[derive(Args, Debug)]
+struct LlmArgs {
+    /// The backend used to talk to the language model
+    #[arg(long, value_enum)]
+    llm_backend: Option<llm::LlmBackendKind>,
+    /// Base URL of an OpenAI-compatible API, e.g. http://localhost:8000/v1
+    #[arg(long)]
+    base_url: Option<String>,
+    /// The model grading deliverables [default: gpt-4]
+    #[arg(long)]
+    model: Option<String>,
+    /// The model explaining validation messages [default: gpt-4o-mini]
+    #[arg(long)]
+    explanation_model: Option<String>,
+    /// Sampling temperature for both models
+    #[arg(long)]
+    temperature: Option<f64>,
+    /// How many deliverables are graded at the same time [default: 4]
+    #[arg(long)]
+    concurrency: Option<usize>,
+    /// The most the run may cost, in USD. No more requests are sent once it is reached
+    #[arg(long)]
+    budget: Option<f64>,
+}
+
+impl LlmArgs {
+    fn to_llm(&self, config: &config::Config) -> Result<llm::Llm, Box<dyn std::error::Error>> {
+        let mut config = config.llm.clone();
+        if let Some(backend) = self.llm_backend {
+            config.backend = backend;
+        }
+        if let Some(base_url) = &self.base_url {
+            config.base_url = Some(base_url.clone());
+            // A base URL on its own means a server other than OpenAI
+            if self.llm_backend.is_none() && config.backend == llm::LlmBackendKind::Openai {
+                config.backend = llm::LlmBackendKind::OpenaiCompatible;
+            }
+        }
+        if let Some(model) = &self.model {
+            config.grading.model = Some(model.clone());
+        }
+        if let Some(model) = &self.explanation_model {
+            config.explanations.model = Some(model.clone());
+        }
+        if let Some(temperature) = self.temperature {
+            config.grading.temperature = Some(temperature);
+            config.explanations.temperature = Some(temperature);
+        }
+        if let Some(concurrency) = self.concurrency {
+            config.concurrency = concurrency;
+        }
+        if let Some(budget) = self.budget {
+            config.budget = Some(budget);
+        }
+        llm::Llm::from_config(&config)
+    }
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Extract and validate without AI
@@ -25,6 +177,8 @@ enum Commands {
         archive_file: PathBuf,
         /// The destination directory
         destination_dir: PathBuf,
+        #[command(flatten)]
+        validation: ValidationArgs,
     },
     /// Extract, validate, and grade with AI
     WithAI {
@@ -34,8 +188,26 @@ enum Commands {
         destination_dir: PathBuf,
         /// Path to the assignment description PDF
         description_file: PathBuf,
+        /// Path to the grading rubric (TOML, YAML or JSON), or the grading criteria PDF
+        criteria_file: PathBuf,
+        /// Only grade the students that failed last time, without extracting and validating again
+        #[arg(long)]
+        retry_failed: bool,
+        /// Grade these students again even if nothing changed, e.g. `ola,kari`. Glob patterns are allowed, e.g. `'*'` for everyone
+        #[arg(long, value_delimiter = ',')]
+        regrade: Vec<String>,
+        #[command(flatten)]
+        validation: ValidationArgs,
+        #[command(flatten)]
+        llm: Box<LlmArgs>,
+    },
+    /// Extract a draft rubric from a grading criteria PDF, for review before grading
+    ExtractRubric {
         /// Path to the grading criteria PDF
         criteria_file: PathBuf,
+        /// Where to write the draft rubric (TOML, YAML or JSON, by extension)
+        #[arg(long, short, default_value = "rubric.toml")]
+        output: PathBuf,
     },
 }
 
@@ -43,16 +215,35 @@ enum Commands {
 async fn main() {
     let cli = Cli::parse();
 
+    let config = match config::Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error loading config: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     match &cli.command {
         Commands::WithoutAI {
             archive_file,
             destination_dir,
+            validation,
         } => {
-            if let Err(e) = extract::extract_files(archive_file, destination_dir) {
+            let options = match validation.to_options(&config, destination_dir) {
+                Ok(options) => options,
+                Err(e) => {
+                    eprintln!("Error in validation config: {}", e);
+                    std::process::exit(1);
+                }
+            };
+
+            if let Err(e) =
+                extract::extract_files(archive_file, destination_dir, grade::GRADING_FILES)
+            {
                 eprintln!("Error extracting file: {:?}", e);
             }
 
-            if let Err(e) = validate::validate_directory(destination_dir, false).await {
+            if let Err(e) = validate::validate_directory(destination_dir, None, &options).await {
                 eprintln!("Error during validation: {:?}", e);
             }
 
@@ -63,30 +254,107 @@ async fn main() {
             destination_dir,
             description_file,
             criteria_file,
+            retry_failed,
+            regrade,
+            validation,
+            llm,
         } => {
             dotenv().ok(); // Ensure .env is loaded
-            if env::var("OPENAI_API_KEY").is_err() {
-                eprintln!("Error: OPENAI_API_KEY environment variable is not set.");
-                std::process::exit(1);
-            }
+            let llm = match llm.to_llm(&config) {
+                Ok(llm) => llm,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            };
 
-            if let Err(e) = extract::extract_files(archive_file, destination_dir) {
-                eprintln!("Error extracting file: {:?}", e);
-            }
+            let options = match validation.to_options(&config, destination_dir) {
+                Ok(options) => options,
+                Err(e) => {
+                    eprintln!("Error in validation config: {}", e);
+                    std::process::exit(1);
+                }
+            };
 
-            // Validate the extracted files
-            if let Err(e) = validate::validate_directory(destination_dir, true).await {
-                eprintln!("Error during validation: {:?}", e);
+            let criteria = match grade::Criteria::load(criteria_file) {
+                Ok(criteria) => criteria,
+                Err(e) => {
+                    eprintln!("Error loading grading criteria: {}", e);
+                    std::process::exit(1);
+                }
+            };
+
+            // A retry grades the deliverables as they were extracted and validated last time
+            if !retry_failed {
+                if let Err(e) =
+                    extract::extract_files(archive_file, destination_dir, grade::GRADING_FILES)
+                {
+                    eprintln!("Error extracting file: {:?}", e);
+                }
+
+                // Validate the extracted files
+                if let Err(e) =
+                    validate::validate_directory(destination_dir, Some(&llm), &options).await
+                {
+                    eprintln!("Error during validation: {:?}", e);
+                }
             }
 
             // Now call the grade function with the description, criteria, and deliverables
-            if let Err(e) =
-                grade::grade_directory(destination_dir, description_file, criteria_file).await
+            if let Err(e) = grade::grade_directory(
+                destination_dir,
+                description_file,
+                &criteria,
+                &llm,
+                &options.prompts,
+                &grade::Selection {
+                    retry_failed: *retry_failed,
+                    regrade,
+                },
+            )
+            .await
             {
                 eprintln!("Error during grading: {:?}", e);
             }
 
-            println!("✅ Finished extracting, validating, and grading deliverables.");
+            // Written even if grading stopped, so the cost of the run is known
+            let usage = llm.usage.report();
+            match usage.write(destination_dir) {
+                Ok(()) => {
+                    usage.print_summary();
+                    println!(
+                        "> Wrote usage report to {:?}",
+                        destination_dir.join("usage.json")
+                    );
+                }
+                Err(e) => eprintln!("Error writing usage report: {}", e),
+            }
+
+            if *retry_failed {
+                println!("✅ Finished grading the deliverables that failed last time.");
+            } else {
+                println!("✅ Finished extracting, validating, and grading deliverables.");
+            }
+        }
+        Commands::ExtractRubric {
+            criteria_file,
+            output,
+        } => {
+            let text = match pdf::parse_pdf(criteria_file) {
+                Ok(text) => text,
+                Err(e) => {
+                    eprintln!("Error reading criteria PDF: {:?}", e);
+                    std::process::exit(1);
+                }
+            };
+
+            let rubric = rubric_extract::extract_rubric(&text);
+            if let Err(e) = rubric_extract::write_draft(&rubric, criteria_file, output) {
+                eprintln!("Error writing rubric: {}", e);
+                std::process::exit(1);
+            }
+
+            println!("✅ Finished extracting rubric. Review it before grading with it.");
         }
     }
 }
@@ -0,0 +1,471 @@
+use crate::config::PromptConfig;
+use crate::status;
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The language of the prompts, and of the feedback to the students.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum Language {
+    #[default]
+    #[serde(rename = "nb")]
+    Norwegian,
+    #[serde(rename = "en")]
+    English,
+}
+
+/// Short phrases used when formatting prompt sections and feedback, in each language.
+pub struct Phrases {
+    pub no_findings: &'static str,
+    pub no_requirement_checks: &'static str,
+    pub no_js_tests: &'static str,
+    pub no_feedback: &'static str,
+    pub starter_code: &'static str,
+    pub unchanged: &'static str,
+    pub modified: &'static str,
+    pub lines_removed: &'static str,
+    pub line: &'static str,
+    pub applies_to: &'static str,
+    pub no_issues: &'static str,
+    pub points: &'static str,
+    pub of: &'static str,
+    pub example: &'static str,
+    pub total: &'static str,
+    pub score: &'static str,
+    /// With `{expected}` and `{actual}`, for a failed JS test.
+    pub expected_actual: &'static str,
+    pub nothing: &'static str,
+    /// With `{error}`, for a script that failed to load before a JS test.
+    pub loading_failed: &'static str,
+    pub library_excluded: &'static str,
+    /// With `{lines}` for the number of lines left out.
+    pub lines_truncated: &'static str,
+    /// With `{part}` and `{parts}`, for grading a large deliverable in parts.
+    pub partial_files: &'static str,
+    /// Descriptions in the JSON schema of the grading.
+    pub schema_file: &'static str,
+    pub schema_total: &'static str,
+    pub schema_max_total: &'static str,
+    pub schema_summary: &'static str,
+    /// What is wrong with a grading response, sent back to the model with the `retry` template.
+    /// Placeholders in braces are filled in, e.g. `{criterion}` with the name of the criterion.
+    pub no_json: &'static str,
+    pub not_schema: &'static str,
+    pub no_criteria: &'static str,
+    pub max_points_not_positive: &'static str,
+    pub points_out_of_range: &'static str,
+    pub no_justification: &'static str,
+    pub lines_reversed: &'static str,
+    pub total_not_sum: &'static str,
+    pub max_total_not_sum: &'static str,
+    pub wrong_max_total: &'static str,
+    pub no_summary: &'static str,
+    pub unknown_criterion: &'static str,
+    pub wrong_max_points: &'static str,
+    pub scored_twice: &'static str,
+    pub criterion_missing: &'static str,
+}
+
+const NORWEGIAN: Phrases = Phrases {
+    no_findings: "Ingen funn.",
+    no_requirement_checks: "Ingen automatiske sjekker av kravene.",
+    no_js_tests: "Ingen automatiske tester av JavaScript.",
+    no_feedback: "Ingen tilbakemelding generert.",
+    starter_code: "Oppgaven har startkode. Filer som er uendret fra startkoden er ikke skrevet av studenten, og skal ikke vurderes. \
+        Endrede filer vises som diff mot startkoden, der linjer med + er skrevet av studenten og linjer med - er fjernet fra startkoden.",
+    unchanged: "uendret fra startkoden",
+    modified: "endret fra startkoden",
+    lines_removed: "bare linjer fjernet",
+    line: "linje",
+    applies_to: "Gjelder",
+    no_issues: "Ingen formelle feil funnet.",
+    points: "poeng",
+    of: "av",
+    example: "Eksempel",
+    total: "Totalt",
+    score: "Poengsum",
+    expected_actual: "Forventet {expected}, fikk {actual}",
+    nothing: "ingenting",
+    loading_failed: "Feil ved lasting av {error}",
+    library_excluded: "bibliotek eller minifisert fil, ikke skrevet av studenten og utelatt fra vurderingen",
+    lines_truncated: "[… {lines} linjer til er utelatt fordi filen er for stor]",
+    partial_files: "Innleveringen er for stor for én forespørsel, og er delt i {parts} deler. Dette er del {part}, med bare noen av filene. \
+        Vurder alle kriteriene ut fra filene i denne delen. Delene slås sammen til én vurdering etterpå.",
+    schema_file: "Sti til filen, relativt til innleveringen",
+    schema_total: "Summen av points",
+    schema_max_total: "Summen av max_points",
+    schema_summary: "Samlet tilbakemelding til studentene",
+    no_json: "Svaret inneholder ikke et JSON-objekt",
+    not_schema: "Svaret følger ikke skjemaet: {error}",
+    no_criteria: "“criteria” kan ikke være tom",
+    max_points_not_positive: "“{criterion}”: max_points må være større enn 0",
+    points_out_of_range: "“{criterion}”: points ({points}) må være mellom 0 og max_points ({max_points})",
+    no_justification: "“{criterion}”: justification er tom",
+    lines_reversed: "“{criterion}”: start_line ({start}) er etter end_line ({end}) i {file}",
+    total_not_sum: "total ({total}) er ikke summen av points ({sum})",
+    max_total_not_sum: "max_total ({max_total}) er ikke summen av max_points ({sum})",
+    wrong_max_total: "Summen av max_points ({sum}) skal være {expected}",
+    no_summary: "summary er tom",
+    unknown_criterion: "“{criterion}” er ikke et kriterium i vurderingsveiledningen",
+    wrong_max_points: "“{criterion}”: max_points skal være {expected}, ikke {max_points}",
+    scored_twice: "“{criterion}” er vurdert mer enn én gang",
+    criterion_missing: "“{criterion}” mangler i svaret",
+};
+
+const ENGLISH: Phrases = Phrases {
+    no_findings: "No findings.",
+    no_requirement_checks: "No automatic checks of the requirements.",
+    no_js_tests: "No automatic tests of the JavaScript.",
+    no_feedback: "No feedback generated.",
+    starter_code: "The assignment has starter code. Files that are unchanged from the starter code are not written by the student, and must not be graded. \
+        Modified files are shown as a diff against the starter code, where lines with + are written by the student and lines with - are removed from the starter code.",
+    unchanged: "unchanged from the starter code",
+    modified: "modified from the starter code",
+    lines_removed: "lines removed only",
+    line: "line",
+    applies_to: "Applies to",
+    no_issues: "No formal errors found.",
+    points: "points",
+    of: "of",
+    example: "Example",
+    total: "Total",
+    score: "Score",
+    expected_actual: "Expected {expected}, got {actual}",
+    nothing: "nothing",
+    loading_failed: "Error while loading {error}",
+    library_excluded: "library or minified file, not written by the student and left out of the grading",
+    lines_truncated: "[… {lines} more lines are left out, since the file is too large]",
+    partial_files: "The deliverable is too large for a single request, and is split into {parts} parts. This is part {part}, with only some of the files. \
+        Grade every criterion by the files in this part. The parts are merged into a single grading afterwards.",
+    schema_file: "Path of the file, relative to the deliverable",
+    schema_total: "The sum of points",
+    schema_max_total: "The sum of max_points",
+    schema_summary: "Overall feedback to the students",
+    no_json: "The response doesn't contain a JSON object",
+    not_schema: "The response doesn't follow the schema: {error}",
+    no_criteria: "“criteria” can't be empty",
+    max_points_not_positive: "“{criterion}”: max_points must be greater than 0",
+    points_out_of_range: "“{criterion}”: points ({points}) must be between 0 and max_points ({max_points})",
+    no_justification: "“{criterion}”: justification is empty",
+    lines_reversed: "“{criterion}”: start_line ({start}) is after end_line ({end}) in {file}",
+    total_not_sum: "total ({total}) isn't the sum of points ({sum})",
+    max_total_not_sum: "max_total ({max_total}) isn't the sum of max_points ({sum})",
+    wrong_max_total: "The sum of max_points ({sum}) must be {expected}",
+    no_summary: "summary is empty",
+    unknown_criterion: "“{criterion}” isn't a criterion in the rubric",
+    wrong_max_points: "“{criterion}”: max_points must be {expected}, not {max_points}",
+    scored_twice: "“{criterion}” is graded more than once",
+    criterion_missing: "“{criterion}” is missing from the response",
+};
+
+impl Language {
+    pub fn phrases(&self) -> &'static Phrases {
+        match self {
+            Language::Norwegian => &NORWEGIAN,
+            Language::English => &ENGLISH,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Language::Norwegian => "nb",
+            Language::English => "en",
+        }
+    }
+}
+
+/// The kinds of templates, with the variables each of them can use.
+#[derive(Debug, Clone, Copy)]
+enum TemplateKind {
+    Grading,
+    Retry,
+    Merge,
+    Explanation,
+    Feedback,
+    ValidationFeedback,
+}
+
+impl TemplateKind {
+    fn name(&self) -> &'static str {
+        match self {
+            TemplateKind::Grading => "grading",
+            TemplateKind::Retry => "retry",
+            TemplateKind::Merge => "merge",
+            TemplateKind::Explanation => "explanation",
+            TemplateKind::Feedback => "feedback",
+            TemplateKind::ValidationFeedback => "validation-feedback",
+        }
+    }
+
+    fn variables(&self) -> &'static [&'static str] {
+        match self {
+            TemplateKind::Grading => &[
+                "student",
+                "assignment",
+                "max_points",
+                "schema",
+                "description",
+                "criteria",
+                "structure_findings",
+                "issues",
+                "accessibility_findings",
+                "requirements",
+                "js_tests",
+                "files",
+            ],
+            TemplateKind::Retry => &["prompt", "response", "violations"],
+            TemplateKind::Merge => &[
+                "student",
+                "assignment",
+                "max_points",
+                "schema",
+                "description",
+                "criteria",
+                "gradings",
+            ],
+            TemplateKind::Explanation => &["message"],
+            TemplateKind::Feedback | TemplateKind::ValidationFeedback => {
+                &["student", "assignment", "feedback"]
+            }
+        }
+    }
+
+    fn default(&self, language: Language) -> &'static str {
+        match (language, self) {
+            (Language::Norwegian, TemplateKind::Grading) => include_str!("prompts/nb/grading.md"),
+            (Language::Norwegian, TemplateKind::Retry) => include_str!("prompts/nb/retry.md"),
+            (Language::Norwegian, TemplateKind::Merge) => include_str!("prompts/nb/merge.md"),
+            (Language::Norwegian, TemplateKind::Explanation) => {
+                include_str!("prompts/nb/explanation.md")
+            }
+            (Language::Norwegian, TemplateKind::Feedback) => include_str!("prompts/nb/feedback.md"),
+            (Language::Norwegian, TemplateKind::ValidationFeedback) => {
+                include_str!("prompts/nb/validation-feedback.md")
+            }
+            (Language::English, TemplateKind::Grading) => include_str!("prompts/en/grading.md"),
+            (Language::English, TemplateKind::Retry) => include_str!("prompts/en/retry.md"),
+            (Language::English, TemplateKind::Merge) => include_str!("prompts/en/merge.md"),
+            (Language::English, TemplateKind::Explanation) => {
+                include_str!("prompts/en/explanation.md")
+            }
+            (Language::English, TemplateKind::Feedback) => include_str!("prompts/en/feedback.md"),
+            (Language::English, TemplateKind::ValidationFeedback) => {
+                include_str!("prompts/en/validation-feedback.md")
+            }
+        }
+    }
+}
+
+/// A prompt or feedback template, with variables written as `{{name}}`.
+#[derive(Debug, Clone)]
+pub struct Template {
+    text: String,
+}
+
+impl Template {
+    /// Function to load a template from a file, or use the default for the language.
+    /// Variables the template can't use are rejected, so typos are caught before anything is sent to the model.
+    fn load(
+        kind: TemplateKind,
+        path: Option<&Path>,
+        language: Language,
+    ) -> Result<Template, Box<dyn std::error::Error>> {
+        let text = match path {
+            Some(path) => fs::read_to_string(path).map_err(|e| {
+                format!(
+                    "Could not read {} template {}: {}",
+                    kind.name(),
+                    path.display(),
+                    e
+                )
+            })?,
+            None => kind.default(language).to_string(),
+        };
+
+        let variable = Regex::new(r"\{\{\s*([^}]*?)\s*\}\}").unwrap();
+        for captures in variable.captures_iter(&text) {
+            let name = &captures[1];
+            if !kind.variables().contains(&name) {
+                return Err(format!(
+                    "Unknown variable {{{{{}}}}} in {} template{}. Available variables: {}",
+                    name,
+                    kind.name(),
+                    path.map(|path| format!(" {}", path.display()))
+                        .unwrap_or_default(),
+                    kind.variables().join(", ")
+                )
+                .into());
+            }
+        }
+
+        Ok(Template {
+            text: text.trim_end().to_string(),
+        })
+    }
+
+    /// Function to fill in the variables of the template. Values are inserted as is, so a value containing
+    /// `{{name}}`, e.g. a student's code, is never expanded.
+    pub fn render(&self, variables: &[(&str, &str)]) -> String {
+        let variable = Regex::new(r"\{\{\s*([^}]*?)\s*\}\}").unwrap();
+        variable
+            .replace_all(&self.text, |captures: &regex::Captures| {
+                variables
+                    .iter()
+                    .find(|(name, _)| *name == &captures[1])
+                    .map(|(_, value)| value.to_string())
+                    .unwrap_or_default()
+            })
+            .to_string()
+    }
+}
+
+/// The templates and language used when talking to the model, and when writing feedback.
+#[derive(Debug, Clone)]
+pub struct Prompts {
+    pub language: Language,
+    pub grading: Template,
+    /// Asks the model again, after an invalid grading response.
+    pub retry: Template,
+    /// Merges the gradings of a deliverable graded in parts.
+    pub merge: Template,
+    pub explanation: Template,
+    pub feedback: Template,
+    pub validation_feedback: Template,
+}
+
+impl Prompts {
+    pub fn load(config: &PromptConfig) -> Result<Prompts, Box<dyn std::error::Error>> {
+        let language = config.language.unwrap_or_default();
+        Ok(Prompts {
+            language,
+            grading: Template::load(TemplateKind::Grading, config.grading.as_deref(), language)?,
+            retry: Template::load(TemplateKind::Retry, config.retry.as_deref(), language)?,
+            merge: Template::load(TemplateKind::Merge, config.merge.as_deref(), language)?,
+            explanation: Template::load(
+                TemplateKind::Explanation,
+                config.explanation.as_deref(),
+                language,
+            )?,
+            feedback: Template::load(TemplateKind::Feedback, config.feedback.as_deref(), language)?,
+            validation_feedback: Template::load(
+                TemplateKind::ValidationFeedback,
+                config.validation_feedback.as_deref(),
+                language,
+            )?,
+        })
+    }
+
+    pub fn phrases(&self) -> &'static Phrases {
+        self.language.phrases()
+    }
+
+    /// Function to identify the templates used for grading, so deliverables graded with other prompts are graded again.
+    pub fn version(&self) -> String {
+        let hash = status::hash(&[
+            self.language.code(),
+            &self.grading.text,
+            &self.retry.text,
+            &self.merge.text,
+            &self.feedback.text,
+        ]);
+        hash[..12].to_string()
+    }
+
+    /// Function to find the explanation cache for the language, e.g. `explanations.en.json`,
+    /// so explanations in one language aren't reused for another. Norwegian uses the file as is.
+    pub fn explanation_cache(&self, path: &Path) -> PathBuf {
+        if self.language == Language::Norwegian {
+            return path.to_path_buf();
+        }
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let file_name = match path.extension().and_then(|s| s.to_str()) {
+            Some(extension) => format!("{}.{}.{}", stem, self.language.code(), extension),
+            None => format!("{}.{}", stem, self.language.code()),
+        };
+        path.with_file_name(file_name)
+    }
+}
+
+/// Function to find the name of a student, from their directory in `deliverables`.
+pub fn student_name(student_dir: &Path) -> String {
+    student_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Function to find the name of the assignment, from the destination directory the student directory is in.
+pub fn assignment_name(student_dir: &Path) -> String {
+    student_dir
+        .parent()
+        .and_then(Path::parent)
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_templates_only_use_their_own_variables() {
+        for language in [Language::Norwegian, Language::English] {
+            let config = PromptConfig {
+                language: Some(language),
+                ..PromptConfig::default()
+            };
+            // Loading checks every variable in every template
+            let prompts = Prompts::load(&config).unwrap();
+            assert_eq!(prompts.language, language);
+        }
+    }
+
+    #[test]
+    fn variables_are_filled_in_once_and_unknown_ones_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("feedback.md");
+
+        fs::write(
+            &path,
+            "Hei {{student}}!\n\n{{ feedback }}\n{{assignment}}\n\n",
+        )
+        .unwrap();
+        let template =
+            Template::load(TemplateKind::Feedback, Some(&path), Language::English).unwrap();
+        assert_eq!(
+            template.render(&[("student", "Ola"), ("feedback", "Bruk {{student}} i koden")]),
+            "Hei Ola!\n\nBruk {{student}} i koden\n"
+        );
+
+        fs::write(&path, "Hei {{studnet}}!").unwrap();
+        let error = Template::load(TemplateKind::Feedback, Some(&path), Language::English)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with("Unknown variable {{studnet}} in feedback template"),
+            "{}",
+            error
+        );
+        assert!(error.ends_with("Available variables: student, assignment, feedback"));
+    }
+
+    #[test]
+    fn explanations_are_cached_per_language() {
+        let english = Prompts::load(&PromptConfig {
+            language: Some(Language::English),
+            ..PromptConfig::default()
+        })
+        .unwrap();
+        let norwegian = Prompts::load(&PromptConfig::default()).unwrap();
+
+        let cache = Path::new("cache/explanations.json");
+        assert_eq!(norwegian.explanation_cache(cache), cache);
+        assert_eq!(
+            english.explanation_cache(cache),
+            Path::new("cache/explanations.en.json")
+        );
+        assert_ne!(norwegian.version(), english.version());
+    }
+}
@@ -0,0 +1,321 @@
+use crate::encoding;
+use crate::issue::{line_column, Issue, IssueSource, Severity};
+use crate::structure::project_root;
+use crate::vendor::VendorDetector;
+use regex::Regex;
+use std::fs;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+/// A local file referenced from an HTML or CSS file.
+struct Reference {
+    target: String,
+    offset: usize,
+}
+
+/// The outcome of resolving a reference against the files on disk.
+enum Resolution {
+    Found,
+    /// The file exists, but with different casing, e.g. `Style.css` vs. `style.css`
+    CaseMismatch(PathBuf),
+    Missing,
+    /// The path leaves the deliverable, e.g. `../../bilder/logo.png`
+    Outside,
+}
+
+/// Function to check every local `href`, `src` and CSS `url()` reference in a deliverable against the files on disk.
+pub fn check_references(
+    student_dir: &Path,
+    vendor: &VendorDetector,
+) -> Result<Vec<Issue>, Box<dyn std::error::Error>> {
+    let attribute =
+        Regex::new(r#"(?i)\s(?:href|src)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap();
+    let url = Regex::new(r#"(?i)url\(\s*(?:"([^"]*)"|'([^']*)'|([^\s"')]+))\s*\)"#).unwrap();
+    let import = Regex::new(r#"(?i)@import\s+(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    let comment = Regex::new(r"(?s)<!--.*?-->|/\*.*?\*/").unwrap();
+
+    let mut issues = Vec::new();
+    // Paths starting with `/` are relative to the root of the site
+    let site_root = project_root(student_dir)?;
+
+    for entry in WalkDir::new(student_dir) {
+        let entry = entry?;
+        let path = entry.path();
+        let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+
+        let patterns = match extension {
+            "html" => vec![&attribute, &url],
+            "css" => vec![&url, &import],
+            _ => continue,
+        };
+
+        // References inside a library, e.g. to the fonts of Font Awesome, aren't the student's
+        let relative_path = path.strip_prefix(student_dir).unwrap_or(path);
+        if vendor.detect(relative_path, &fs::read(path)?).is_some() {
+            continue;
+        }
+        let content = match encoding::read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+
+        let comments: Vec<Range<usize>> = comment.find_iter(&content).map(|m| m.range()).collect();
+        let base_dir = path.parent().unwrap_or(student_dir);
+
+        for pattern in patterns {
+            for captures in pattern.captures_iter(&content) {
+                let value = (1..=3).find_map(|i| captures.get(i)).unwrap();
+                if comments.iter().any(|range| range.contains(&value.start())) {
+                    continue;
+                }
+
+                let reference = Reference {
+                    target: value.as_str().trim().to_string(),
+                    offset: value.start(),
+                };
+                let dirs = Dirs {
+                    student_dir,
+                    site_root: &site_root,
+                    base_dir,
+                };
+                if let Some(issue) = check_reference(&dirs, relative_path, &content, &reference) {
+                    issues.push(issue);
+                }
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Where references are resolved from.
+struct Dirs<'a> {
+    student_dir: &'a Path,
+    /// What paths starting with `/` are relative to.
+    site_root: &'a Path,
+    /// The folder of the file with the reference.
+    base_dir: &'a Path,
+}
+
+/// Function to check a single reference, returning an issue if it doesn't resolve to a file in the deliverable.
+fn check_reference(
+    dirs: &Dirs,
+    file: &Path,
+    content: &str,
+    reference: &Reference,
+) -> Option<Issue> {
+    let target = reference.target.as_str();
+    let lowercase = target.to_ascii_lowercase();
+
+    // Only local files are checked
+    if target.is_empty()
+        || target.starts_with('#')
+        || target.starts_with("//")
+        || ["http:", "https:", "mailto:", "tel:", "javascript:", "data:"]
+            .iter()
+            .any(|scheme| lowercase.starts_with(scheme))
+    {
+        return None;
+    }
+
+    let (line, column) = line_column(content, reference.offset);
+    let issue = |severity: Severity, message: String| {
+        Some(
+            Issue::new(IssueSource::References, severity, file, message)
+                .at(Some(line), Some(column))
+                .with_extract(Some(target.to_string())),
+        )
+    };
+
+    if is_absolute_local_path(target) {
+        return issue(
+            Severity::Error,
+            format!(
+                "“{}” is an absolute path on the author's computer, and won't work anywhere else. Use a path relative to the file instead",
+                target
+            ),
+        );
+    }
+
+    // Strip the query string and fragment, and decode escapes such as `%20` and `%C3%A6`
+    let path = percent_decode(target.split(['?', '#']).next().unwrap_or(""));
+    if path.is_empty() {
+        return None;
+    }
+
+    let resolution = if let Some(root_relative) = path.strip_prefix('/') {
+        resolve(
+            dirs.student_dir,
+            dirs.site_root,
+            Path::new(&root_relative.replace('\\', "/")),
+        )
+    } else {
+        resolve(
+            dirs.student_dir,
+            dirs.base_dir,
+            Path::new(&path.replace('\\', "/")),
+        )
+    };
+    let student_dir = dirs.student_dir;
+
+    match resolution {
+        Resolution::Found if path.contains('\\') => issue(
+            Severity::Warning,
+            format!(
+                "“{}” uses backslashes, which only work on Windows. Use forward slashes (/) instead",
+                target
+            ),
+        ),
+        Resolution::Found => None,
+        Resolution::CaseMismatch(actual) => issue(
+            Severity::Error,
+            format!(
+                "“{}” doesn't match the casing of “{}” on disk. Web servers are case-sensitive, so the file won't be found",
+                target,
+                actual.strip_prefix(student_dir).unwrap_or(&actual).display()
+            ),
+        ),
+        Resolution::Missing => issue(
+            Severity::Error,
+            format!("“{}” refers to a file that doesn't exist", target),
+        ),
+        Resolution::Outside => issue(
+            Severity::Error,
+            format!(
+                "“{}” refers to a file outside the deliverable, which won't be there when the site is published",
+                target
+            ),
+        ),
+    }
+}
+
+/// Function to decode the `%XX` escapes of a URL path. The path is kept as is if it doesn't decode to UTF-8.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escape = bytes
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).unwrap_or_else(|_| path.to_string())
+}
+
+/// Function to detect paths such as `C:\Users\...`, `file:///...` or `/Users/...` that only exist on the author's computer.
+fn is_absolute_local_path(target: &str) -> bool {
+    let bytes = target.as_bytes();
+    let is_drive_path = bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && (bytes[2] == b'\\' || bytes[2] == b'/');
+
+    is_drive_path
+        || target.to_ascii_lowercase().starts_with("file:")
+        || ["/Users/", "/home/", "\\Users\\"]
+            .iter()
+            .any(|prefix| target.starts_with(prefix))
+}
+
+/// Function to resolve a relative path component by component, comparing names exactly.
+/// This also detects casing mismatches on case-insensitive file systems, such as on macOS.
+/// Paths leaving `student_dir` aren't followed.
+fn resolve(student_dir: &Path, base_dir: &Path, relative: &Path) -> Resolution {
+    let mut current = base_dir.to_path_buf();
+    let mut case_mismatch = false;
+
+    for component in relative.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if current == student_dir {
+                    return Resolution::Outside;
+                }
+                current.pop();
+            }
+            Component::Normal(name) => {
+                let entries: Vec<_> = match fs::read_dir(&current) {
+                    Ok(entries) => entries
+                        .filter_map(Result::ok)
+                        .map(|e| e.file_name())
+                        .collect(),
+                    Err(_) => return Resolution::Missing,
+                };
+
+                if entries.iter().any(|entry| entry == name) {
+                    current.push(name);
+                } else if let Some(entry) = entries.iter().find(|entry| {
+                    entry.to_string_lossy().to_lowercase() == name.to_string_lossy().to_lowercase()
+                }) {
+                    case_mismatch = true;
+                    current.push(entry);
+                } else {
+                    return Resolution::Missing;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return Resolution::Missing,
+        }
+    }
+
+    if case_mismatch {
+        Resolution::CaseMismatch(current)
+    } else {
+        Resolution::Found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::VendorConfig;
+
+    #[test]
+    fn references_are_decoded_and_kept_inside_the_deliverable() {
+        let dir = tempfile::tempdir().unwrap();
+        let student_dir = dir.path().join("ola");
+        let project = student_dir.join("prosjekt");
+        fs::create_dir_all(project.join("css")).unwrap();
+        fs::write(dir.path().join("hemmelig.png"), "").unwrap();
+        fs::write(project.join("bildeæ.png"), "").unwrap();
+        fs::write(project.join("mitt bilde.png"), "").unwrap();
+        fs::write(project.join("css/style.css"), "").unwrap();
+        // The fonts of a library that were left out don't count
+        fs::write(
+            project.join("css/font-awesome.min.css"),
+            "@font-face{src:url(../fonts/fontawesome-webfont.woff2)}",
+        )
+        .unwrap();
+        fs::write(
+            project.join("index.html"),
+            r#"<link href="/css/style.css"><img src="bilde%C3%A6.png"><img src="mitt%20bilde.png">
+<img src="../../hemmelig.png"><img src="mangler.png">"#,
+        )
+        .unwrap();
+
+        let vendor = VendorDetector::new(&VendorConfig::default()).unwrap();
+        let issues = check_references(&student_dir, &vendor).unwrap();
+        let messages: Vec<&str> = issues.iter().map(|issue| issue.message.as_str()).collect();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].contains("outside the deliverable"));
+        assert!(messages[1].contains("“mangler.png” refers to a file that doesn't exist"));
+    }
+
+    #[test]
+    fn invalid_escapes_are_kept() {
+        assert_eq!(percent_decode("a%2Fb%zz%"), "a/b%zz%");
+        assert_eq!(percent_decode("%FF.png"), "%FF.png");
+    }
+}
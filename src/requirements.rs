@@ -0,0 +1,942 @@
+use crate::encoding;
+use crate::prompts::Phrases;
+use crate::starter::StarterDiff;
+use crate::stylesheets::{StyleEntry, StyleEntryKind, StyleIndex};
+use crate::vendor::VendorDetector;
+use regex::{Regex, RegexBuilder};
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// A file of requirement rules for an assignment, e.g.
+///
+/// ```toml
+/// [[rule]]
+/// name = "A table with 7 rows inside a section, above the footer"
+/// points = 5
+///
+/// [[rule.check]]
+/// selector = "section table tr"
+/// count = 7
+///
+/// [[rule.check]]
+/// selector = "section table"
+/// before = "footer"
+///
+/// [[rule]]
+/// name = "Uses flexbox or grid for the layout"
+/// points = 2
+///
+/// [[rule.css]]
+/// property = "display"
+/// value = "flex|grid"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSet {
+    #[serde(rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+/// A requirement worth a number of points. It passes if a single HTML file satisfies all of its HTML checks,
+/// and the deliverable's styles satisfy all of its CSS checks.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name: String,
+    pub points: f64,
+    /// Only check this file, relative to the student directory. By default every HTML file is tried.
+    pub file: Option<PathBuf>,
+    #[serde(default, rename = "check")]
+    pub checks: Vec<HtmlCheck>,
+    #[serde(default)]
+    pub css: Vec<CssCheck>,
+}
+
+/// An assertion about the elements matching a CSS selector.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HtmlCheck {
+    pub selector: String,
+    /// Exact number of matching elements.
+    pub count: Option<usize>,
+    /// Minimum number of matching elements. Defaults to 1 unless `count` or `max` is given.
+    pub min: Option<usize>,
+    /// Maximum number of matching elements.
+    pub max: Option<usize>,
+    /// Only count elements whose text contains this, ignoring case.
+    pub text: Option<String>,
+    /// Only count elements with this attribute.
+    pub attribute: Option<String>,
+    /// Only count elements where `attribute` has exactly this value.
+    pub value: Option<String>,
+    /// The first matching element must come before the first element matching this selector.
+    pub before: Option<String>,
+    /// The first matching element must come after the first element matching this selector.
+    pub after: Option<String>,
+}
+
+/// An assertion about the at-rules, style rules or declarations in a deliverable's styles,
+/// including `<style>` elements and style attributes. Patterns are case-insensitive regular expressions.
+///
+/// Declarations are matched if `property` or `value` is given, style rules if `selector` is given,
+/// and at-rules otherwise.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CssCheck {
+    /// Pattern for the selector of the style rule, e.g. `nav a:hover`.
+    pub selector: Option<String>,
+    /// Pattern for the whole property name, e.g. `display` or `--.*` for custom properties.
+    pub property: Option<String>,
+    /// Pattern for the value, e.g. `flex|grid`.
+    pub value: Option<String>,
+    /// Name of an at-rule that must enclose the match, e.g. `media` or `keyframes`.
+    pub at_rule: Option<String>,
+    /// Pattern for the query of an enclosing `@media` rule.
+    pub media: Option<String>,
+    /// An enclosing `@media` rule must limit the viewport width to at most this many pixels.
+    pub max_width: Option<f64>,
+    /// Exact number of matches.
+    pub count: Option<usize>,
+    /// Minimum number of matches. Defaults to 1 unless `count` or `max` is given.
+    pub min: Option<usize>,
+    /// Maximum number of matches.
+    pub max: Option<usize>,
+}
+
+impl RuleSet {
+    /// Function to load a rule file, checking that every selector is valid.
+    pub fn load(path: &Path) -> Result<RuleSet, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read rule file {}: {}", path.display(), e))?;
+        let rule_set: RuleSet = toml::from_str(&contents)
+            .map_err(|e| format!("Invalid rule file {}: {}", path.display(), e))?;
+
+        for rule in &rule_set.rules {
+            if rule.checks.is_empty() && rule.css.is_empty() {
+                return Err(
+                    format!("Invalid rule “{}”: it has no HTML or CSS checks", rule.name).into(),
+                );
+            }
+            for check in &rule.checks {
+                let selectors = [
+                    Some(&check.selector),
+                    check.before.as_ref(),
+                    check.after.as_ref(),
+                ];
+                for selector in selectors.into_iter().flatten() {
+                    parse_selector(selector)
+                        .map_err(|e| format!("Invalid rule “{}”: {}", rule.name, e))?;
+                }
+                if check.value.is_some() && check.attribute.is_none() {
+                    return Err(format!(
+                        "Invalid rule “{}”: “value” requires “attribute”",
+                        rule.name
+                    )
+                    .into());
+                }
+            }
+            for check in &rule.css {
+                check
+                    .patterns()
+                    .map_err(|e| format!("Invalid rule “{}”: {}", rule.name, e))?;
+            }
+        }
+
+        println!(
+            "> Loaded {} requirement rules from {}",
+            rule_set.rules.len(),
+            path.display()
+        );
+        Ok(rule_set)
+    }
+
+    /// Function to evaluate every rule against a student's deliverable. Libraries and template code from the starter
+    /// don't count, since the student didn't write them. If the deliverable can't be read, every rule fails with
+    /// the error, so one broken deliverable doesn't stop the others.
+    pub fn evaluate(
+        &self,
+        student_dir: &Path,
+        vendor: &VendorDetector,
+        starter_diff: Option<&StarterDiff>,
+    ) -> Checklist {
+        self.evaluate_deliverable(student_dir, vendor, starter_diff)
+            .unwrap_or_else(|e| {
+                eprintln!("> Could not check the requirements: {}", e);
+                let results = self
+                    .rules
+                    .iter()
+                    .map(|rule| RuleResult {
+                        name: rule.name.clone(),
+                        points: 0.0,
+                        max_points: rule.points,
+                        passed: false,
+                        file: None,
+                        failures: vec![format!("Could not check the deliverable: {}", e)],
+                        evidence: Vec::new(),
+                    })
+                    .collect();
+                Checklist { results }
+            })
+    }
+
+    fn evaluate_deliverable(
+        &self,
+        student_dir: &Path,
+        vendor: &VendorDetector,
+        starter_diff: Option<&StarterDiff>,
+    ) -> Result<Checklist, Box<dyn std::error::Error>> {
+        let styles = if self.rules.iter().any(|rule| !rule.css.is_empty()) {
+            StyleIndex::build(student_dir, vendor, starter_diff)?
+        } else {
+            StyleIndex::default()
+        };
+
+        let mut documents = HashMap::new();
+        // Pages left out, and why, so a rule naming one of them says so instead of that it doesn't exist
+        let mut skipped = HashMap::new();
+        for entry in WalkDir::new(student_dir) {
+            let entry = entry?;
+            let path = entry.path();
+            if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("html") {
+                let relative_path = path.strip_prefix(student_dir).unwrap_or(path).to_path_buf();
+                if starter_diff.is_some_and(|diff| diff.is_template(&relative_path)) {
+                    skipped.insert(relative_path, "unchanged starter code".to_string());
+                    continue;
+                }
+                if let Some(reason) = vendor.detect(&relative_path, &fs::read(path)?) {
+                    skipped.insert(relative_path, reason.to_string());
+                    continue;
+                }
+                documents.insert(
+                    relative_path,
+                    Html::parse_document(&encoding::read_to_string(path)?),
+                );
+            }
+        }
+
+        let mut files: Vec<&PathBuf> = documents.keys().collect();
+        files.sort();
+
+        let results = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let (file, mut failures) = evaluate_html(rule, &documents, &files, &skipped);
+
+                let mut evidence = Vec::new();
+                for check in &rule.css {
+                    match check.evaluate(&styles) {
+                        Ok(snippets) => evidence.extend(snippets),
+                        Err(failure) => failures.push(failure),
+                    }
+                }
+
+                let passed = failures.is_empty();
+                RuleResult {
+                    name: rule.name.clone(),
+                    points: if passed { rule.points } else { 0.0 },
+                    max_points: rule.points,
+                    passed,
+                    file,
+                    failures,
+                    evidence,
+                }
+            })
+            .collect();
+
+        Ok(Checklist { results })
+    }
+}
+
+/// Function to run the HTML checks of a rule against each candidate file, returning the file that satisfied them,
+/// or why each file failed.
+fn evaluate_html(
+    rule: &Rule,
+    documents: &HashMap<PathBuf, Html>,
+    files: &[&PathBuf],
+    skipped: &HashMap<PathBuf, String>,
+) -> (Option<PathBuf>, Vec<String>) {
+    if rule.checks.is_empty() {
+        return (None, Vec::new());
+    }
+
+    let candidates: Vec<&PathBuf> = match &rule.file {
+        Some(file) => files.iter().copied().filter(|f| *f == file).collect(),
+        None => files.to_vec(),
+    };
+
+    let mut failures = Vec::new();
+    for file in &candidates {
+        let document = &documents[*file];
+        let failed: Vec<String> = rule
+            .checks
+            .iter()
+            .filter_map(|check| check.evaluate(document).err())
+            .collect();
+
+        if failed.is_empty() {
+            return (Some(file.to_path_buf()), Vec::new());
+        }
+        failures.push(format!("{}: {}", file.display(), failed.join("; ")));
+    }
+
+    if candidates.is_empty() {
+        failures.push(match &rule.file {
+            Some(file) => match skipped.get(file) {
+                Some(reason) => format!("{} is not checked ({})", file.display(), reason),
+                None => format!("{} doesn't exist", file.display()),
+            },
+            None => "No HTML files".to_string(),
+        });
+    }
+    (None, failures)
+}
+
+fn parse_selector(selector: &str) -> Result<Selector, String> {
+    Selector::parse(selector).map_err(|e| format!("invalid selector “{}”: {}", selector, e))
+}
+
+/// Function to get the text of an element with whitespace collapsed, for comparing against rules.
+fn element_text(element: &ElementRef) -> String {
+    element
+        .text()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl HtmlCheck {
+    /// Function to run the check on a document, returning why it failed.
+    fn evaluate(&self, document: &Html) -> Result<(), String> {
+        let selector = parse_selector(&self.selector)?;
+
+        let matches: Vec<ElementRef> = document
+            .select(&selector)
+            .filter(|element| match &self.text {
+                Some(text) => element_text(element)
+                    .to_lowercase()
+                    .contains(&text.to_lowercase()),
+                None => true,
+            })
+            .filter(|element| match &self.attribute {
+                Some(attribute) => match (element.value().attr(attribute), &self.value) {
+                    (Some(actual), Some(expected)) => actual == expected,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                },
+                None => true,
+            })
+            .collect();
+
+        let description = self.describe();
+        let found = matches.len();
+
+        check_count(found, self.count, self.min, self.max, &description)?;
+
+        let order = [(&self.before, "before"), (&self.after, "after")];
+        for (other, relation) in order {
+            let Some(other) = other else {
+                continue;
+            };
+            let Some(first) = matches.first() else {
+                return Err(format!("no {} to compare with “{}”", description, other));
+            };
+            let Some(other_element) = document.select(&parse_selector(other)?).next() else {
+                return Err(format!("no “{}” to compare {} with", other, description));
+            };
+
+            let (this, that) = (
+                document_position(document, first),
+                document_position(document, &other_element),
+            );
+            let in_order = if relation == "before" {
+                this < that
+            } else {
+                this > that
+            };
+            if !in_order {
+                return Err(format!("{} is not {} “{}”", description, relation, other));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Function to describe the elements the check is looking for, for failure messages.
+    fn describe(&self) -> String {
+        let mut description = format!("“{}”", self.selector);
+        if let Some(attribute) = &self.attribute {
+            match &self.value {
+                Some(value) => description.push_str(&format!(" with {}=\"{}\"", attribute, value)),
+                None => description.push_str(&format!(" with attribute “{}”", attribute)),
+            }
+        }
+        if let Some(text) = &self.text {
+            description.push_str(&format!(" containing “{}”", text));
+        }
+        description
+    }
+}
+
+/// The compiled patterns of a CSS check.
+struct CssPatterns {
+    selector: Option<Regex>,
+    property: Option<Regex>,
+    value: Option<Regex>,
+    media: Option<Regex>,
+}
+
+/// How many matching snippets to keep as evidence for each CSS check.
+const MAX_EVIDENCE: usize = 3;
+
+impl CssCheck {
+    fn patterns(&self) -> Result<CssPatterns, String> {
+        let compile = |pattern: &Option<String>, whole: bool| -> Result<Option<Regex>, String> {
+            let Some(pattern) = pattern else {
+                return Ok(None);
+            };
+            let source = if whole {
+                format!("^(?:{})$", pattern)
+            } else {
+                pattern.clone()
+            };
+            RegexBuilder::new(&source)
+                .case_insensitive(true)
+                .build()
+                .map(Some)
+                .map_err(|e| format!("invalid pattern “{}”: {}", pattern, e))
+        };
+
+        Ok(CssPatterns {
+            selector: compile(&self.selector, false)?,
+            property: compile(&self.property, true)?,
+            value: compile(&self.value, false)?,
+            media: compile(&self.media, false)?,
+        })
+    }
+
+    /// Function to run the check on a deliverable's styles, returning evidence snippets or why it failed.
+    fn evaluate(&self, styles: &StyleIndex) -> Result<Vec<String>, String> {
+        let patterns = self.patterns()?;
+        let kind = if self.property.is_some() || self.value.is_some() {
+            StyleEntryKind::Declaration
+        } else if self.selector.is_some() {
+            StyleEntryKind::Rule
+        } else {
+            StyleEntryKind::AtRule
+        };
+
+        let matches: Vec<&StyleEntry> = styles
+            .entries
+            .iter()
+            .filter(|entry| entry.kind == kind)
+            .filter(|entry| self.matches(entry, &patterns))
+            .collect();
+
+        let description = self.describe();
+        let found = matches.len();
+
+        check_count(found, self.count, self.min, self.max, &description)?;
+
+        Ok(matches
+            .iter()
+            .take(MAX_EVIDENCE)
+            .map(|entry| entry.snippet())
+            .collect())
+    }
+
+    fn matches(&self, entry: &StyleEntry, patterns: &CssPatterns) -> bool {
+        let is_match = |pattern: &Option<Regex>, text: &Option<String>| match pattern {
+            Some(pattern) => text.as_deref().is_some_and(|text| pattern.is_match(text)),
+            None => true,
+        };
+        let media_queries = || {
+            entry
+                .at_rules
+                .iter()
+                .filter(|at_rule| at_rule.name == "media")
+                .map(|at_rule| at_rule.prelude.as_str())
+        };
+
+        is_match(&patterns.selector, &entry.selector)
+            && is_match(&patterns.property, &entry.property)
+            && is_match(&patterns.value, &entry.value)
+            && self.at_rule.as_ref().is_none_or(|name| {
+                entry
+                    .at_rules
+                    .iter()
+                    .any(|at_rule| at_rule.name.eq_ignore_ascii_case(name))
+            })
+            && patterns
+                .media
+                .as_ref()
+                .is_none_or(|media| media_queries().any(|query| media.is_match(query)))
+            && self.max_width.is_none_or(|limit| {
+                media_queries()
+                    .filter_map(max_width)
+                    .any(|width| width <= limit)
+            })
+    }
+
+    /// Function to describe what the check is looking for, for failure messages.
+    fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(selector) = &self.selector {
+            parts.push(format!("selector “{}”", selector));
+        }
+        if let Some(property) = &self.property {
+            parts.push(format!("property “{}”", property));
+        }
+        if let Some(value) = &self.value {
+            parts.push(format!("value “{}”", value));
+        }
+        if let Some(at_rule) = &self.at_rule {
+            parts.push(format!("inside @{}", at_rule));
+        }
+        if let Some(media) = &self.media {
+            parts.push(format!("inside @media matching “{}”", media));
+        }
+        if let Some(limit) = self.max_width {
+            parts.push(format!("inside @media for widths up to {}px", limit));
+        }
+
+        let kind = if self.property.is_some() || self.value.is_some() {
+            "CSS declaration"
+        } else if self.selector.is_some() {
+            "CSS rule"
+        } else {
+            "CSS at-rule"
+        };
+        if parts.is_empty() {
+            kind.to_string()
+        } else {
+            format!("{} with {}", kind, parts.join(", "))
+        }
+    }
+}
+
+/// Function to find the largest viewport width, in pixels, a media query applies to, e.g. 600 for
+/// `(max-width: 600px)` or `(width <= 37.5em)`.
+fn max_width(query: &str) -> Option<f64> {
+    let pattern = Regex::new(
+        r"(?i)(?:max-width\s*:|\bwidth\s*<=?)\s*([\d.]+)(px|em|rem)|([\d.]+)(px|em|rem)\s*>=?\s*width\b",
+    )
+    .unwrap();
+
+    pattern
+        .captures_iter(query)
+        .filter_map(|captures| {
+            let number = captures
+                .get(1)
+                .or(captures.get(3))?
+                .as_str()
+                .parse::<f64>()
+                .ok()?;
+            let unit = captures.get(2).or(captures.get(4))?.as_str().to_lowercase();
+            Some(if unit == "px" { number } else { number * 16.0 })
+        })
+        .reduce(f64::min)
+}
+
+/// Function to check the number of matches against the `count`, `min` and `max` of a check.
+/// At least one match is required unless `count` or `max` is given.
+fn check_count(
+    found: usize,
+    count: Option<usize>,
+    min: Option<usize>,
+    max: Option<usize>,
+    description: &str,
+) -> Result<(), String> {
+    if let Some(count) = count {
+        if found != count {
+            return Err(format!(
+                "expected {} {}, found {}",
+                count, description, found
+            ));
+        }
+    }
+    let min = min.unwrap_or(if count.is_some() || max.is_some() {
+        0
+    } else {
+        1
+    });
+    if found < min {
+        return Err(format!(
+            "expected at least {} {}, found {}",
+            min, description, found
+        ));
+    }
+    if let Some(max) = max {
+        if found > max {
+            return Err(format!(
+                "expected at most {} {}, found {}",
+                max, description, found
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Function to find the position of an element in document order.
+fn document_position(document: &Html, element: &ElementRef) -> usize {
+    document
+        .tree
+        .root()
+        .descendants()
+        .position(|node| node.id() == element.id())
+        .unwrap_or(usize::MAX)
+}
+
+/// The outcome of a single rule for a student.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RuleResult {
+    pub name: String,
+    pub points: f64,
+    pub max_points: f64,
+    pub passed: bool,
+    /// The file that satisfied the rule.
+    pub file: Option<PathBuf>,
+    /// Why the rule failed, per file that was tried.
+    pub failures: Vec<String>,
+    /// Snippets of CSS that satisfied the rule's CSS checks.
+    #[serde(default)]
+    pub evidence: Vec<String>,
+}
+
+/// The scored requirement checklist for a student, stored as `checklist.json` and `checklist.md` in the student directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Checklist {
+    pub results: Vec<RuleResult>,
+}
+
+impl Checklist {
+    pub fn points(&self) -> f64 {
+        self.results.iter().map(|result| result.points).sum()
+    }
+
+    pub fn max_points(&self) -> f64 {
+        self.results.iter().map(|result| result.max_points).sum()
+    }
+
+    /// Function to read the checklist of a student, if the requirements have been checked.
+    pub fn read(project_dir: &Path) -> Option<Checklist> {
+        let checklist = fs::read_to_string(project_dir.join("checklist.json")).ok()?;
+        serde_json::from_str(&checklist).ok()
+    }
+
+    pub fn write(
+        &self,
+        project_dir: &Path,
+        phrases: &Phrases,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(
+            project_dir.join("checklist.json"),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        fs::write(project_dir.join("checklist.md"), self.to_markdown(phrases))?;
+        println!(
+            "> Requirements: {} of {} points",
+            self.points(),
+            self.max_points()
+        );
+        Ok(())
+    }
+
+    /// Function to format the checklist as Markdown, which is also used in the grading prompt.
+    pub fn to_markdown(&self, phrases: &Phrases) -> String {
+        let mut output = String::new();
+        for result in &self.results {
+            let mark = if result.passed { "x" } else { " " };
+            output.push_str(&format!(
+                "- [{}] {} ({} {} {} {})\n",
+                mark, result.name, result.points, phrases.of, result.max_points, phrases.points
+            ));
+            for failure in &result.failures {
+                output.push_str(&format!("  - {}\n", failure));
+            }
+            for snippet in &result.evidence {
+                output.push_str(&format!("  - `{}`\n", snippet));
+            }
+        }
+        output.push_str(&format!(
+            "\n{}: {} {} {} {}\n",
+            phrases.total,
+            self.points(),
+            phrases.of,
+            self.max_points(),
+            phrases.points
+        ));
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::VendorConfig;
+    use crate::starter::StarterCode;
+
+    const RULES: &str = r#"
+        [[rule]]
+        name = "Uses flexbox or grid for the layout"
+        points = 2
+
+        [[rule.css]]
+        property = "display"
+        value = "flex|grid"
+    "#;
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html lang="no">
+<body>
+  <nav><a href="index.html">Hjem</a> <a href="kontakt.html">Kontakt</a></nav>
+  <h2>Vanlige  spørsmål</h2>
+  <section>
+    <table>
+      <tr><td>1</td></tr>
+      <tr><td>2</td></tr>
+      <tr><td>3</td></tr>
+    </table>
+  </section>
+  <footer>Kontakt oss</footer>
+</body>
+</html>"#;
+
+    #[test]
+    fn html_checks_count_compare_and_order_elements() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.html"), PAGE).unwrap();
+        fs::write(dir.path().join("om.html"), "<h1>Om oss</h1>").unwrap();
+
+        let rules: RuleSet = toml::from_str(
+            r#"
+            [[rule]]
+            name = "A table with 3 rows inside a section, above the footer"
+            points = 5
+
+            [[rule.check]]
+            selector = "section table tr"
+            count = 3
+
+            [[rule.check]]
+            selector = "section table"
+            before = "footer"
+
+            [[rule]]
+            name = "An h2 containing 'spørsmål'"
+            points = 2
+
+            [[rule.check]]
+            selector = "h2"
+            text = "vanlige SPØRSMÅL"
+
+            [[rule]]
+            name = "A link to the contact page"
+            points = 1
+
+            [[rule.check]]
+            selector = "nav a"
+            attribute = "href"
+            value = "contact.html"
+
+            [[rule]]
+            name = "A footer above the table"
+            points = 1
+
+            [[rule.check]]
+            selector = "footer"
+            before = "table"
+
+            [[rule]]
+            name = "A heading on the about page"
+            points = 1
+            file = "om.html"
+
+            [[rule.check]]
+            selector = "h1"
+            max = 0
+            "#,
+        )
+        .unwrap();
+        let vendor = VendorDetector::new(&VendorConfig::default()).unwrap();
+        let checklist = rules.evaluate(dir.path(), &vendor, None);
+
+        let passed: Vec<bool> = checklist.results.iter().map(|r| r.passed).collect();
+        assert_eq!(passed, [true, true, false, false, false]);
+        assert_eq!(checklist.points(), 7.0);
+        assert_eq!(checklist.max_points(), 10.0);
+        assert_eq!(
+            checklist.results[0].file.as_deref(),
+            Some(Path::new("index.html"))
+        );
+        assert_eq!(
+            checklist.results[2].failures,
+            [
+                "index.html: expected at least 1 “nav a” with href=\"contact.html\", found 0",
+                "om.html: expected at least 1 “nav a” with href=\"contact.html\", found 0",
+            ]
+        );
+        assert_eq!(
+            checklist.results[3].failures[0],
+            "index.html: “footer” is not before “table”"
+        );
+        assert_eq!(
+            checklist.results[4].failures,
+            ["om.html: expected at most 0 “h1”, found 1"]
+        );
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected_when_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rules.toml");
+        let load = |rules: &str| {
+            fs::write(&path, rules).unwrap();
+            RuleSet::load(&path).map_err(|e| e.to_string())
+        };
+
+        let no_checks = load("[[rule]]\nname = \"Tom\"\npoints = 1\n").unwrap_err();
+        assert!(
+            no_checks.contains("it has no HTML or CSS checks"),
+            "{}",
+            no_checks
+        );
+
+        let selector =
+            load("[[rule]]\nname = \"Feil\"\npoints = 1\n[[rule.check]]\nselector = \"nav >\"\n")
+                .unwrap_err();
+        assert!(
+            selector.contains("invalid selector “nav >”"),
+            "{}",
+            selector
+        );
+
+        let value = load(
+            "[[rule]]\nname = \"Lenke\"\npoints = 1\n[[rule.check]]\nselector = \"a\"\nvalue = \"x\"\n",
+        )
+        .unwrap_err();
+        assert!(value.contains("“value” requires “attribute”"), "{}", value);
+
+        assert!(load(RULES).is_ok());
+    }
+
+    #[test]
+    fn css_checks_match_declarations_rules_and_at_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("style.css"),
+            ":root {\n  --hovedfarge: #336;\n}\nnav a:hover {\n  color: var(--hovedfarge);\n}\n@media (width <= 35em) {\n  nav {\n    flex-direction: column;\n  }\n}\n",
+        )
+        .unwrap();
+
+        let rules: RuleSet = toml::from_str(
+            r#"
+            [[rule]]
+            name = "Has a media query below 600px"
+            points = 2
+
+            [[rule.css]]
+            max_width = 600
+
+            [[rule]]
+            name = "Defines a hover effect on nav links"
+            points = 1
+
+            [[rule.css]]
+            selector = "nav.*a:hover"
+
+            [[rule]]
+            name = "Uses one custom property"
+            points = 1
+
+            [[rule.css]]
+            property = "--.*"
+            count = 1
+
+            [[rule]]
+            name = "Changes the layout in a media query"
+            points = 1
+
+            [[rule.css]]
+            property = "display"
+            at_rule = "media"
+
+            [[rule]]
+            name = "Has a media query below 500px"
+            points = 1
+
+            [[rule.css]]
+            max_width = 500
+            "#,
+        )
+        .unwrap();
+        let vendor = VendorDetector::new(&VendorConfig::default()).unwrap();
+        let checklist = rules.evaluate(dir.path(), &vendor, None);
+
+        let passed: Vec<bool> = checklist.results.iter().map(|r| r.passed).collect();
+        assert_eq!(passed, [true, true, true, false, false]);
+        assert_eq!(
+            checklist.results[1].evidence,
+            ["style.css:4: nav a:hover { … }"]
+        );
+        assert_eq!(
+            checklist.results[3].failures,
+            ["expected at least 1 CSS declaration with property “display”, inside @media, found 0"]
+        );
+        assert_eq!(max_width("(max-width: 600px)"), Some(600.0));
+        assert_eq!(max_width("screen and (37.5em >= width)"), Some(600.0));
+        assert_eq!(max_width("(min-width: 600px)"), None);
+    }
+
+    #[test]
+    fn libraries_and_unchanged_starter_code_do_not_pass_css_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let starter_dir = dir.path().join("starter");
+        let student_dir = dir.path().join("ola");
+        for project in [&starter_dir, &student_dir] {
+            fs::create_dir_all(project.join("css")).unwrap();
+            fs::write(project.join("index.html"), "<h1>Oppskrifter</h1>").unwrap();
+            fs::write(
+                project.join("css/style.css"),
+                "body {\n  margin: 0;\n}\n.kort {\n  display: flex;\n}\n",
+            )
+            .unwrap();
+        }
+        fs::write(
+            student_dir.join("css/bootstrap.min.css"),
+            ".d-flex{display:flex!important}",
+        )
+        .unwrap();
+
+        let rules: RuleSet = toml::from_str(RULES).unwrap();
+        let vendor = VendorDetector::new(&VendorConfig::default()).unwrap();
+        let starter = StarterCode::load(&starter_dir).unwrap();
+        let evaluate = || {
+            let diff = starter.diff(&student_dir).unwrap();
+            rules
+                .evaluate(&student_dir, &vendor, Some(&diff))
+                .results
+                .remove(0)
+        };
+        let result = evaluate();
+        assert!(!result.passed, "{:?}", result);
+
+        // Once the student changes the file, its styles count
+        fs::write(
+            student_dir.join("css/style.css"),
+            "body {\n  margin: 0;\n}\n.kort {\n  display: grid;\n}\n",
+        )
+        .unwrap();
+        let result = evaluate();
+        assert!(result.passed, "{:?}", result);
+        assert_eq!(
+            result.evidence,
+            ["css/style.css:4: .kort { display: grid }"]
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use crate::assessment::Assessment;
+use crate::prompts::Phrases;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// A grading rubric, read from TOML, YAML or JSON, e.g.
+///
+/// ```toml
+/// title = "Assignment 1"
+///
+/// [[section]]
+/// name = "HTML"
+///
+/// [[section.criterion]]
+/// name = "Is the table created correctly?"
+/// points = 6
+/// description = "The table has a caption, a header row and one row per day"
+/// examples = ["<th scope=\"col\">Monday</th>"]
+/// ```
+///
+/// Criteria that don't belong to a section can be listed at the top level with `[[criterion]]`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rubric {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(
+        default,
+        rename = "criterion",
+        alias = "criteria",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub criteria: Vec<Criterion>,
+    #[serde(
+        default,
+        rename = "section",
+        alias = "sections",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub sections: Vec<Section>,
+}
+
+/// A group of related criteria.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Section {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, rename = "criterion", alias = "criteria")]
+    pub criteria: Vec<Criterion>,
+}
+
+/// A single criterion, worth a number of points.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Criterion {
+    pub name: String,
+    pub points: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Examples of what satisfies the criterion, or what doesn't.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<String>,
+}
+
+impl Rubric {
+    /// Function to check whether a file is a rubric, rather than a criteria PDF, by its extension.
+    pub fn is_rubric_file(path: &Path) -> bool {
+        matches!(
+            path.extension().and_then(|s| s.to_str()),
+            Some("toml" | "yaml" | "yml" | "json")
+        )
+    }
+
+    pub fn load(path: &Path) -> Result<Rubric, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read rubric {}: {}", path.display(), e))?;
+        let invalid = |e: String| format!("Invalid rubric {}: {}", path.display(), e);
+        let rubric: Rubric = match path.extension().and_then(|s| s.to_str()) {
+            Some("yaml" | "yml") => {
+                serde_yaml::from_str(&contents).map_err(|e| invalid(e.to_string()))?
+            }
+            Some("json") => serde_json::from_str(&contents).map_err(|e| invalid(e.to_string()))?,
+            _ => toml::from_str(&contents).map_err(|e| invalid(e.to_string()))?,
+        };
+
+        if rubric.all_criteria().next().is_none() {
+            return Err(invalid("The rubric has no criteria".to_string()).into());
+        }
+        let mut names = HashSet::new();
+        for (_, criterion) in rubric.all_criteria() {
+            if criterion.points <= 0.0 {
+                return Err(invalid(format!(
+                    "“{}” must be worth more than 0 points",
+                    criterion.name
+                ))
+                .into());
+            }
+            // Scores are matched to criteria by name
+            if !names.insert(criterion.name.as_str()) {
+                return Err(invalid(format!("“{}” is listed twice", criterion.name)).into());
+            }
+        }
+
+        Ok(rubric)
+    }
+
+    /// Function to list every criterion with the section it belongs to, in order.
+    pub fn all_criteria(&self) -> impl Iterator<Item = (Option<&Section>, &Criterion)> {
+        self.criteria
+            .iter()
+            .map(|criterion| (None, criterion))
+            .chain(self.sections.iter().flat_map(|section| {
+                section
+                    .criteria
+                    .iter()
+                    .map(move |criterion| (Some(section), criterion))
+            }))
+    }
+
+    pub fn max_points(&self) -> f64 {
+        self.all_criteria()
+            .map(|(_, criterion)| criterion.points)
+            .sum()
+    }
+
+    fn criterion(&self, name: &str) -> Option<&Criterion> {
+        self.all_criteria()
+            .map(|(_, criterion)| criterion)
+            .find(|criterion| criterion.name == name)
+    }
+
+    /// Function to find the section a criterion belongs to, by the name of the criterion.
+    pub fn section_of(&self, name: &str) -> Option<&Section> {
+        self.all_criteria()
+            .find(|(_, criterion)| criterion.name == name)
+            .and_then(|(section, _)| section)
+    }
+
+    /// Function to check a grading against the rubric: every criterion must be scored once, with its maximum points.
+    pub fn violations(&self, assessment: &Assessment, phrases: &Phrases) -> Vec<String> {
+        let mut violations = Vec::new();
+        let mut scored = HashSet::new();
+
+        for score in &assessment.criteria {
+            let about = |phrase: &str| phrase.replace("{criterion}", &score.criterion);
+            match self.criterion(&score.criterion) {
+                None => violations.push(about(phrases.unknown_criterion)),
+                Some(criterion) if criterion.points != score.max_points => violations.push(
+                    about(phrases.wrong_max_points)
+                        .replace("{expected}", &criterion.points.to_string())
+                        .replace("{max_points}", &score.max_points.to_string()),
+                ),
+                Some(_) => {}
+            }
+            if !scored.insert(score.criterion.as_str()) {
+                violations.push(about(phrases.scored_twice));
+            }
+        }
+        for (_, criterion) in self.all_criteria() {
+            if !scored.contains(criterion.name.as_str()) {
+                violations.push(
+                    phrases
+                        .criterion_missing
+                        .replace("{criterion}", &criterion.name),
+                );
+            }
+        }
+
+        violations
+    }
+
+    /// Function to format the rubric as Markdown for the grading prompt.
+    pub fn to_markdown(&self, phrases: &Phrases) -> String {
+        let mut output = String::new();
+        if let Some(title) = &self.title {
+            output.push_str(&format!("# {}\n\n", title));
+        }
+
+        let mut current_section = None;
+        for (section, criterion) in self.all_criteria() {
+            let name = section.map(|section| section.name.as_str());
+            if name != current_section {
+                if let Some(section) = section {
+                    output.push_str(&format!("\n## {}\n\n", section.name));
+                    if let Some(description) = &section.description {
+                        output.push_str(&format!("{}\n\n", description.trim()));
+                    }
+                }
+                current_section = name;
+            }
+
+            output.push_str(&format!(
+                "- {} ({} {})\n",
+                criterion.name, criterion.points, phrases.points
+            ));
+            if let Some(description) = &criterion.description {
+                output.push_str(&format!("  {}\n", description.trim()));
+            }
+            for example in &criterion.examples {
+                output.push_str(&format!("  - {}: {}\n", phrases.example, example.trim()));
+            }
+        }
+
+        output.push_str(&format!(
+            "\n{}: {} {}\n",
+            phrases.total,
+            self.max_points(),
+            phrases.points
+        ));
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompts::Language;
+
+    fn criterion(name: &str, points: f64) -> Criterion {
+        Criterion {
+            name: name.to_string(),
+            points,
+            description: None,
+            examples: Vec::new(),
+        }
+    }
+
+    fn rubric() -> Rubric {
+        Rubric {
+            title: None,
+            criteria: vec![criterion("Gyldig kode", 2.0)],
+            sections: vec![Section {
+                name: "HTML".to_string(),
+                description: None,
+                criteria: vec![criterion("Tabell", 6.0), criterion("Skjema", 4.0)],
+            }],
+        }
+    }
+
+    fn assessment(scores: &[(&str, f64)]) -> Assessment {
+        let criteria: Vec<_> = scores
+            .iter()
+            .map(|(name, max_points)| {
+                serde_json::json!({
+                    "criterion": name,
+                    "points": 0,
+                    "max_points": max_points,
+                    "justification": "Mangler.",
+                })
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "criteria": criteria,
+            "total": 0,
+            "max_total": 12,
+            "summary": "",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn every_criterion_is_scored_once_with_its_points() {
+        let rubric = rubric();
+        let phrases = Language::Norwegian.phrases();
+        assert_eq!(rubric.max_points(), 12.0);
+        assert!(rubric
+            .violations(
+                &assessment(&[("Gyldig kode", 2.0), ("Tabell", 6.0), ("Skjema", 4.0)]),
+                phrases
+            )
+            .is_empty());
+
+        let violations = rubric.violations(
+            &assessment(&[("Tabell", 5.0), ("Tabell", 6.0), ("Navigasjon", 2.0)]),
+            phrases,
+        );
+        assert_eq!(
+            violations,
+            [
+                "“Tabell”: max_points skal være 6, ikke 5",
+                "“Tabell” er vurdert mer enn én gang",
+                "“Navigasjon” er ikke et kriterium i vurderingsveiledningen",
+                "“Gyldig kode” mangler i svaret",
+                "“Skjema” mangler i svaret",
+            ]
+        );
+    }
+}
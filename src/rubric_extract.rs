@@ -0,0 +1,337 @@
+use crate::rubric::{Criterion, Rubric, Section};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+
+/// Function to extract a draft rubric from the text of a criteria PDF. Criteria are lines with a point value,
+/// e.g. "Is the table created correctly? 6 points" or "Tabell (0-6 poeng)", or lines ending with a number
+/// in a table with a "Poeng" or "Points" column. Numbered lines and lines like "Del 2" start a new section,
+/// and so do lines with a point value that the criteria below them add up to, e.g. "Del 1: HTML (30 poeng)".
+/// The lines following a criterion are kept as its description.
+pub fn extract_rubric(text: &str) -> Rubric {
+    // The maximum of a range is used, e.g. 6 for "0–6 poeng"
+    let points = Regex::new(
+        r"(?i)[(\[]?\s*(?:maks\.?\s*|max\.?\s*)?(?:\d+(?:[.,]\d+)?\s*[-–]\s*)?(\d+(?:[.,]\d+)?)\s*(?:poeng|points?|pts?\.?|p)\s*[)\]]?\s*$",
+    )
+    .unwrap();
+    let trailing_number = Regex::new(r"^(.*\D)\s+(\d+(?:[.,]\d+)?)$").unwrap();
+    let points_column = Regex::new(r"(?i)\b(poeng|points?)\s*$").unwrap();
+    let section = Regex::new(
+        r"(?i)^(?:\d+(?:\.\d+)*\.?\s+\S|(?:del|oppgave|part|task|section|seksjon)\s+\d+)",
+    )
+    .unwrap();
+    let total = Regex::new(r"(?i)^(?:totalt?|sum|maks(?:imal)?\s+poengsum|max(?:imum)?\s+score)\b")
+        .unwrap();
+
+    let mut rubric = Rubric {
+        title: None,
+        criteria: Vec::new(),
+        sections: Vec::new(),
+    };
+    let mut in_points_table = false;
+    let mut previous_line: Option<&str> = None;
+    // Whether the lines that follow describe the last criterion
+    let mut describing = false;
+
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    for (index, &line) in lines.iter().enumerate() {
+        if line.is_empty() {
+            describing = false;
+            continue;
+        }
+
+        if total.is_match(line) {
+            describing = false;
+            previous_line = None;
+            continue;
+        }
+
+        let criterion = if let Some(captures) = points.captures(line) {
+            let name = line[..captures.get(0).unwrap().start()].to_string();
+            let value = parse_number(&captures[1]);
+            if is_section_total(value, &lines[index..], &points, &total) {
+                rubric.sections.push(Section {
+                    name: clean_name(&name),
+                    description: None,
+                    criteria: Vec::new(),
+                });
+                describing = false;
+                previous_line = None;
+                continue;
+            }
+            Some((name, value))
+        } else if in_points_table {
+            trailing_number
+                .captures(line)
+                // "Del 2" is a section, not a criterion worth 2 points
+                .filter(|captures| !section.is_match(line) || captures[1].contains(' '))
+                .map(|captures| (captures[1].to_string(), parse_number(&captures[2])))
+        } else {
+            None
+        };
+
+        if let Some((name, points)) = criterion {
+            let mut name = clean_name(&name);
+            // The point value is sometimes on a line of its own, below the criterion
+            if name.is_empty() {
+                name = previous_line.map(clean_name).unwrap_or_default();
+                remove_description(&mut rubric, &name);
+            }
+            if name.is_empty() || points <= 0.0 {
+                continue;
+            }
+
+            current_criteria(&mut rubric).push(Criterion {
+                name,
+                points,
+                description: None,
+                examples: Vec::new(),
+            });
+            describing = true;
+            previous_line = None;
+            continue;
+        }
+
+        if points_column.is_match(line) {
+            in_points_table = true;
+            describing = false;
+            previous_line = None;
+            continue;
+        }
+
+        if section.is_match(line) && line.len() < 80 {
+            rubric.sections.push(Section {
+                name: clean_name(line),
+                description: None,
+                criteria: Vec::new(),
+            });
+            describing = false;
+            previous_line = None;
+            continue;
+        }
+
+        if rubric.title.is_none() && rubric.criteria.is_empty() && rubric.sections.is_empty() {
+            rubric.title = Some(line.to_string());
+            continue;
+        }
+
+        if describing {
+            if let Some(criterion) = current_criteria(&mut rubric).last_mut() {
+                let description = criterion.description.get_or_insert_with(String::new);
+                if !description.is_empty() {
+                    description.push(' ');
+                }
+                description.push_str(line);
+            }
+        }
+        previous_line = Some(line);
+    }
+
+    // Sections without criteria are usually headings of the document, rather than of the rubric
+    rubric
+        .sections
+        .retain(|section| !section.criteria.is_empty());
+    rubric
+}
+
+/// Function to write a draft rubric as TOML, YAML or JSON, by the extension of the file.
+/// An existing file isn't overwritten, since it may have been reviewed already.
+pub fn write_draft(
+    rubric: &Rubric,
+    source: &Path,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()).into());
+    }
+
+    let contents = match path.extension().and_then(|s| s.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::to_string(rubric)?,
+        Some("json") => serde_json::to_string_pretty(rubric)?,
+        _ => format!(
+            "# Draft rubric extracted from {}.\n# Review the criteria and points before using it for grading.\n\n{}",
+            source.display(),
+            toml::to_string(rubric)?
+        ),
+    };
+    fs::write(path, contents)?;
+
+    let criteria = rubric.all_criteria().count();
+    if criteria == 0 {
+        eprintln!(
+            "> No criteria with point values were found in {}. Add them to {} by hand",
+            source.display(),
+            path.display()
+        );
+    } else {
+        println!(
+            "> Wrote draft rubric with {} criteria and {} points in total to {:?}",
+            criteria,
+            rubric.max_points(),
+            path
+        );
+    }
+
+    Ok(())
+}
+
+/// Function to check whether the first of `lines`, worth `value` points, is the heading of the criteria below it
+/// rather than a criterion of its own: at least two of them add up to its points, before the next heading
+/// at the same level or above.
+fn is_section_total(value: f64, lines: &[&str], points: &Regex, total: &Regex) -> bool {
+    let level = heading_level(lines[0]);
+    let mut sum = 0.0;
+    let mut criteria = 0;
+    for line in &lines[1..] {
+        let ends_section =
+            heading_level(line).is_some_and(|other| level.is_none_or(|level| other <= level));
+        if total.is_match(line) || ends_section {
+            break;
+        }
+        if let Some(captures) = points.captures(line) {
+            sum += parse_number(&captures[1]);
+            criteria += 1;
+            if sum >= value - 1e-9 {
+                return criteria >= 2 && (sum - value).abs() < 1e-9;
+            }
+        }
+    }
+    false
+}
+
+/// Function to find the level of a heading: 0 for "Del 2" or "Oppgave 2", and the depth of the numbering
+/// for numbered lines, e.g. 2 for "1.2 Tabell".
+fn heading_level(line: &str) -> Option<usize> {
+    let named = Regex::new(r"(?i)^(?:del|oppgave|part|task|section|seksjon)\s+\d+").unwrap();
+    let numbered = Regex::new(r"^(\d+(?:\.\d+)*)\.?\s+\S").unwrap();
+    if named.is_match(line) {
+        return Some(0);
+    }
+    numbered
+        .captures(line)
+        .map(|captures| captures[1].split('.').count())
+}
+
+/// The criteria that new criteria are added to: those of the last section, or the top level.
+fn current_criteria(rubric: &mut Rubric) -> &mut Vec<Criterion> {
+    match rubric.sections.last_mut() {
+        Some(section) => &mut section.criteria,
+        None => &mut rubric.criteria,
+    }
+}
+
+/// Function to remove a line from the description of the last criterion, when it turns out to be the name of the next.
+fn remove_description(rubric: &mut Rubric, line: &str) {
+    if let Some(criterion) = current_criteria(rubric).last_mut() {
+        if let Some(description) = &mut criterion.description {
+            if let Some(stripped) = description.strip_suffix(line) {
+                *description = stripped.trim_end().to_string();
+            }
+            if description.is_empty() {
+                criterion.description = None;
+            }
+        }
+    }
+}
+
+fn clean_name(name: &str) -> String {
+    name.trim_matches(|c: char| {
+        c.is_whitespace() || matches!(c, '(' | '[' | ':' | '-' | '–' | '|' | '.' | ',')
+    })
+    .to_string()
+}
+
+fn parse_number(number: &str) -> f64 {
+    number.replace(',', ".").parse().unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn section_headings_with_points_are_not_criteria() {
+        let rubric = extract_rubric(
+            "Vurderingsveiledning oblig 1\n\
+             Del 1: HTML (30 poeng)\n\
+             Semantiske elementer (0-20 poeng)\n\
+             Bruker header, nav, main og footer.\n\
+             Tabell 10 poeng\n\
+             Del 2: CSS (10 poeng)\n\
+             Flexbox (5 poeng)\n\
+             Responsivt (5 p)\n\
+             Totalt 40 poeng\n",
+        );
+
+        assert_eq!(
+            rubric.title.as_deref(),
+            Some("Vurderingsveiledning oblig 1")
+        );
+        assert_eq!(rubric.max_points(), 40.0);
+        let sections: Vec<(&str, usize)> = rubric
+            .sections
+            .iter()
+            .map(|section| (section.name.as_str(), section.criteria.len()))
+            .collect();
+        assert_eq!(sections, [("Del 1: HTML", 2), ("Del 2: CSS", 2)]);
+
+        let semantic = &rubric.sections[0].criteria[0];
+        assert_eq!(semantic.name, "Semantiske elementer");
+        assert_eq!(semantic.points, 20.0);
+        assert_eq!(
+            semantic.description.as_deref(),
+            Some("Bruker header, nav, main og footer.")
+        );
+    }
+
+    #[test]
+    fn numbered_headings_contain_the_deeper_numbered_criteria() {
+        let rubric = extract_rubric(
+            "1. HTML (10 poeng)\n\
+             1.1 Tabell (6 poeng)\n\
+             1.2 Skjema (4 poeng)\n\
+             2. CSS (6 poeng)\n\
+             2.1 Flexbox (3 poeng)\n\
+             2.2 Media queries (3 poeng)\n",
+        );
+        assert_eq!(rubric.max_points(), 16.0);
+        assert_eq!(rubric.sections.len(), 2);
+        assert_eq!(rubric.sections[1].name, "2. CSS");
+        assert_eq!(rubric.sections[1].criteria[1].name, "2.2 Media queries");
+    }
+
+    #[test]
+    fn numbered_criteria_are_kept() {
+        let rubric = extract_rubric(
+            "1. Lag en tabell (6 poeng)\n\
+             2. Lag et skjema (4 poeng)\n\
+             3. Style siden (10 poeng)\n",
+        );
+        let criteria: Vec<(&str, f64)> = rubric
+            .all_criteria()
+            .map(|(_, criterion)| (criterion.name.as_str(), criterion.points))
+            .collect();
+        assert_eq!(
+            criteria,
+            [
+                ("1. Lag en tabell", 6.0),
+                ("2. Lag et skjema", 4.0),
+                ("3. Style siden", 10.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn points_tables_are_read() {
+        let rubric = extract_rubric(
+            "Kriterium Poeng\n\
+             Gyldig HTML 5\n\
+             Del 2\n\
+             Navigasjon mellom sidene 3\n",
+        );
+        assert_eq!(rubric.sections.len(), 1);
+        assert_eq!(rubric.sections[0].name, "Del 2");
+        assert_eq!(rubric.criteria[0].name, "Gyldig HTML");
+        assert_eq!(rubric.max_points(), 8.0);
+    }
+}
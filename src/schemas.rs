@@ -22,3 +22,27 @@ pub struct Message {
     pub hiliteStart: Option<u32>,
     pub hiliteLength: Option<u32>,
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CssValidationResponse {
+    pub cssvalidation: CssValidation,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CssValidation {
+    pub validity: Option<bool>,
+    #[serde(default)]
+    pub errors: Vec<CssMessage>,
+    #[serde(default)]
+    pub warnings: Vec<CssMessage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CssMessage {
+    pub line: Option<u32>,
+    pub context: Option<String>,
+    #[serde(rename = "type")]
+    pub message_type: Option<String>,
+    pub message: String,
+    pub level: Option<u32>,
+}
@@ -0,0 +1,780 @@
+use crate::config::SimilarityConfig;
+use crate::encoding;
+use crate::starter::{format_ranges, LineRange, StarterCode};
+use crate::vendor::VendorDetector;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// How many matched regions to show code from, per pair.
+const EXCERPT_REGIONS: usize = 3;
+/// How many lines to show from each side of a matched region.
+const EXCERPT_LINES: usize = 20;
+
+/// Keywords and well-known globals, which are kept when normalizing JS identifiers.
+const JS_KEYWORDS: &[&str] = &[
+    "async",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "import",
+    "in",
+    "instanceof",
+    "let",
+    "new",
+    "null",
+    "of",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "while",
+    "yield",
+    "document",
+    "window",
+    "console",
+    "localStorage",
+    "Math",
+    "JSON",
+    "Array",
+    "Object",
+    "String",
+    "Number",
+    "Date",
+    "Promise",
+    "fetch",
+];
+
+/// The placeholder every renamed identifier is normalized to.
+const IDENTIFIER: &str = "$id";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Language {
+    Html,
+    Css,
+    Js,
+}
+
+/// A normalized token, and the line it starts on.
+struct Token {
+    text: String,
+    line: u32,
+}
+
+/// A winnowed fingerprint: the hash of `token_length` consecutive tokens, and where they are.
+#[derive(Debug, Clone, Copy)]
+struct Fingerprint {
+    hash: u64,
+    file: usize,
+    /// Index of the first token.
+    token: usize,
+    first_line: u32,
+    last_line: u32,
+}
+
+/// The fingerprints of a single deliverable.
+struct Deliverable {
+    name: String,
+    /// Paths of the fingerprinted files, relative to the student directory.
+    files: Vec<PathBuf>,
+    fingerprints: Vec<Fingerprint>,
+    /// The distinct hashes of the fingerprints.
+    hashes: HashSet<u64>,
+}
+
+/// A region of code that two deliverables have in common.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchedRegion {
+    pub first_file: PathBuf,
+    pub first_lines: LineRange,
+    pub second_file: PathBuf,
+    pub second_lines: LineRange,
+    /// How many fingerprints the region consists of.
+    pub fingerprints: usize,
+}
+
+/// Two deliverables that are suspiciously similar.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimilarPair {
+    pub first: String,
+    pub second: String,
+    /// The larger of the two shares below, used for ranking.
+    pub similarity: f64,
+    /// The share of the first deliverable's fingerprints that are found in the second.
+    pub first_share: f64,
+    /// The share of the second deliverable's fingerprints that are found in the first.
+    pub second_share: f64,
+    pub shared_fingerprints: usize,
+    /// The matched regions, largest first.
+    pub regions: Vec<MatchedRegion>,
+}
+
+/// The similarity analysis of a class, stored as `similarity.json` and `similarity.md` in the destination directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SimilarityReport {
+    /// The deliverables that were compared.
+    pub deliverables: usize,
+    /// Library files that were left out, relative to the deliverables directory.
+    pub excluded: Vec<PathBuf>,
+    /// The suspicious pairs, most similar first.
+    pub pairs: Vec<SimilarPair>,
+}
+
+/// Function to compare the HTML, CSS and JS of every deliverable with each other, and write the ranked pairs.
+pub fn write_similarity_report(
+    destination_dir: &Path,
+    student_dirs: &[PathBuf],
+    starter: Option<&StarterCode>,
+    vendor: &VendorDetector,
+    config: &SimilarityConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if student_dirs.len() < 2 {
+        return Ok(());
+    }
+
+    println!("> Comparing deliverables for similarity");
+    let report = analyse(student_dirs, starter, vendor, config)?;
+
+    fs::write(
+        destination_dir.join("similarity.json"),
+        serde_json::to_string_pretty(&report)?,
+    )?;
+    fs::write(
+        destination_dir.join("similarity.md"),
+        report.to_markdown(&destination_dir.join("deliverables")),
+    )?;
+
+    println!(
+        "> Wrote similarity report with {} suspicious pairs to {:?}",
+        report.pairs.len(),
+        destination_dir.join("similarity.md")
+    );
+
+    Ok(())
+}
+
+fn analyse(
+    student_dirs: &[PathBuf],
+    starter: Option<&StarterCode>,
+    vendor: &VendorDetector,
+    config: &SimilarityConfig,
+) -> Result<SimilarityReport, Box<dyn std::error::Error>> {
+    // Code from the starter is shared by everyone, and doesn't count as a match
+    let mut starter_hashes = HashSet::new();
+    if let Some(starter) = starter {
+        for (path, content) in starter.files() {
+            if let Some(language) = language(path) {
+                let tokens = tokenize(content, language);
+                starter_hashes.extend(winnow(&tokens, 0, config).iter().map(|f| f.hash));
+            }
+        }
+    }
+
+    let mut excluded = Vec::new();
+    let mut deliverables = Vec::new();
+    for student_dir in student_dirs {
+        let name = student_dir
+            .file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let mut files = Vec::new();
+        let mut fingerprints = Vec::new();
+        for entry in WalkDir::new(student_dir).sort_by_file_name() {
+            let entry = entry?;
+            let path = entry.path();
+            let Some(language) = language(path) else {
+                continue;
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let relative_path = path.strip_prefix(student_dir)?.to_path_buf();
+            if vendor.detect(&relative_path, &fs::read(path)?).is_some() {
+                excluded.push(Path::new(&name).join(&relative_path));
+                continue;
+            }
+
+            let content = match encoding::read_to_string(path) {
+                Ok(content) => content,
+                Err(_) => continue,
+            };
+            let tokens = tokenize(&content, language);
+            fingerprints.extend(
+                winnow(&tokens, files.len(), config)
+                    .into_iter()
+                    .filter(|f| !starter_hashes.contains(&f.hash)),
+            );
+            files.push(relative_path);
+        }
+
+        let hashes = fingerprints.iter().map(|f| f.hash).collect();
+        deliverables.push(Deliverable {
+            name,
+            files,
+            fingerprints,
+            hashes,
+        });
+    }
+
+    // Boilerplate that most of the class has in common doesn't say anything about a pair
+    let mut frequency: HashMap<u64, usize> = HashMap::new();
+    for deliverable in &deliverables {
+        for hash in &deliverable.hashes {
+            *frequency.entry(*hash).or_insert(0) += 1;
+        }
+    }
+    let max_frequency = ((deliverables.len() as f64 * config.max_share).floor() as usize).max(2);
+
+    let mut shared: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+    let mut owners: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (index, deliverable) in deliverables.iter().enumerate() {
+        for hash in &deliverable.hashes {
+            if frequency[hash] <= max_frequency {
+                owners.entry(*hash).or_default().push(index);
+            }
+        }
+    }
+    for students in owners.values() {
+        for (i, first) in students.iter().enumerate() {
+            for second in &students[i + 1..] {
+                *shared.entry((*first, *second)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let counted = |deliverable: &Deliverable| {
+        deliverable
+            .hashes
+            .iter()
+            .filter(|hash| frequency[*hash] <= max_frequency)
+            .count()
+            .max(1)
+    };
+
+    let mut pairs = Vec::new();
+    for ((first, second), count) in shared {
+        let (first, second) = (&deliverables[first], &deliverables[second]);
+        let first_share = count as f64 / counted(first) as f64;
+        let second_share = count as f64 / counted(second) as f64;
+        let similarity = first_share.max(second_share);
+        if similarity < config.min_similarity {
+            continue;
+        }
+
+        pairs.push(SimilarPair {
+            first: first.name.clone(),
+            second: second.name.clone(),
+            similarity,
+            first_share,
+            second_share,
+            shared_fingerprints: count,
+            regions: matched_regions(first, second, &owners, config),
+        });
+    }
+    pairs.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+
+    Ok(SimilarityReport {
+        deliverables: deliverables.len(),
+        excluded,
+        pairs,
+    })
+}
+
+/// Function to combine the fingerprints two deliverables have in common into regions of matching code.
+fn matched_regions(
+    first: &Deliverable,
+    second: &Deliverable,
+    owners: &HashMap<u64, Vec<usize>>,
+    config: &SimilarityConfig,
+) -> Vec<MatchedRegion> {
+    let mut in_second: HashMap<u64, Vec<&Fingerprint>> = HashMap::new();
+    for fingerprint in &second.fingerprints {
+        if first.hashes.contains(&fingerprint.hash) && owners.contains_key(&fingerprint.hash) {
+            in_second
+                .entry(fingerprint.hash)
+                .or_default()
+                .push(fingerprint);
+        }
+    }
+
+    let mut matches: Vec<(&Fingerprint, &Fingerprint)> = Vec::new();
+    for fingerprint in &first.fingerprints {
+        for other in in_second.get(&fingerprint.hash).into_iter().flatten() {
+            matches.push((fingerprint, other));
+        }
+    }
+    matches.sort_by_key(|(a, b)| (a.file, b.file, a.token, b.token));
+
+    // Fingerprints are at most a window apart within a match, so anything further apart is a new region
+    let gap = config.token_length + config.window;
+    let mut regions: Vec<(MatchedRegion, usize, usize)> = Vec::new();
+    for (a, b) in matches {
+        // Repeated code also matches elsewhere in the other file, so any region this continues is extended,
+        // not only the last one
+        let continued = regions.iter_mut().rev().find(|(region, last_a, last_b)| {
+            region.first_file == first.files[a.file]
+                && region.second_file == second.files[b.file]
+                && a.token <= *last_a + gap
+                && b.token.abs_diff(*last_b) <= gap
+        });
+        if let Some((region, last_a, last_b)) = continued {
+            region.first_lines.start = region.first_lines.start.min(a.first_line);
+            region.first_lines.end = region.first_lines.end.max(a.last_line);
+            region.second_lines.start = region.second_lines.start.min(b.first_line);
+            region.second_lines.end = region.second_lines.end.max(b.last_line);
+            region.fingerprints += 1;
+            *last_a = a.token;
+            *last_b = b.token;
+            continue;
+        }
+        regions.push((
+            MatchedRegion {
+                first_file: first.files[a.file].clone(),
+                first_lines: LineRange {
+                    start: a.first_line,
+                    end: a.last_line,
+                },
+                second_file: second.files[b.file].clone(),
+                second_lines: LineRange {
+                    start: b.first_line,
+                    end: b.last_line,
+                },
+                fingerprints: 1,
+            },
+            a.token,
+            b.token,
+        ));
+    }
+
+    let mut regions: Vec<MatchedRegion> = regions.into_iter().map(|(region, ..)| region).collect();
+    regions.sort_by_key(|region| std::cmp::Reverse(region.fingerprints));
+    regions
+}
+
+/// Function to select fingerprints from the hashes of every `token_length` consecutive tokens, by winnowing:
+/// the smallest hash in each window of `window` hashes is kept.
+fn winnow(tokens: &[Token], file: usize, config: &SimilarityConfig) -> Vec<Fingerprint> {
+    let length = config.token_length.max(1);
+    if tokens.len() < length {
+        return Vec::new();
+    }
+
+    let hashes: Vec<u64> = tokens
+        .windows(length)
+        .map(|gram| {
+            let mut hasher = DefaultHasher::new();
+            for token in gram {
+                token.text.hash(&mut hasher);
+            }
+            hasher.finish()
+        })
+        .collect();
+
+    let window = config.window.max(1).min(hashes.len());
+    let mut fingerprints: Vec<Fingerprint> = Vec::new();
+    for start in 0..=hashes.len() - window {
+        // The rightmost smallest hash, so the same fingerprint is picked by overlapping windows
+        let (offset, hash) = hashes[start..start + window]
+            .iter()
+            .enumerate()
+            .rev()
+            .min_by_key(|(_, hash)| **hash)
+            .unwrap();
+        let token = start + offset;
+        if fingerprints.last().is_some_and(|last| last.token == token) {
+            continue;
+        }
+        fingerprints.push(Fingerprint {
+            hash: *hash,
+            file,
+            token,
+            first_line: tokens[token].line,
+            last_line: tokens[token + length - 1].line,
+        });
+    }
+    fingerprints
+}
+
+/// Function to split code into tokens, leaving out whitespace and comments, and normalizing the names students
+/// choose themselves, so renaming variables or classes doesn't hide copied code.
+fn tokenize(content: &str, language: Language) -> Vec<Token> {
+    let chars: Vec<char> = content.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+
+    let starts_with = |i: usize, pattern: &str| {
+        pattern
+            .chars()
+            .enumerate()
+            .all(|(j, c)| chars.get(i + j) == Some(&c))
+    };
+    // Skips to just after `end`, counting lines on the way
+    let skip_to = |i: &mut usize, line: &mut u32, end: &str| {
+        while *i < chars.len() && !starts_with(*i, end) {
+            if chars[*i] == '\n' {
+                *line += 1;
+            }
+            *i += 1;
+        }
+        *i = (*i + end.chars().count()).min(chars.len());
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start_line = line;
+
+        if c.is_whitespace() {
+            if c == '\n' {
+                line += 1;
+            }
+            i += 1;
+            continue;
+        }
+        if starts_with(i, "/*") {
+            skip_to(&mut i, &mut line, "*/");
+            continue;
+        }
+        if language == Language::Html && starts_with(i, "<!--") {
+            skip_to(&mut i, &mut line, "-->");
+            continue;
+        }
+        if language == Language::Js && starts_with(i, "//") {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+
+        let text = if c == '"' || c == '\'' || c == '`' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' {
+                    i += 1;
+                } else if chars[i] == '\n' {
+                    if c != '`' {
+                        break;
+                    }
+                    line += 1;
+                }
+                i += 1;
+            }
+            i = (i + 1).min(chars.len());
+            let text: String = chars[start..i].iter().collect();
+            normalize_string(text, &tokens, language)
+        } else if c.is_alphanumeric()
+            || c == '_'
+            || c == '$'
+            || c == '-' && language != Language::Js
+        {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric()
+                    || chars[i] == '_'
+                    || chars[i] == '$'
+                    || chars[i] == '-' && language != Language::Js)
+            {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            normalize_word(text, &tokens, language)
+        } else {
+            i += 1;
+            c.to_string()
+        };
+
+        tokens.push(Token {
+            text,
+            line: start_line,
+        });
+    }
+
+    tokens
+}
+
+/// Function to normalize a word: JS identifiers, and CSS class names, ids and custom properties.
+fn normalize_word(word: String, previous: &[Token], language: Language) -> String {
+    let after = |text: &str| previous.last().is_some_and(|token| token.text == text);
+    match language {
+        Language::Js
+            if !JS_KEYWORDS.contains(&word.as_str()) && !word.starts_with(char::is_numeric) =>
+        {
+            IDENTIFIER.to_string()
+        }
+        Language::Css if after(".") || after("#") || word.starts_with("--") => {
+            IDENTIFIER.to_string()
+        }
+        Language::Html => word.to_lowercase(),
+        _ => word,
+    }
+}
+
+/// Function to normalize a string: the values of HTML attributes naming classes, ids and form fields.
+fn normalize_string(string: String, previous: &[Token], language: Language) -> String {
+    let mut last_two = previous
+        .iter()
+        .rev()
+        .take(2)
+        .map(|token| token.text.as_str());
+    let is_name = language == Language::Html
+        && last_two.next() == Some("=")
+        && matches!(last_two.next(), Some("class" | "id" | "for" | "name"));
+    if is_name {
+        IDENTIFIER.to_string()
+    } else {
+        string
+    }
+}
+
+fn language(path: &Path) -> Option<Language> {
+    match path.extension().and_then(|s| s.to_str())? {
+        "html" | "htm" => Some(Language::Html),
+        "css" => Some(Language::Css),
+        "js" => Some(Language::Js),
+        _ => None,
+    }
+}
+
+impl SimilarityReport {
+    /// Function to format the report as Markdown, with excerpts of the largest matched regions of each pair.
+    fn to_markdown(&self, deliverables_dir: &Path) -> String {
+        let mut output = String::from("# Similarity report\n\n");
+        output.push_str(&format!(
+            "{} deliverables compared. Code from the starter and common libraries is not counted. \
+            A high similarity is a reason to take a closer look, not proof of plagiarism.\n\n",
+            self.deliverables
+        ));
+
+        if self.pairs.is_empty() {
+            output.push_str("No suspicious pairs found.\n");
+        } else {
+            output.push_str(
+                "| # | First | Second | Similarity | First → second | Second → first | Regions |\n",
+            );
+            output.push_str("|---|---|---|---|---|---|---|\n");
+            for (rank, pair) in self.pairs.iter().enumerate() {
+                output.push_str(&format!(
+                    "| {} | {} | {} | {:.0} % | {:.0} % | {:.0} % | {} |\n",
+                    rank + 1,
+                    pair.first,
+                    pair.second,
+                    pair.similarity * 100.0,
+                    pair.first_share * 100.0,
+                    pair.second_share * 100.0,
+                    pair.regions.len()
+                ));
+            }
+        }
+
+        for (rank, pair) in self.pairs.iter().enumerate() {
+            output.push_str(&format!(
+                "\n## {}. {} and {} ({:.0} %)\n\n",
+                rank + 1,
+                pair.first,
+                pair.second,
+                pair.similarity * 100.0
+            ));
+            for region in &pair.regions {
+                output.push_str(&format!(
+                    "- `{}` lines {} ↔ `{}` lines {} ({} fingerprints)\n",
+                    region.first_file.display(),
+                    format_ranges(&[region.first_lines]),
+                    region.second_file.display(),
+                    format_ranges(&[region.second_lines]),
+                    region.fingerprints
+                ));
+            }
+
+            for region in pair.regions.iter().take(EXCERPT_REGIONS) {
+                for (student, file, lines) in [
+                    (&pair.first, &region.first_file, region.first_lines),
+                    (&pair.second, &region.second_file, region.second_lines),
+                ] {
+                    let path = deliverables_dir.join(student).join(file);
+                    let extension = file.extension().and_then(|s| s.to_str()).unwrap_or("");
+                    output.push_str(&format!(
+                        "\n{}/{}, lines {}:\n\n```{}\n{}\n```\n",
+                        student,
+                        file.display(),
+                        format_ranges(&[lines]),
+                        extension,
+                        excerpt(&path, lines)
+                    ));
+                }
+            }
+        }
+
+        if !self.excluded.is_empty() {
+            output.push_str("\n## Excluded library files\n\n");
+            for file in &self.excluded {
+                output.push_str(&format!("- `{}`\n", file.display()));
+            }
+        }
+
+        output
+    }
+}
+
+/// Function to read the lines of a matched region, cut off after `EXCERPT_LINES` lines.
+fn excerpt(path: &Path, lines: LineRange) -> String {
+    let content = encoding::read_to_string(path).unwrap_or_default();
+    let length = (lines.end - lines.start + 1) as usize;
+    let mut excerpt: Vec<&str> = content
+        .lines()
+        .skip(lines.start as usize - 1)
+        .take(length.min(EXCERPT_LINES))
+        .collect();
+    if length > EXCERPT_LINES {
+        excerpt.push("…");
+    }
+    excerpt.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::VendorConfig;
+
+    const SCRIPT: &str = "const list = document.querySelector('#list');
+const items = [];
+
+function addItem(text) {
+    const item = document.createElement('li');
+    item.textContent = text;
+    list.appendChild(item);
+    items.push(text);
+    localStorage.setItem('handleliste', JSON.stringify(items));
+}
+
+function removeItem(index) {
+    items.splice(index, 1);
+    list.removeChild(list.children[index]);
+    localStorage.setItem('handleliste', JSON.stringify(items));
+}
+";
+
+    fn deliverable(root: &Path, name: &str, script: &str) -> PathBuf {
+        let student_dir = root.join(name);
+        fs::create_dir_all(student_dir.join("js")).unwrap();
+        fs::write(student_dir.join("js/app.js"), script).unwrap();
+        student_dir
+    }
+
+    #[test]
+    fn copied_code_is_matched_on_the_same_lines_in_both() {
+        let dir = tempfile::tempdir().unwrap();
+        // Renamed, moved down three lines and with a comment, but otherwise copied
+        let copied = format!(
+            "// Handleliste\n\n\n{}",
+            SCRIPT
+                .replace("items", "varer")
+                .replace("addItem", "leggTil")
+        );
+        let own = "let count = 0;\nfor (let i = 0; i < 10; i++) {\n    count += i * 2;\n}\nconsole.log(count);\n";
+        let student_dirs = vec![
+            deliverable(dir.path(), "ola", SCRIPT),
+            deliverable(dir.path(), "kari", &copied),
+            deliverable(dir.path(), "per", own),
+        ];
+
+        let vendor = VendorDetector::new(&VendorConfig::default()).unwrap();
+        let report = analyse(&student_dirs, None, &vendor, &SimilarityConfig::default()).unwrap();
+
+        assert_eq!(report.deliverables, 3);
+        assert_eq!(report.pairs.len(), 1);
+        let pair = &report.pairs[0];
+        assert_eq!((pair.first.as_str(), pair.second.as_str()), ("ola", "kari"));
+        assert_eq!(pair.similarity, 1.0);
+
+        let region = &pair.regions[0];
+        assert_eq!(region.first_file, Path::new("js/app.js"));
+        assert_eq!(region.first_lines.start + 3, region.second_lines.start);
+        assert_eq!(region.first_lines.end + 3, region.second_lines.end);
+        // The whole script is one region, not split where the two functions repeat each other
+        assert_eq!(region.first_lines.start, 1);
+        assert!(region.first_lines.end >= 15);
+    }
+
+    fn texts(content: &str, language: Language) -> Vec<String> {
+        tokenize(content, language)
+            .into_iter()
+            .map(|token| token.text)
+            .collect()
+    }
+
+    #[test]
+    fn names_comments_and_whitespace_are_normalized_away() {
+        assert_eq!(
+            texts("let sum = a + 1; // legg sammen", Language::Js),
+            texts("let\ttotal=\n  b+1;", Language::Js)
+        );
+        assert_eq!(
+            texts("let sum = a + 1;", Language::Js),
+            ["let", "$id", "=", "$id", "+", "1", ";"]
+        );
+        assert_eq!(
+            texts(".kort, #meny { --farge: red; }", Language::Css),
+            texts("/* kort */ .card,#menu{--color:red;}", Language::Css)
+        );
+        assert_eq!(
+            texts(
+                "<DIV class=\"kort\" title=\"Hei\"><!-- tekst --></DIV>",
+                Language::Html
+            ),
+            ["<", "div", "class", "=", "$id", "title", "=", "\"Hei\"", ">", "<", "/", "div", ">"]
+        );
+
+        let lines: Vec<u32> = tokenize("/* en\nto */\nlet x = `a\nb`;\nx;", Language::Js)
+            .iter()
+            .map(|token| token.line)
+            .collect();
+        assert_eq!(lines, [3, 3, 3, 3, 4, 5, 5]);
+    }
+
+    #[test]
+    fn the_same_code_gives_the_same_fingerprints() {
+        let config = SimilarityConfig::default();
+        let hashes = |content: &str| -> Vec<u64> {
+            winnow(&tokenize(content, Language::Js), 0, &config)
+                .iter()
+                .map(|fingerprint| fingerprint.hash)
+                .collect()
+        };
+
+        let original = hashes(SCRIPT);
+        assert!(!original.is_empty());
+        assert_eq!(original, hashes(&SCRIPT.replace("items", "varer")));
+        assert_ne!(original, hashes(&SCRIPT.replace("'li'", "'p'")));
+        assert!(hashes("x;").is_empty());
+    }
+}
@@ -0,0 +1,404 @@
+use crate::encoding;
+use crate::issue::{Issue, IssueSource};
+use crate::structure::project_root;
+use serde::{Deserialize, Serialize};
+use similar::{DiffTag, TextDiff};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// The starter code handed out with an assignment, which every deliverable is compared against.
+#[derive(Debug, Clone)]
+pub struct StarterCode {
+    /// The HTML, CSS and JS files, by their path relative to the project root.
+    files: BTreeMap<PathBuf, String>,
+}
+
+impl StarterCode {
+    /// Function to read the HTML, CSS and JS files of the starter code.
+    pub fn load(starter_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if !starter_dir.is_dir() {
+            return Err(format!("{} is not a directory", starter_dir.display()).into());
+        }
+
+        let root = project_root(starter_dir)?;
+        let files = source_files(&root)?
+            .into_iter()
+            .map(|path| {
+                let content = encoding::read_to_string(&root.join(&path))?;
+                Ok((path, normalize(&content)))
+            })
+            .collect::<Result<_, Box<dyn std::error::Error>>>()?;
+
+        Ok(StarterCode { files })
+    }
+
+    /// Function to list the files of the starter code, by their path relative to the project root.
+    pub fn files(&self) -> impl Iterator<Item = (&Path, &str)> {
+        self.files
+            .iter()
+            .map(|(path, content)| (path.as_path(), content.as_str()))
+    }
+
+    /// Function to compare a deliverable to the starter code, file by file.
+    pub fn diff(&self, student_dir: &Path) -> Result<StarterDiff, Box<dyn std::error::Error>> {
+        let root = project_root(student_dir)?;
+        // Paths in the diff are relative to the student directory, like in the validation report
+        let prefix = root.strip_prefix(student_dir).unwrap_or(Path::new(""));
+
+        let mut files = Vec::new();
+        for path in source_files(&root)? {
+            let content = match encoding::read_to_string(&root.join(&path)) {
+                Ok(content) => normalize(&content),
+                Err(e) => {
+                    eprintln!("> Could not read {}: {}", root.join(&path).display(), e);
+                    continue;
+                }
+            };
+
+            let file = prefix.join(&path);
+            let diff = match self.files.get(&path) {
+                None => FileDiff {
+                    file,
+                    status: FileStatus::Added,
+                    changed_lines: vec![LineRange {
+                        start: 1,
+                        end: content.lines().count().max(1) as u32,
+                    }],
+                    patch: None,
+                },
+                Some(starter) if *starter == content => FileDiff {
+                    file,
+                    status: FileStatus::Unchanged,
+                    changed_lines: Vec::new(),
+                    patch: None,
+                },
+                Some(starter) => compare(file, starter, &content),
+            };
+            files.push(diff);
+        }
+
+        for path in self.files.keys() {
+            if !root.join(path).is_file() {
+                files.push(FileDiff {
+                    file: prefix.join(path),
+                    status: FileStatus::Removed,
+                    changed_lines: Vec::new(),
+                    patch: None,
+                });
+            }
+        }
+
+        Ok(StarterDiff { files })
+    }
+}
+
+/// How a file in a deliverable differs from the starter code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    /// The file is identical to the starter code, so it's template code.
+    Unchanged,
+    Modified,
+    /// The file isn't part of the starter code.
+    Added,
+    /// The file is part of the starter code, but missing from the deliverable.
+    Removed,
+}
+
+/// A range of lines, 1-based and inclusive.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LineRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+/// The differences between a single file in a deliverable and the starter code.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileDiff {
+    /// Path of the file, relative to the student directory.
+    pub file: PathBuf,
+    pub status: FileStatus,
+    /// The lines in the student's file that were written or changed by the student.
+    pub changed_lines: Vec<LineRange>,
+    /// The whole file as a unified diff against the starter code, for modified files.
+    pub patch: Option<String>,
+}
+
+impl FileDiff {
+    /// Function to check whether a line was written by the student. Lines of added files always are.
+    pub fn is_changed(&self, line: u32) -> bool {
+        self.changed_lines
+            .iter()
+            .any(|range| range.start <= line && line <= range.end)
+    }
+}
+
+/// The differences between a deliverable and the starter code, stored as `starter-diff.json` and
+/// `starter-diff.md` in the student directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StarterDiff {
+    pub files: Vec<FileDiff>,
+}
+
+impl StarterDiff {
+    /// Function to read the starter code diff of a student, if the deliverable has been compared.
+    pub fn read(project_dir: &Path) -> Option<StarterDiff> {
+        let diff = fs::read_to_string(project_dir.join("starter-diff.json")).ok()?;
+        serde_json::from_str(&diff).ok()
+    }
+
+    pub fn write(&self, project_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(
+            project_dir.join("starter-diff.json"),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        fs::write(project_dir.join("starter-diff.md"), self.to_markdown())?;
+        println!(
+            "> Starter code: {} of {} files unchanged",
+            self.count(FileStatus::Unchanged),
+            self.files.len()
+        );
+        Ok(())
+    }
+
+    pub fn get(&self, file: &Path) -> Option<&FileDiff> {
+        self.files.iter().find(|diff| diff.file == file)
+    }
+
+    /// Function to check whether a file is unchanged template code.
+    pub fn is_template(&self, file: &Path) -> bool {
+        self.get(file)
+            .is_some_and(|diff| diff.status == FileStatus::Unchanged)
+    }
+
+    fn count(&self, status: FileStatus) -> usize {
+        self.files
+            .iter()
+            .filter(|diff| diff.status == status)
+            .count()
+    }
+
+    /// Function to leave out the issues found in template code: unchanged files, and lines of modified files that
+    /// the student didn't touch. Returns the remaining issues and how many were left out.
+    pub fn remove_template_issues(&self, issues: Vec<Issue>) -> (Vec<Issue>, usize) {
+        let total = issues.len();
+        let issues: Vec<Issue> = issues
+            .into_iter()
+            .filter(|issue| {
+                // Missing and forbidden files are the student's responsibility, whatever the template contains
+                if issue.source == IssueSource::Structure {
+                    return true;
+                }
+                match self.get(&issue.file) {
+                    Some(diff) => match (diff.status, issue.line) {
+                        (FileStatus::Unchanged, _) => false,
+                        (FileStatus::Modified, Some(line)) => diff.is_changed(line),
+                        _ => true,
+                    },
+                    None => true,
+                }
+            })
+            .collect();
+        let removed = total - issues.len();
+        (issues, removed)
+    }
+
+    /// Function to format the diff as Markdown, listing the files by how they differ from the starter code.
+    pub fn to_markdown(&self) -> String {
+        let mut output = String::new();
+        for diff in &self.files {
+            let description = match diff.status {
+                FileStatus::Unchanged => "unchanged starter code".to_string(),
+                FileStatus::Modified if diff.changed_lines.is_empty() => {
+                    "modified, lines removed only".to_string()
+                }
+                FileStatus::Modified => {
+                    format!("modified, lines {}", format_ranges(&diff.changed_lines))
+                }
+                FileStatus::Added => "added by the student".to_string(),
+                FileStatus::Removed => "removed from the starter code".to_string(),
+            };
+            output.push_str(&format!("- `{}`: {}\n", diff.file.display(), description));
+        }
+        output
+    }
+}
+
+/// Function to format line ranges compactly, e.g. `3–10, 15`.
+pub fn format_ranges(ranges: &[LineRange]) -> String {
+    ranges
+        .iter()
+        .map(|range| {
+            if range.start == range.end {
+                range.start.to_string()
+            } else {
+                format!("{}–{}", range.start, range.end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Function to diff a modified file line by line against the starter code.
+fn compare(file: PathBuf, starter: &str, content: &str) -> FileDiff {
+    let diff = TextDiff::from_lines(starter, content);
+
+    let mut changed_lines: Vec<LineRange> = Vec::new();
+    for op in diff.ops() {
+        let lines = op.new_range();
+        if op.tag() == DiffTag::Equal || lines.is_empty() {
+            continue;
+        }
+        let range = LineRange {
+            start: lines.start as u32 + 1,
+            end: lines.end as u32,
+        };
+        match changed_lines.last_mut() {
+            Some(last) if last.end + 1 >= range.start => last.end = range.end,
+            _ => changed_lines.push(range),
+        }
+    }
+
+    // Show the whole file, so the changes can be read in context
+    let context = starter.lines().count().max(content.lines().count());
+    let patch = diff
+        .unified_diff()
+        .context_radius(context)
+        .header("starter", &file.display().to_string())
+        .to_string();
+
+    FileDiff {
+        file,
+        status: FileStatus::Modified,
+        changed_lines,
+        patch: Some(patch),
+    }
+}
+
+/// Function to list the HTML, CSS and JS files in a project, relative to its root.
+fn source_files(root: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(root).sort_by_file_name() {
+        let entry = entry?;
+        let extension = entry.path().extension().and_then(|s| s.to_str());
+        if entry.file_type().is_file() && matches!(extension, Some("html" | "css" | "js")) {
+            files.push(entry.path().strip_prefix(root)?.to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+/// Function to normalize line endings and trailing whitespace, which editors change without the student noticing.
+fn normalize(content: &str) -> String {
+    let mut normalized: String = content
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n");
+    normalized.push('\n');
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::issue::Severity;
+
+    const SCRIPT: &str = "const liste = [];\n\nfunction leggTil(vare) {\n  // TODO\n}\n\nfunction vis() {\n  // TODO\n}\n";
+
+    fn write(dir: &Path, files: &[(&str, &str)]) {
+        for (name, content) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+    }
+
+    fn issue(file: &str, line: u32) -> Issue {
+        Issue::new(
+            IssueSource::Js,
+            Severity::Warning,
+            Path::new(file),
+            "Use “===” instead of “==”".to_string(),
+        )
+        .at(Some(line), None)
+    }
+
+    #[test]
+    fn deliverables_are_compared_to_the_starter_file_by_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let starter_dir = dir.path().join("starter");
+        write(
+            &starter_dir,
+            &[
+                ("index.html", "<h1>Handleliste</h1>\n"),
+                ("css/style.css", "body {\n  margin: 0;\n}\n"),
+                ("js/app.js", SCRIPT),
+                ("om.html", "<h1>Om</h1>\n"),
+            ],
+        );
+        // The student zipped the folder with the project, and their editor changed the line endings
+        let project = dir.path().join("ola").join("handleliste");
+        write(
+            &project,
+            &[
+                ("index.html", "<h1>Handleliste</h1>  \r\n"),
+                ("css/style.css", "body {\n  margin: 0;\n}\n"),
+                (
+                    "js/app.js",
+                    &SCRIPT
+                        .replacen("  // TODO", "  liste.push(vare);\n  vis();", 1)
+                        .replacen("  // TODO", "  console.log(liste);", 1),
+                ),
+                ("kontakt.html", "<h1>Kontakt</h1>\n<p>Ring oss</p>\n"),
+            ],
+        );
+
+        let starter = StarterCode::load(&starter_dir).unwrap();
+        let diff = starter.diff(&dir.path().join("ola")).unwrap();
+
+        let status = |file: &str| diff.get(&Path::new("handleliste").join(file)).unwrap();
+        assert!(diff.is_template(Path::new("handleliste/index.html")));
+        assert_eq!(status("css/style.css").status, FileStatus::Unchanged);
+        assert_eq!(status("kontakt.html").status, FileStatus::Added);
+        assert_eq!(format_ranges(&status("kontakt.html").changed_lines), "1–2");
+        assert_eq!(status("om.html").status, FileStatus::Removed);
+
+        let script = status("js/app.js");
+        assert_eq!(script.status, FileStatus::Modified);
+        assert_eq!(format_ranges(&script.changed_lines), "4–5, 9");
+        assert!(script
+            .patch
+            .as_ref()
+            .unwrap()
+            .contains("+  liste.push(vare);"));
+
+        let (issues, removed) = diff.remove_template_issues(vec![
+            issue("handleliste/css/style.css", 2),
+            issue("handleliste/js/app.js", 1),
+            issue("handleliste/js/app.js", 5),
+            issue("handleliste/kontakt.html", 1),
+            Issue::new(
+                IssueSource::Structure,
+                Severity::Error,
+                Path::new("handleliste/index.html"),
+                "Missing file".to_string(),
+            ),
+        ]);
+        assert_eq!(removed, 2);
+        let remaining: Vec<(&Path, Option<u32>)> = issues
+            .iter()
+            .map(|issue| (issue.file.as_path(), issue.line))
+            .collect();
+        assert_eq!(
+            remaining,
+            [
+                (Path::new("handleliste/js/app.js"), Some(5)),
+                (Path::new("handleliste/kontakt.html"), Some(1)),
+                (Path::new("handleliste/index.html"), None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,441 @@
+use crate::explain;
+use crate::issue::Severity;
+use crate::validate::ValidationReport;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many of the most common messages to include in the report.
+const TOP_MESSAGES: usize = 20;
+
+/// Validation statistics for a single student.
+struct StudentStatistics {
+    name: String,
+    errors: usize,
+    warnings: usize,
+    info: usize,
+    unvalidated: Vec<String>,
+    excluded: Vec<String>,
+}
+
+impl StudentStatistics {
+    fn total(&self) -> usize {
+        self.errors + self.warnings + self.info
+    }
+}
+
+/// A validation message, normalized into a template, and how often it occurs across the class.
+struct MessageStatistics {
+    template: String,
+    example: String,
+    occurrences: usize,
+    students: usize,
+}
+
+/// Validation statistics aggregated over every student in the class.
+struct ClassStatistics {
+    students: Vec<StudentStatistics>,
+    messages: Vec<MessageStatistics>,
+    file_types: BTreeMap<String, usize>,
+}
+
+impl ClassStatistics {
+    fn new(reports: &[(PathBuf, ValidationReport)]) -> Self {
+        let mut students = Vec::new();
+        let mut messages: HashMap<String, (String, usize, HashSet<String>)> = HashMap::new();
+        let mut file_types = BTreeMap::new();
+
+        for (student_dir, report) in reports {
+            let name = student_dir
+                .file_name()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            let count = |severity| {
+                report
+                    .issues
+                    .iter()
+                    .filter(|issue| issue.severity == severity)
+                    .count()
+            };
+
+            for issue in &report.issues {
+                let (_, occurrences, students) = messages
+                    .entry(explain::template(&issue.message))
+                    .or_insert_with(|| (issue.message.clone(), 0, HashSet::new()));
+                *occurrences += 1;
+                students.insert(name.clone());
+
+                let file_type = issue
+                    .file
+                    .extension()
+                    .map(|s| s.to_string_lossy().to_lowercase())
+                    .unwrap_or_else(|| "other".to_string());
+                *file_types.entry(file_type).or_insert(0) += 1;
+            }
+
+            students.push(StudentStatistics {
+                errors: count(Severity::Error),
+                warnings: count(Severity::Warning),
+                info: count(Severity::Info),
+                unvalidated: report
+                    .unvalidated
+                    .iter()
+                    .map(|file| format!("{} ({})", file.file.display(), file.reason))
+                    .collect(),
+                excluded: report
+                    .excluded
+                    .iter()
+                    .map(|file| format!("{} ({})", file.file.display(), file.reason))
+                    .collect(),
+                name,
+            });
+        }
+
+        students.sort_by(|a, b| b.total().cmp(&a.total()).then(a.name.cmp(&b.name)));
+
+        let mut messages: Vec<MessageStatistics> = messages
+            .into_iter()
+            .map(
+                |(template, (example, occurrences, students))| MessageStatistics {
+                    template,
+                    example,
+                    occurrences,
+                    students: students.len(),
+                },
+            )
+            .collect();
+        messages.sort_by(|a, b| {
+            b.occurrences
+                .cmp(&a.occurrences)
+                .then(a.template.cmp(&b.template))
+        });
+
+        ClassStatistics {
+            students,
+            messages,
+            file_types,
+        }
+    }
+
+    fn total_issues(&self) -> usize {
+        self.students.iter().map(StudentStatistics::total).sum()
+    }
+
+    fn without_issues(&self) -> Vec<&str> {
+        self.students
+            .iter()
+            .filter(|student| student.total() == 0 && student.unvalidated.is_empty())
+            .map(|student| student.name.as_str())
+            .collect()
+    }
+
+    fn with_unvalidated_files(&self) -> Vec<&StudentStatistics> {
+        self.students
+            .iter()
+            .filter(|student| !student.unvalidated.is_empty())
+            .collect()
+    }
+
+    fn with_excluded_files(&self) -> Vec<&StudentStatistics> {
+        self.students
+            .iter()
+            .filter(|student| !student.excluded.is_empty())
+            .collect()
+    }
+
+    /// Function to compute how many students fall into each bucket of issue counts.
+    fn distribution(&self) -> Vec<(String, usize)> {
+        let buckets: [(usize, Option<usize>); 6] = [
+            (0, Some(0)),
+            (1, Some(5)),
+            (6, Some(10)),
+            (11, Some(25)),
+            (26, Some(50)),
+            (51, None),
+        ];
+
+        buckets
+            .iter()
+            .map(|(low, high)| {
+                let label = match high {
+                    Some(high) if high == low => low.to_string(),
+                    Some(high) => format!("{}–{}", low, high),
+                    None => format!("{}+", low),
+                };
+                let count = self
+                    .students
+                    .iter()
+                    .filter(|student| {
+                        student.total() >= *low && high.is_none_or(|high| student.total() <= high)
+                    })
+                    .count();
+                (label, count)
+            })
+            .collect()
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut output = String::from("# Validation report for the class\n\n");
+        output.push_str(&format!(
+            "{} students, {} issues in total.\n\n",
+            self.students.len(),
+            self.total_issues()
+        ));
+
+        output.push_str(&format!("## Top {} messages\n\n", TOP_MESSAGES));
+        output.push_str("| # | Message | Occurrences | Students |\n|---|---|---|---|\n");
+        for (index, message) in self.messages.iter().take(TOP_MESSAGES).enumerate() {
+            output.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                index + 1,
+                message.example.replace('|', "\\|").replace('\n', " "),
+                message.occurrences,
+                message.students
+            ));
+        }
+
+        output.push_str("\n## Issues per file type\n\n| File type | Issues |\n|---|---|\n");
+        for (file_type, count) in &self.file_types {
+            output.push_str(&format!("| {} | {} |\n", file_type, count));
+        }
+
+        output.push_str("\n## Distribution of issues\n\n| Issues | Students |\n|---|---|\n");
+        for (label, count) in self.distribution() {
+            output.push_str(&format!("| {} | {} |\n", label, count));
+        }
+
+        output.push_str(
+            "\n## Issues per student\n\n| Student | Errors | Warnings | Info | Total |\n|---|---|---|---|---|\n",
+        );
+        for student in &self.students {
+            output.push_str(&format!(
+                "| {} | {} | {} | {} | {} |\n",
+                student.name,
+                student.errors,
+                student.warnings,
+                student.info,
+                student.total()
+            ));
+        }
+
+        output.push_str("\n## Students without issues\n\n");
+        let without_issues = self.without_issues();
+        if without_issues.is_empty() {
+            output.push_str("None.\n");
+        }
+        for name in without_issues {
+            output.push_str(&format!("- {}\n", name));
+        }
+
+        output.push_str("\n## Students with files that couldn't be validated\n\n");
+        let unvalidated = self.with_unvalidated_files();
+        if unvalidated.is_empty() {
+            output.push_str("None.\n");
+        }
+        for student in unvalidated {
+            output.push_str(&format!(
+                "- {}: {}\n",
+                student.name,
+                student.unvalidated.join(", ")
+            ));
+        }
+
+        output
+            .push_str("\n## Library and minified files that were neither validated nor graded\n\n");
+        let excluded = self.with_excluded_files();
+        if excluded.is_empty() {
+            output.push_str("None.\n");
+        }
+        for student in excluded {
+            output.push_str(&format!(
+                "- {}: {}\n",
+                student.name,
+                student.excluded.join(", ")
+            ));
+        }
+
+        output
+    }
+
+    fn to_html(&self) -> String {
+        let table = |headers: &[&str], rows: Vec<Vec<String>>| {
+            let mut table = String::from("<table>\n<tr>");
+            for header in headers {
+                table.push_str(&format!("<th>{}</th>", escape_html(header)));
+            }
+            table.push_str("</tr>\n");
+            for row in rows {
+                table.push_str("<tr>");
+                for cell in row {
+                    table.push_str(&format!("<td>{}</td>", escape_html(&cell)));
+                }
+                table.push_str("</tr>\n");
+            }
+            table.push_str("</table>\n");
+            table
+        };
+        let list = |items: Vec<String>| {
+            if items.is_empty() {
+                return "<p>None.</p>\n".to_string();
+            }
+            let items: String = items
+                .iter()
+                .map(|item| format!("<li>{}</li>", escape_html(item)))
+                .collect();
+            format!("<ul>{}</ul>\n", items)
+        };
+
+        let mut body = format!(
+            "<h1>Validation report for the class</h1>\n<p>{} students, {} issues in total.</p>\n",
+            self.students.len(),
+            self.total_issues()
+        );
+
+        body.push_str(&format!("<h2>Top {} messages</h2>\n", TOP_MESSAGES));
+        body.push_str(&table(
+            &["#", "Message", "Occurrences", "Students"],
+            self.messages
+                .iter()
+                .take(TOP_MESSAGES)
+                .enumerate()
+                .map(|(index, message)| {
+                    vec![
+                        (index + 1).to_string(),
+                        message.example.clone(),
+                        message.occurrences.to_string(),
+                        message.students.to_string(),
+                    ]
+                })
+                .collect(),
+        ));
+
+        body.push_str("<h2>Issues per file type</h2>\n");
+        body.push_str(&table(
+            &["File type", "Issues"],
+            self.file_types
+                .iter()
+                .map(|(file_type, count)| vec![file_type.clone(), count.to_string()])
+                .collect(),
+        ));
+
+        body.push_str("<h2>Distribution of issues</h2>\n");
+        body.push_str(&table(
+            &["Issues", "Students"],
+            self.distribution()
+                .into_iter()
+                .map(|(label, count)| vec![label, count.to_string()])
+                .collect(),
+        ));
+
+        body.push_str("<h2>Issues per student</h2>\n");
+        body.push_str(&table(
+            &["Student", "Errors", "Warnings", "Info", "Total"],
+            self.students
+                .iter()
+                .map(|student| {
+                    vec![
+                        student.name.clone(),
+                        student.errors.to_string(),
+                        student.warnings.to_string(),
+                        student.info.to_string(),
+                        student.total().to_string(),
+                    ]
+                })
+                .collect(),
+        ));
+
+        body.push_str("<h2>Students without issues</h2>\n");
+        body.push_str(&list(
+            self.without_issues()
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+        ));
+
+        body.push_str("<h2>Students with files that couldn't be validated</h2>\n");
+        body.push_str(&list(
+            self.with_unvalidated_files()
+                .into_iter()
+                .map(|student| format!("{}: {}", student.name, student.unvalidated.join(", ")))
+                .collect(),
+        ));
+
+        body.push_str(
+            "<h2>Library and minified files that were neither validated nor graded</h2>\n",
+        );
+        body.push_str(&list(
+            self.with_excluded_files()
+                .into_iter()
+                .map(|student| format!("{}: {}", student.name, student.excluded.join(", ")))
+                .collect(),
+        ));
+
+        format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>Validation report</title>\n\
+            <style>body {{ font-family: sans-serif; }} table {{ border-collapse: collapse; }} \
+            th, td {{ border: 1px solid #ccc; padding: 4px 8px; text-align: left; }}</style>\n\
+            </head>\n<body>\n{}</body>\n</html>\n",
+            body
+        )
+    }
+
+    fn to_csv(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record([
+            "student",
+            "errors",
+            "warnings",
+            "info",
+            "total",
+            "unvalidated_files",
+        ])?;
+        for student in &self.students {
+            writer.write_record([
+                student.name.clone(),
+                student.errors.to_string(),
+                student.warnings.to_string(),
+                student.info.to_string(),
+                student.total().to_string(),
+                student.unvalidated.join("; "),
+            ])?;
+        }
+        Ok(String::from_utf8(writer.into_inner()?)?)
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Function to aggregate the validation reports of every student into a class report,
+/// written as `class-report.md`, `class-report.html` and `class-report.csv` in the destination directory.
+pub fn write_class_report(
+    destination_dir: &Path,
+    reports: &[(PathBuf, ValidationReport)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let statistics = ClassStatistics::new(reports);
+
+    fs::write(
+        destination_dir.join("class-report.md"),
+        statistics.to_markdown(),
+    )?;
+    fs::write(
+        destination_dir.join("class-report.html"),
+        statistics.to_html(),
+    )?;
+    fs::write(
+        destination_dir.join("class-report.csv"),
+        statistics.to_csv()?,
+    )?;
+
+    println!(
+        "> Wrote class report to {:?}",
+        destination_dir.join("class-report.md")
+    );
+
+    Ok(())
+}
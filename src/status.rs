@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// The workspace state, in the destination directory. It is kept when the deliverables are extracted again.
+pub const STATE_FILE: &str = "grading-state.json";
+
+/// Whether a student's deliverable was graded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Graded,
+    /// Grading failed, e.g. because the model API kept failing after retries.
+    Failed,
+    /// The model never returned a valid structured grading. Its last response was kept as the feedback.
+    Unstructured,
+}
+
+/// How a student was graded last time, and from what.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StudentState {
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Hash of everything the grading prompt is made from: the student's files, the findings from validation,
+    /// the description and the criteria. Unknown if grading failed before the prompt was made.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inputs_hash: Option<String>,
+    pub model: String,
+    /// Hash of the prompt templates used for grading.
+    pub prompt_version: String,
+}
+
+impl StudentState {
+    /// Function to tell why a student graded with `self` has to be graded again, to be graded as `current`.
+    /// Returns `None` if the grading still holds.
+    pub fn changes(&self, current: &StudentState) -> Option<&'static str> {
+        if self.status == Status::Failed {
+            Some("grading failed last time")
+        } else if self.status == Status::Unstructured {
+            Some("no structured grading came back last time")
+        } else if self.inputs_hash != current.inputs_hash {
+            Some("the deliverable, the findings or the assignment changed")
+        } else if self.model != current.model {
+            Some("the model changed")
+        } else if self.prompt_version != current.prompt_version {
+            Some("the prompts changed")
+        } else {
+            None
+        }
+    }
+}
+
+/// How each student was graded, stored as `grading-state.json` in the destination directory,
+/// so a later run only grades the students that failed or changed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GradingState {
+    pub students: BTreeMap<String, StudentState>,
+}
+
+impl GradingState {
+    pub fn read(destination_dir: &Path) -> Option<Self> {
+        let state = fs::read_to_string(destination_dir.join(STATE_FILE)).ok()?;
+        serde_json::from_str(&state).ok()
+    }
+
+    /// Function to write the state. It is written after each student, so it is up to date if the run is stopped.
+    pub fn write(&self, destination_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(
+            destination_dir.join(STATE_FILE),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// Function to tell if a student wasn't graded, or was graded without a structured grading.
+    pub fn is_failed(&self, student: &str) -> bool {
+        self.students
+            .get(student)
+            .is_some_and(|state| state.status != Status::Graded)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.students
+            .values()
+            .filter(|state| state.status != Status::Graded)
+            .count()
+    }
+}
+
+/// Function to hash the parts of an input as SHA-256 hex. Each part is length-prefixed, so moving text
+/// from one part to the next changes the hash.
+pub fn hash(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graded() -> StudentState {
+        StudentState {
+            status: Status::Graded,
+            error: None,
+            inputs_hash: Some(hash(&["index.html", "<p>Hei</p>"])),
+            model: "gpt-4".to_string(),
+            prompt_version: "abc".to_string(),
+        }
+    }
+
+    #[test]
+    fn unchanged_gradings_hold() {
+        assert_eq!(graded().changes(&graded()), None);
+    }
+
+    #[test]
+    fn failures_and_changes_are_graded_again() {
+        let failed = StudentState {
+            status: Status::Failed,
+            error: Some("429 Too Many Requests".to_string()),
+            ..graded()
+        };
+        assert_eq!(failed.changes(&graded()), Some("grading failed last time"));
+
+        let unstructured = StudentState {
+            status: Status::Unstructured,
+            ..graded()
+        };
+        assert_eq!(
+            unstructured.changes(&graded()),
+            Some("no structured grading came back last time")
+        );
+
+        let changed = StudentState {
+            inputs_hash: Some(hash(&["index.html", "<p>Hallo</p>"])),
+            ..graded()
+        };
+        assert_eq!(
+            graded().changes(&changed),
+            Some("the deliverable, the findings or the assignment changed")
+        );
+
+        let model = StudentState {
+            model: "gpt-4o".to_string(),
+            ..graded()
+        };
+        assert_eq!(graded().changes(&model), Some("the model changed"));
+
+        let prompts = StudentState {
+            prompt_version: "def".to_string(),
+            ..graded()
+        };
+        assert_eq!(graded().changes(&prompts), Some("the prompts changed"));
+    }
+
+    #[test]
+    fn moving_text_between_parts_changes_the_hash() {
+        assert_ne!(hash(&["ab", "c"]), hash(&["a", "bc"]));
+        assert_eq!(hash(&["ab", "c"]), hash(&["ab", "c"]));
+    }
+}
@@ -0,0 +1,253 @@
+use crate::config::StructureConfig;
+use crate::issue::{Issue, IssueSource, Severity};
+use globset::{GlobBuilder, GlobMatcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// A path or glob from the structure manifest, compiled for matching.
+#[derive(Debug, Clone)]
+struct PathPattern {
+    pattern: String,
+    /// Patterns ending with `/` only match folders.
+    is_dir: bool,
+    matcher: GlobMatcher,
+    /// The same pattern ignoring case, to tell misnamed files apart from missing ones.
+    case_insensitive_matcher: GlobMatcher,
+}
+
+impl PathPattern {
+    fn compile(pattern: &str, case_insensitive: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let glob = pattern.trim_end_matches('/');
+        let build = |case_insensitive| {
+            GlobBuilder::new(glob)
+                .case_insensitive(case_insensitive)
+                .literal_separator(true)
+                .build()
+                .map(|glob| glob.compile_matcher())
+                .map_err(|e| format!("Invalid path pattern “{}”: {}", pattern, e))
+        };
+
+        Ok(PathPattern {
+            pattern: pattern.to_string(),
+            is_dir: pattern.ends_with('/'),
+            matcher: build(case_insensitive)?,
+            case_insensitive_matcher: build(true)?,
+        })
+    }
+
+    fn is_glob(&self) -> bool {
+        self.pattern.contains(['*', '?', '[', '{'])
+    }
+}
+
+/// The files and folders every deliverable of an assignment must, or must not, contain.
+#[derive(Debug, Clone)]
+pub struct StructureManifest {
+    required: Vec<PathPattern>,
+    forbidden: Vec<PathPattern>,
+}
+
+/// A file or folder in a deliverable, relative to the project root.
+struct Entry {
+    path: PathBuf,
+    is_dir: bool,
+}
+
+impl StructureManifest {
+    pub fn new(config: &StructureConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| PathPattern::compile(pattern, config.case_insensitive))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(StructureManifest {
+            required: compile(&config.required)?,
+            forbidden: compile(&config.forbidden)?,
+        })
+    }
+
+    /// Function to check a deliverable against the manifest, reporting missing, misnamed and forbidden paths.
+    pub fn check(&self, student_dir: &Path) -> Result<Vec<Issue>, Box<dyn std::error::Error>> {
+        let root = project_root(student_dir)?;
+        // Paths in issues are relative to the student directory, like for every other checker
+        let prefix = root.strip_prefix(student_dir).unwrap_or(Path::new(""));
+
+        let mut entries = Vec::new();
+        for entry in WalkDir::new(&root).min_depth(1) {
+            let entry = entry?;
+            if is_generated(student_dir, entry.path()) {
+                continue;
+            }
+            entries.push(Entry {
+                path: entry.path().strip_prefix(&root)?.to_path_buf(),
+                is_dir: entry.file_type().is_dir(),
+            });
+        }
+
+        let mut issues = Vec::new();
+
+        for pattern in &self.required {
+            let candidates = || {
+                entries
+                    .iter()
+                    .filter(|entry| entry.is_dir == pattern.is_dir)
+            };
+            if candidates().any(|entry| pattern.matcher.is_match(&entry.path)) {
+                continue;
+            }
+
+            let kind = if pattern.is_dir { "folder" } else { "file" };
+            let misnamed =
+                candidates().find(|entry| pattern.case_insensitive_matcher.is_match(&entry.path));
+            let message = match misnamed {
+                Some(entry) => format!(
+                    "The {} “{}” should be named “{}”. Web servers are case-sensitive, so links to it won't work",
+                    kind,
+                    entry.path.display(),
+                    pattern.pattern.trim_end_matches('/')
+                ),
+                None if pattern.is_glob() => format!(
+                    "No {} matches “{}”, which the assignment requires",
+                    kind, pattern.pattern
+                ),
+                None => format!("The required {} “{}” is missing", kind, pattern.pattern),
+            };
+            let file = misnamed
+                .map(|entry| entry.path.clone())
+                .unwrap_or_else(|| PathBuf::from(pattern.pattern.trim_end_matches('/')));
+            issues.push(Issue::new(
+                IssueSource::Structure,
+                Severity::Error,
+                &prefix.join(file),
+                message,
+            ));
+        }
+
+        let mut reported: Vec<&Path> = Vec::new();
+        for entry in &entries {
+            // Everything inside a forbidden folder is covered by reporting the folder
+            if reported.iter().any(|dir| entry.path.starts_with(dir)) {
+                continue;
+            }
+            let Some(pattern) = self.forbidden.iter().find(|pattern| {
+                (!pattern.is_dir || entry.is_dir) && pattern.matcher.is_match(&entry.path)
+            }) else {
+                continue;
+            };
+
+            let kind = if entry.is_dir { "folder" } else { "file" };
+            issues.push(Issue::new(
+                IssueSource::Structure,
+                Severity::Warning,
+                &prefix.join(&entry.path),
+                format!(
+                    "The {} “{}” shouldn't be part of the deliverable (matches “{}”)",
+                    kind,
+                    entry.path.display(),
+                    pattern.pattern
+                ),
+            ));
+            if entry.is_dir {
+                reported.push(&entry.path);
+            }
+        }
+
+        Ok(issues)
+    }
+}
+
+/// The files the grader writes to each student directory. They aren't part of the deliverable.
+pub const GENERATED_FILES: &[&str] = &[
+    "validation.json",
+    "validate.txt",
+    "final.txt",
+    "starter-diff.json",
+    "starter-diff.md",
+    "checklist.json",
+    "checklist.md",
+    "js-tests.json",
+    "js-tests.md",
+    "feedback.txt",
+    "grade.json",
+    "grade-metadata.json",
+];
+
+/// Function to check whether a path in a student directory is a file written by the grader.
+pub fn is_generated(student_dir: &Path, path: &Path) -> bool {
+    path.parent() == Some(student_dir)
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| GENERATED_FILES.contains(&name))
+}
+
+/// Function to find the root of the project in a deliverable. Students often zip the folder containing
+/// their project rather than its contents, so single folders are descended into. Files written by the
+/// grader are ignored, so the root is the same before and after grading.
+pub fn project_root(student_dir: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let mut root = student_dir.to_path_buf();
+    loop {
+        let entries: Vec<PathBuf> = fs::read_dir(&root)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| !is_generated(student_dir, path))
+            .collect();
+        match entries.as_slice() {
+            [only] if only.is_dir() => root = only.clone(),
+            _ => return Ok(root),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_folders_are_descended_into() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("ola").join("oblig1").join("prosjekt");
+        fs::create_dir_all(project.join("css")).unwrap();
+        fs::write(project.join("index.html"), "").unwrap();
+
+        assert_eq!(project_root(&dir.path().join("ola")).unwrap(), project);
+    }
+
+    #[test]
+    fn the_root_stops_at_files_or_several_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let student_dir = dir.path().join("ola");
+        fs::create_dir_all(student_dir.join("prosjekt")).unwrap();
+        fs::write(student_dir.join("index.html"), "").unwrap();
+        assert_eq!(project_root(&student_dir).unwrap(), student_dir);
+
+        fs::remove_file(student_dir.join("index.html")).unwrap();
+        fs::create_dir(student_dir.join("ekstra")).unwrap();
+        assert_eq!(project_root(&student_dir).unwrap(), student_dir);
+    }
+
+    #[test]
+    fn grader_files_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let student_dir = dir.path().join("ola");
+        fs::create_dir_all(student_dir.join("prosjekt")).unwrap();
+        for name in GENERATED_FILES {
+            fs::write(student_dir.join(name), "").unwrap();
+        }
+
+        assert_eq!(
+            project_root(&student_dir).unwrap(),
+            student_dir.join("prosjekt")
+        );
+        // Only at the top of the student directory
+        fs::write(student_dir.join("prosjekt").join("feedback.txt"), "").unwrap();
+        fs::create_dir(student_dir.join("prosjekt").join("css")).unwrap();
+        assert_eq!(
+            project_root(&student_dir).unwrap(),
+            student_dir.join("prosjekt")
+        );
+    }
+}
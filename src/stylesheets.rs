@@ -0,0 +1,388 @@
+use crate::encoding;
+use crate::inline::{self, InlineKind};
+use crate::issue::line_column;
+use crate::starter::StarterDiff;
+use crate::vendor::VendorDetector;
+use lightningcss::declaration::DeclarationBlock;
+use lightningcss::properties::custom::CustomPropertyName;
+use lightningcss::properties::Property;
+use lightningcss::rules::{CssRule, CssRuleList};
+use lightningcss::stylesheet::{ParserOptions, PrinterOptions, StyleAttribute, StyleSheet};
+use lightningcss::traits::ToCss;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// What a style entry describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StyleEntryKind {
+    AtRule,
+    Rule,
+    Declaration,
+}
+
+/// An at-rule that a style entry is nested in, e.g. `@media (max-width: 600px)`.
+#[derive(Debug, Clone)]
+pub struct AtRule {
+    pub name: String,
+    pub prelude: String,
+}
+
+/// A single at-rule, style rule or declaration in a deliverable's stylesheets.
+#[derive(Debug, Clone)]
+pub struct StyleEntry {
+    pub kind: StyleEntryKind,
+    /// Path of the file, relative to the student directory.
+    pub file: PathBuf,
+    pub line: u32,
+    /// The at-rules the entry is nested in, outermost first. An at-rule entry includes itself.
+    pub at_rules: Vec<AtRule>,
+    /// The selector of the enclosing style rule. `None` for style attributes and at-rules.
+    pub selector: Option<String>,
+    pub property: Option<String>,
+    pub value: Option<String>,
+}
+
+impl StyleEntry {
+    /// Function to format the entry as a short snippet of CSS, to use as evidence.
+    pub fn snippet(&self) -> String {
+        let mut snippet = String::new();
+        let at_rules = match self.kind {
+            StyleEntryKind::AtRule => &self.at_rules[..self.at_rules.len().saturating_sub(1)],
+            _ => &self.at_rules[..],
+        };
+        for at_rule in at_rules {
+            snippet.push_str(&format!("@{} {} {{ ", at_rule.name, at_rule.prelude));
+        }
+
+        let body = match (self.kind, &self.selector, &self.property, &self.value) {
+            (StyleEntryKind::AtRule, ..) => {
+                let at_rule = self.at_rules.last().unwrap();
+                format!("@{} {} {{ … }}", at_rule.name, at_rule.prelude)
+            }
+            (StyleEntryKind::Rule, Some(selector), ..) => format!("{} {{ … }}", selector),
+            (_, Some(selector), Some(property), Some(value)) => {
+                format!("{} {{ {}: {} }}", selector, property, value)
+            }
+            (_, None, Some(property), Some(value)) => format!("style=\"{}: {}\"", property, value),
+            _ => String::new(),
+        };
+        snippet.push_str(body.trim());
+
+        for _ in at_rules {
+            snippet.push_str(" }");
+        }
+
+        format!("{}:{}: {}", self.file.display(), self.line, snippet)
+    }
+}
+
+/// Every at-rule, style rule and declaration in a deliverable's stylesheets, `<style>` elements and style attributes.
+#[derive(Debug, Default)]
+pub struct StyleIndex {
+    pub entries: Vec<StyleEntry>,
+}
+
+impl StyleIndex {
+    /// Function to parse every stylesheet in a deliverable, including the styles embedded in HTML files.
+    /// Libraries and template code from the starter are left out, since the student didn't write them.
+    pub fn build(
+        student_dir: &Path,
+        vendor: &VendorDetector,
+        starter_diff: Option<&StarterDiff>,
+    ) -> Result<StyleIndex, Box<dyn std::error::Error>> {
+        let mut index = StyleIndex::default();
+
+        for entry in WalkDir::new(student_dir) {
+            let entry = entry?;
+            let path = entry.path();
+            let relative_path = path.strip_prefix(student_dir).unwrap_or(path);
+
+            let extension = path.extension().and_then(|s| s.to_str());
+            if !path.is_file() || (extension != Some("css") && extension != Some("html")) {
+                continue;
+            }
+            if vendor.detect(relative_path, &fs::read(path)?).is_some() {
+                continue;
+            }
+            let content = match encoding::read_to_string(path) {
+                Ok(content) => content,
+                Err(_) => continue,
+            };
+
+            if extension == Some("css") {
+                index.add_stylesheet(relative_path, &content, 0);
+                continue;
+            }
+
+            for block in inline::extract_inline_blocks(&content) {
+                // Lines in the block are counted from the line it starts on in the HTML file
+                let (line, _) = line_column(&content, block.offset);
+                match block.kind {
+                    InlineKind::Style => {
+                        index.add_stylesheet(relative_path, &block.content, line - 1)
+                    }
+                    InlineKind::StyleAttribute => {
+                        if let Ok(attribute) =
+                            StyleAttribute::parse(&block.content, ParserOptions::default())
+                        {
+                            let context = Context {
+                                file: relative_path,
+                                line_offset: line - 1,
+                                at_rules: Vec::new(),
+                            };
+                            index.add_declarations(&context, &attribute.declarations, None, 0);
+                        }
+                    }
+                    InlineKind::Script => {}
+                }
+            }
+        }
+
+        // Styles in unchanged starter files, including their <style> elements and style attributes
+        if let Some(diff) = starter_diff {
+            index.entries.retain(|entry| !diff.is_template(&entry.file));
+        }
+
+        Ok(index)
+    }
+
+    fn add_stylesheet(&mut self, file: &Path, content: &str, line_offset: u32) {
+        let options = ParserOptions {
+            error_recovery: true,
+            ..ParserOptions::default()
+        };
+        if let Ok(stylesheet) = StyleSheet::parse(content, options) {
+            let context = Context {
+                file,
+                line_offset,
+                at_rules: Vec::new(),
+            };
+            self.add_rules(&context, &stylesheet.rules);
+        }
+    }
+
+    /// Function to recursively add every rule in a rule list, keeping track of the at-rules they are nested in.
+    fn add_rules<R>(&mut self, context: &Context, rules: &CssRuleList<'_, R>) {
+        for rule in &rules.0 {
+            let (name, prelude, line) = match rule {
+                CssRule::Style(style) => {
+                    let selector = to_css(&style.selectors);
+                    let line = style.loc.line;
+                    self.push(context, StyleEntryKind::Rule, line, Some(&selector), None);
+                    self.add_declarations(context, &style.declarations, Some(&selector), line);
+                    self.add_rules(context, &style.rules);
+                    continue;
+                }
+                CssRule::Nesting(nesting) => {
+                    self.add_declarations(
+                        context,
+                        &nesting.style.declarations,
+                        None,
+                        nesting.style.loc.line,
+                    );
+                    self.add_rules(context, &nesting.style.rules);
+                    continue;
+                }
+                CssRule::Media(media) => ("media", to_css(&media.query), media.loc.line),
+                CssRule::Supports(supports) => {
+                    ("supports", to_css(&supports.condition), supports.loc.line)
+                }
+                CssRule::Container(container) => (
+                    "container",
+                    container.condition.as_ref().map(to_css).unwrap_or_default(),
+                    container.loc.line,
+                ),
+                CssRule::LayerBlock(layer) => (
+                    "layer",
+                    layer.name.as_ref().map(to_css).unwrap_or_default(),
+                    layer.loc.line,
+                ),
+                CssRule::Scope(scope) => ("scope", String::new(), scope.loc.line),
+                CssRule::StartingStyle(starting) => {
+                    ("starting-style", String::new(), starting.loc.line)
+                }
+                CssRule::Keyframes(keyframes) => {
+                    ("keyframes", to_css(&keyframes.name), keyframes.loc.line)
+                }
+                CssRule::FontFace(font_face) => ("font-face", String::new(), font_face.loc.line),
+                CssRule::Import(import) => {
+                    ("import", format!("\"{}\"", import.url), import.loc.line)
+                }
+                CssRule::Page(page) => ("page", String::new(), page.loc.line),
+                _ => continue,
+            };
+
+            let mut nested = context.clone();
+            nested.at_rules.push(AtRule {
+                name: name.to_string(),
+                prelude,
+            });
+            self.push(&nested, StyleEntryKind::AtRule, line, None, None);
+
+            match rule {
+                CssRule::Media(media) => self.add_rules(&nested, &media.rules),
+                CssRule::Supports(supports) => self.add_rules(&nested, &supports.rules),
+                CssRule::Container(container) => self.add_rules(&nested, &container.rules),
+                CssRule::LayerBlock(layer) => self.add_rules(&nested, &layer.rules),
+                CssRule::Scope(scope) => self.add_rules(&nested, &scope.rules),
+                CssRule::StartingStyle(starting) => self.add_rules(&nested, &starting.rules),
+                CssRule::Keyframes(keyframes) => {
+                    for keyframe in &keyframes.keyframes {
+                        self.add_declarations(&nested, &keyframe.declarations, None, line);
+                    }
+                }
+                CssRule::Page(page) => {
+                    self.add_declarations(&nested, &page.declarations, None, line)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn add_declarations(
+        &mut self,
+        context: &Context,
+        declarations: &DeclarationBlock<'_>,
+        selector: Option<&str>,
+        line: u32,
+    ) {
+        for (property, important) in declarations.iter() {
+            let mut value = property
+                .value_to_css_string(PrinterOptions::default())
+                .unwrap_or_default();
+
+            // Browsers drop unknown properties and invalid values, so they don't count towards any requirement.
+            // Values using var() or env() can only be checked at runtime, and are kept.
+            let is_invalid = match property {
+                Property::Custom(custom) => matches!(custom.name, CustomPropertyName::Unknown(_)),
+                Property::Unparsed(_) => !value.contains("var(") && !value.contains("env("),
+                _ => false,
+            };
+            if is_invalid {
+                continue;
+            }
+
+            if important {
+                value.push_str(" !important");
+            }
+            let property = property.property_id().name().to_string();
+            self.push(
+                context,
+                StyleEntryKind::Declaration,
+                line,
+                selector,
+                Some((property, value)),
+            );
+        }
+    }
+
+    fn push(
+        &mut self,
+        context: &Context,
+        kind: StyleEntryKind,
+        line: u32,
+        selector: Option<&str>,
+        declaration: Option<(String, String)>,
+    ) {
+        let (property, value) = match declaration {
+            Some((property, value)) => (Some(property), Some(value)),
+            None => (None, None),
+        };
+        self.entries.push(StyleEntry {
+            kind,
+            file: context.file.to_path_buf(),
+            // Locations from the CSS parser are 0-based
+            line: context.line_offset + line + 1,
+            at_rules: context.at_rules.clone(),
+            selector: selector.map(str::to_string),
+            property,
+            value,
+        });
+    }
+}
+
+fn to_css<T: ToCss>(value: &T) -> String {
+    value
+        .to_css_string(PrinterOptions::default())
+        .unwrap_or_default()
+}
+
+/// Where the rules currently being indexed come from.
+#[derive(Clone)]
+struct Context<'a> {
+    file: &'a Path,
+    /// Lines before the stylesheet in its file, for `<style>` elements and style attributes.
+    line_offset: u32,
+    at_rules: Vec<AtRule>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::VendorConfig;
+
+    fn build(files: &[(&str, &str)]) -> StyleIndex {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            fs::write(dir.path().join(name), content).unwrap();
+        }
+        let vendor = VendorDetector::new(&VendorConfig::default()).unwrap();
+        StyleIndex::build(dir.path(), &vendor, None).unwrap()
+    }
+
+    fn snippets(index: &StyleIndex) -> Vec<String> {
+        index.entries.iter().map(StyleEntry::snippet).collect()
+    }
+
+    #[test]
+    fn stylesheets_are_indexed_with_their_at_rules_and_lines() {
+        let index = build(&[(
+            "style.css",
+            "nav a:hover {\n  color: red;\n  skrift: fet;\n}\n\n@media (max-width: 600px) {\n  nav {\n    display: none !important;\n  }\n}\n",
+        )]);
+
+        let nav = index
+            .entries
+            .iter()
+            .find(|entry| entry.property.as_deref() == Some("display"))
+            .unwrap();
+        assert_eq!(nav.kind, StyleEntryKind::Declaration);
+        assert_eq!(nav.line, 7);
+        assert_eq!(nav.at_rules.len(), 1);
+        assert_eq!(nav.at_rules[0].name, "media");
+
+        // The unknown property is left out, since browsers ignore it
+        let snippets = snippets(&index);
+        assert_eq!(snippets.len(), 5, "{:?}", snippets);
+        assert_eq!(snippets[0], "style.css:1: nav a:hover { … }");
+        assert_eq!(snippets[1], "style.css:1: nav a:hover { color: red }");
+        assert!(
+            snippets[2].starts_with("style.css:6: @media"),
+            "{:?}",
+            snippets
+        );
+        assert!(
+            snippets[4].ends_with("{ nav { display: none !important } }"),
+            "{:?}",
+            snippets
+        );
+    }
+
+    #[test]
+    fn embedded_styles_are_indexed_on_their_lines_in_the_page() {
+        let index = build(&[(
+            "index.html",
+            "<!DOCTYPE html>\n<html>\n<head>\n<style>\n  h1 { color: navy; }\n</style>\n</head>\n<body>\n<p style=\"margin: 0\">Hei</p>\n</body>\n</html>\n",
+        )]);
+
+        assert_eq!(
+            snippets(&index),
+            [
+                "index.html:5: h1 { … }",
+                "index.html:5: h1 { color: navy }",
+                "index.html:9: style=\"margin: 0\"",
+            ]
+        );
+    }
+}
@@ -0,0 +1,362 @@
+use crate::budget::{self, TokenCounter};
+use crate::llm::{ModelParams, TokenUsage};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Prices of OpenAI models in USD per million tokens, input and output, most specific names first.
+/// Set `input_price` and `output_price` in the config for other models, or when the prices change.
+const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4.1-nano", 0.1, 0.4),
+    ("gpt-4.1-mini", 0.4, 1.6),
+    ("gpt-4.1", 2.0, 8.0),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-4-32k", 60.0, 120.0),
+    ("gpt-4", 30.0, 60.0),
+    ("gpt-3.5-turbo", 0.5, 1.5),
+    ("o1-mini", 1.1, 4.4),
+    ("o1", 15.0, 60.0),
+];
+
+/// The price of a model, in USD per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+impl Price {
+    /// Function to find the price of a known OpenAI model.
+    pub fn of_model(model: &str) -> Option<Price> {
+        PRICES
+            .iter()
+            .find(|(name, _, _)| model.starts_with(name))
+            .map(|&(_, input, output)| Price { input, output })
+    }
+
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        (usage.prompt_tokens as f64 * self.input + usage.completion_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+/// What the model was asked to do, so the usage can be summed up for each student.
+#[derive(Debug, Clone, Copy)]
+pub enum Purpose<'a> {
+    Explanation,
+    Grading { student: &'a str },
+}
+
+/// Requests, tokens and cost, summed up.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub requests: usize,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    /// Cost in USD, of the requests to models with a known price.
+    pub cost: f64,
+}
+
+impl Usage {
+    fn add(&mut self, usage: &TokenUsage, cost: f64) {
+        self.requests += 1;
+        self.prompt_tokens += usage.prompt_tokens;
+        self.completion_tokens += usage.completion_tokens;
+        self.cost += cost;
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} requests, {} prompt tokens, {} completion tokens, ${:.4}",
+            self.requests, self.prompt_tokens, self.completion_tokens, self.cost
+        )
+    }
+}
+
+/// The usage of a run, stored as `usage.json` in the destination directory.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageReport {
+    /// The budget of the run in USD, if any.
+    pub budget: Option<f64>,
+    pub total: Usage,
+    /// Explaining validation messages.
+    pub explanations: Usage,
+    /// Grading each student's deliverable.
+    pub students: BTreeMap<String, Usage>,
+    /// Models without a known price, which aren't included in the cost.
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub unpriced_models: BTreeSet<String>,
+}
+
+/// The error when a request is refused, because it could take the run over budget.
+#[derive(Debug)]
+pub struct BudgetExceeded {
+    pub budget: f64,
+    pub spent: f64,
+    /// Set aside for requests that haven't finished yet.
+    pub reserved: f64,
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The next request could take the run over its budget of ${} (${:.4} spent",
+            self.budget, self.spent
+        )?;
+        if self.reserved > 0.0 {
+            write!(
+                f,
+                ", ${:.4} set aside for requests in progress",
+                self.reserved
+            )?;
+        }
+        write!(f, "), so no more requests are sent")
+    }
+}
+
+impl Error for BudgetExceeded {}
+
+/// The cost set aside for a request while it is in progress, from `UsageLedger::check`.
+/// It is given back with `record` once the usage is known, or with `release` if the request failed.
+#[must_use]
+#[derive(Debug)]
+pub struct Reservation {
+    cost: f64,
+}
+
+/// Keeps track of the tokens used during a run, and stops requests that could take it over budget.
+pub struct UsageLedger {
+    report: RefCell<UsageReport>,
+    /// The cost set aside for requests in progress, so requests sent at the same time can't overspend together.
+    reserved: Cell<f64>,
+    exhausted: Cell<bool>,
+}
+
+impl UsageLedger {
+    pub fn new(budget: Option<f64>) -> Self {
+        UsageLedger {
+            report: RefCell::new(UsageReport {
+                budget,
+                ..UsageReport::default()
+            }),
+            reserved: Cell::new(0.0),
+            exhausted: Cell::new(false),
+        }
+    }
+
+    /// Function to check that a prompt can be sent without going over budget, and set its cost aside until
+    /// the usage is known. The response isn't known yet, so it is counted as `max_tokens`, or the default
+    /// room for the response if that isn't set. Once a request is refused, no more are sent.
+    pub fn check(&self, params: &ModelParams, prompt: &str) -> Result<Reservation, BudgetExceeded> {
+        let report = self.report.borrow();
+        let Some(budget) = report.budget else {
+            return Ok(Reservation { cost: 0.0 });
+        };
+        let spent = report.total.cost;
+        let reserved = self.reserved.get();
+        let cost = params.price.map_or(0.0, |price| {
+            price.cost(&TokenUsage {
+                prompt_tokens: TokenCounter::new(&params.model).count(prompt),
+                completion_tokens: budget::response_tokens(params),
+            })
+        });
+
+        if self.exhausted.get() || spent + reserved + cost > budget {
+            self.exhausted.set(true);
+            return Err(BudgetExceeded {
+                budget,
+                spent,
+                reserved,
+            });
+        }
+        self.reserved.set(reserved + cost);
+        Ok(Reservation { cost })
+    }
+
+    /// Function to give back the cost set aside for a request that failed.
+    pub fn release(&self, reservation: Reservation) {
+        self.reserved
+            .set((self.reserved.get() - reservation.cost).max(0.0));
+    }
+
+    /// Function to record the usage of a finished request, in place of the cost set aside for it.
+    pub fn record(
+        &self,
+        params: &ModelParams,
+        purpose: Purpose,
+        usage: &TokenUsage,
+        reservation: Reservation,
+    ) {
+        self.release(reservation);
+        let mut report = self.report.borrow_mut();
+        let cost = match params.price {
+            Some(price) => price.cost(usage),
+            None => {
+                report.unpriced_models.insert(params.model.clone());
+                0.0
+            }
+        };
+
+        report.total.add(usage, cost);
+        match purpose {
+            Purpose::Explanation => report.explanations.add(usage, cost),
+            Purpose::Grading { student } => report
+                .students
+                .entry(student.to_string())
+                .or_default()
+                .add(usage, cost),
+        }
+    }
+
+    pub fn report(&self) -> UsageReport {
+        self.report.borrow().clone()
+    }
+
+    /// Function to estimate what sending `prompts` will cost, counting only the prompts, and print it.
+    pub fn print_estimate(&self, what: &str, params: &ModelParams, prompts: &[String]) {
+        let counter = TokenCounter::new(&params.model);
+        let usage = TokenUsage {
+            prompt_tokens: prompts.iter().map(|prompt| counter.count(prompt)).sum(),
+            completion_tokens: 0,
+        };
+
+        let Some(price) = params.price else {
+            println!(
+                "> Estimate for {}: {} requests to {}, {} prompt tokens, at an unknown price",
+                what,
+                prompts.len(),
+                params.model,
+                usage.prompt_tokens
+            );
+            return;
+        };
+        let cost = price.cost(&usage);
+        println!(
+            "> Estimate for {}: {} requests to {}, {} prompt tokens, ${:.4} before the responses",
+            what,
+            prompts.len(),
+            params.model,
+            usage.prompt_tokens,
+            cost
+        );
+
+        let report = self.report.borrow();
+        if let Some(budget) = report.budget {
+            let left = budget - report.total.cost;
+            if cost > left {
+                eprintln!(
+                    "> The estimate is over what is left of the budget (${:.4} of ${}), so not everything will be sent",
+                    left.max(0.0), budget
+                );
+            }
+        }
+    }
+}
+
+impl UsageReport {
+    pub fn write(&self, destination_dir: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(
+            destination_dir.join("usage.json"),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    pub fn print_summary(&self) {
+        println!("> Usage: {}", self.total);
+        if self.explanations.requests > 0 {
+            println!(">   Explaining validation messages: {}", self.explanations);
+        }
+        if !self.students.is_empty() {
+            let mut grading = Usage::default();
+            for usage in self.students.values() {
+                grading.requests += usage.requests;
+                grading.prompt_tokens += usage.prompt_tokens;
+                grading.completion_tokens += usage.completion_tokens;
+                grading.cost += usage.cost;
+            }
+            println!(
+                ">   Grading {} deliverables: {}, ${:.4} per deliverable",
+                self.students.len(),
+                grading,
+                grading.cost / self.students.len() as f64
+            );
+        }
+        if let Some(budget) = self.budget {
+            println!(
+                ">   Budget: ${}, ${:.4} left",
+                budget,
+                (budget - self.total.cost).max(0.0)
+            );
+        }
+        for model in &self.unpriced_models {
+            eprintln!(
+                "> No price is known for {}, so it isn't included in the cost. Set input_price and output_price in the config.",
+                model
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> ModelParams {
+        ModelParams {
+            model: "gpt-4o".to_string(),
+            temperature: None,
+            max_tokens: Some(1000),
+            context_window: None,
+            // $1 per thousand tokens, in and out
+            price: Some(Price {
+                input: 1000.0,
+                output: 1000.0,
+            }),
+        }
+    }
+
+    #[test]
+    fn requests_in_progress_count_against_the_budget() {
+        let params = params();
+        // Each request reserves about $1: a short prompt and 1000 tokens of response
+        let ledger = UsageLedger::new(Some(2.5));
+
+        let first = ledger.check(&params, "Hei").unwrap();
+        let second = ledger.check(&params, "Hei").unwrap();
+        let refused = ledger.check(&params, "Hei").unwrap_err();
+        assert!(refused.reserved > 2.0 && refused.spent == 0.0);
+
+        let usage = TokenUsage {
+            prompt_tokens: 100,
+            completion_tokens: 100,
+        };
+        ledger.record(&params, Purpose::Grading { student: "ola" }, &usage, first);
+        ledger.release(second);
+        assert!((ledger.report().total.cost - 0.2).abs() < 1e-9);
+        assert_eq!(ledger.report().students["ola"].requests, 1);
+        // Refused once, so no more requests are sent
+        assert!(ledger.check(&params, "Hei").is_err());
+    }
+
+    #[test]
+    fn without_max_tokens_the_default_response_is_reserved() {
+        let params = ModelParams {
+            max_tokens: None,
+            ..params()
+        };
+        // The default response of 2000 tokens costs $2
+        assert!(UsageLedger::new(Some(1.5)).check(&params, "Hei").is_err());
+        assert!(UsageLedger::new(Some(2.5)).check(&params, "Hei").is_ok());
+    }
+}
@@ -1,23 +1,95 @@
+use crate::accessibility;
+use crate::config::SimilarityConfig;
+use crate::css::{self, CssBackend};
+use crate::encoding;
+use crate::explain::{self, ExplanationCache};
+use crate::filter::{IssueFilter, SuppressionSummary};
+use crate::inline::{self, InlineKind};
+use crate::issue::{Issue, IssueSource, Severity};
+use crate::js::{self, ScriptContext};
+use crate::js_tests::TestSuite;
+use crate::llm::Llm;
+use crate::prompts::{self, Phrases, Prompts};
+use crate::references;
+use crate::requirements::RuleSet;
 use crate::schemas::ValidationResult;
-use openai_api_rs::v1::api::OpenAIClient;
-use openai_api_rs::v1::chat_completion::{
-    self, ChatCompletionMessage, ChatCompletionRequest, MessageRole,
-};
-use openai_api_rs::v1::common::GPT4_O_MINI;
+use crate::similarity;
+use crate::starter::{StarterCode, StarterDiff};
+use crate::stats;
+use crate::structure::StructureManifest;
+use crate::vendor::{ExcludedFile, VendorDetector};
 use reqwest::Client;
-use std::env;
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::fs::File;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
+/// Options controlling how deliverables are validated.
+#[derive(Debug, Clone)]
+pub struct ValidationOptions {
+    pub css_backend: CssBackend,
+    pub filter: IssueFilter,
+    /// The files and folders required or forbidden in the assignment's deliverables.
+    pub structure: Option<StructureManifest>,
+    /// Where explanations of validator messages are cached between runs.
+    pub explanation_cache: PathBuf,
+    /// How deliverables are compared with each other.
+    pub similarity: SimilarityConfig,
+    /// Tells libraries and minified files apart from the student's code.
+    pub vendor: VendorDetector,
+    /// The prompts and language of the explanations, and of the feedback.
+    pub prompts: Prompts,
+    /// Requirement rules to check each deliverable against.
+    pub requirements: Option<RuleSet>,
+    /// Test cases to run the students' JavaScript against.
+    pub js_tests: Option<TestSuite>,
+    /// The starter code, to tell template code apart from what the student wrote.
+    pub starter: Option<StarterCode>,
+}
+
+/// The validation results for a student, stored as `validation.json` in the student directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+    /// How many issues were suppressed, by the rule that suppressed them.
+    pub suppressed: SuppressionSummary,
+    /// Files that couldn't be validated, e.g. because the validator was unreachable.
+    #[serde(default)]
+    pub unvalidated: Vec<UnvalidatedFile>,
+    /// How many issues were left out because they are in unchanged starter code.
+    #[serde(default)]
+    pub template_issues: usize,
+    /// Libraries and minified files, which are neither validated nor graded.
+    #[serde(default)]
+    pub excluded: Vec<ExcludedFile>,
+}
+
+/// A file that couldn't be validated, and why.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnvalidatedFile {
+    /// Path of the file, relative to the student directory.
+    pub file: PathBuf,
+    pub reason: String,
+}
+
+impl ValidationReport {
+    /// Function to read the validation report of a student, if validation has been run.
+    pub fn read(project_dir: &Path) -> Option<ValidationReport> {
+        let report = fs::read_to_string(project_dir.join("validation.json")).ok()?;
+        serde_json::from_str(&report).ok()
+    }
+}
+
 /// Function to traverse a directory and validate HTML, CSS, and JS files.
 pub async fn validate_directory(
     destination_dir: &Path,
-    with_ai: bool,
+    llm: Option<&Llm>,
+    options: &ValidationOptions,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let client = Client::new();
+    let mut reports = Vec::new();
 
     let deliverables_dir = destination_dir.join("deliverables");
     for entry in fs::read_dir(&deliverables_dir)? {
@@ -26,26 +98,114 @@ pub async fn validate_directory(
 
         if student_dir.is_dir() {
             println!("Validating student directory: {}", student_dir.display());
+            let starter_diff = match &options.starter {
+                Some(starter) => {
+                    println!("> Comparing to the starter code");
+                    Some(starter.diff(&student_dir)?)
+                }
+                None => None,
+            };
+
             // Collect validation issues for the student directory
-            let validation_issues = collect_and_validate_files(&student_dir, &client).await?;
+            let (validation_issues, unvalidated, excluded) =
+                collect_and_validate_files(&student_dir, &client, options, starter_diff.as_ref())
+                    .await?;
+
+            let (validation_issues, template_issues) = match &starter_diff {
+                Some(diff) => diff.remove_template_issues(validation_issues),
+                None => (validation_issues, 0),
+            };
+            if template_issues > 0 {
+                println!(
+                    "> Left out {} issues in unchanged starter code",
+                    template_issues
+                );
+            }
+
+            let (validation_issues, suppressed) = options.filter.apply(validation_issues);
+
+            if suppressed.total() > 0 {
+                let rules: Vec<String> = suppressed
+                    .0
+                    .iter()
+                    .map(|(rule, count)| format!("{} by “{}”", count, rule))
+                    .collect();
+                println!(
+                    "> Suppressed {} issues: {}",
+                    suppressed.total(),
+                    rules.join(", ")
+                );
+            }
 
-            // If with_ai is true, generate AI feedback and write to validate.txt
-            if with_ai {
-                validate_with_ai(&student_dir, &validation_issues).await?;
+            let report = ValidationReport {
+                issues: validation_issues,
+                suppressed,
+                unvalidated,
+                template_issues,
+                excluded,
+            };
+            write_report(&student_dir, &report)?;
+            // Written after the checks, so the diff isn't counted as a file in the deliverable
+            if let Some(diff) = &starter_diff {
+                diff.write(&student_dir)?;
             }
+
+            if let Some(rules) = &options.requirements {
+                println!("> Checking requirements");
+                rules
+                    .evaluate(&student_dir, &options.vendor, starter_diff.as_ref())
+                    .write(&student_dir, options.prompts.phrases())?;
+            }
+
+            if let Some(suite) = &options.js_tests {
+                println!("> Running JS tests");
+                suite
+                    .run(&student_dir)
+                    .write(&student_dir, options.prompts.phrases())?;
+            }
+
+            reports.push((student_dir, report));
         }
     }
 
+    stats::write_class_report(destination_dir, &reports)?;
+
+    if options.similarity.enabled {
+        let student_dirs: Vec<PathBuf> = reports.iter().map(|(dir, _)| dir.clone()).collect();
+        similarity::write_similarity_report(
+            destination_dir,
+            &student_dirs,
+            options.starter.as_ref(),
+            &options.vendor,
+            &options.similarity,
+        )?;
+    }
+
+    // If a language model is given, generate AI feedback and write to validate.txt
+    if let Some(llm) = llm {
+        validate_with_ai(&reports, options, llm).await?;
+    }
+
     Ok(())
 }
 
 async fn collect_and_validate_files(
     student_dir: &Path,
     client: &Client,
-) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    options: &ValidationOptions,
+    starter_diff: Option<&StarterDiff>,
+) -> Result<(Vec<Issue>, Vec<UnvalidatedFile>, Vec<ExcludedFile>), Box<dyn std::error::Error>> {
     let mut validation_issues = Vec::new();
+    let mut unvalidated = Vec::new();
+    let mut excluded = Vec::new();
+    let script_context = build_script_context(student_dir)?;
 
-    for entry in WalkDir::new(&student_dir) {
+    if let Some(structure) = &options.structure {
+        println!("> Checking project structure");
+        validation_issues.extend(structure.check(student_dir)?);
+    }
+
+    for entry in WalkDir::new(student_dir) {
         let entry = entry?;
         let path = entry.path();
 
@@ -55,23 +215,87 @@ async fn collect_and_validate_files(
                 || filename.ends_with(".css")
                 || filename.ends_with(".js")
             {
+                let relative_path = path.strip_prefix(student_dir).unwrap_or(path);
+                if starter_diff.is_some_and(|diff| diff.is_template(relative_path)) {
+                    println!("> Skipping unchanged starter code: {}", path.display());
+                    continue;
+                }
+                if let Some(reason) = options.vendor.detect(relative_path, &fs::read(path)?) {
+                    println!("> Skipping {} ({})", path.display(), reason);
+                    excluded.push(ExcludedFile {
+                        file: relative_path.to_path_buf(),
+                        reason,
+                    });
+                    continue;
+                }
+
                 println!("> Validating file: {}", path.display());
-                let issues = validate_file(&path, client).await?;
-                validation_issues.extend(issues);
+                match validate_file(student_dir, path, client, options, &script_context).await {
+                    Ok(issues) => validation_issues.extend(issues),
+                    // A single file failing shouldn't stop the rest of the class from being validated
+                    Err(e) => {
+                        eprintln!("> Could not validate {}: {}", path.display(), e);
+                        unvalidated.push(UnvalidatedFile {
+                            file: relative_path.to_path_buf(),
+                            reason: e.to_string(),
+                        });
+                    }
+                }
             }
         }
     }
 
-    Ok(validation_issues)
+    println!("> Checking references to local files");
+    validation_issues.extend(references::check_references(student_dir, &options.vendor)?);
+
+    println!("> Auditing accessibility");
+    validation_issues.extend(accessibility::check_accessibility(
+        student_dir,
+        &options.vendor,
+    )?);
+
+    Ok((validation_issues, unvalidated, excluded))
+}
+
+/// Function to gather the scripts and HTML files of a deliverable, so scripts can be checked against each other.
+fn build_script_context(student_dir: &Path) -> Result<ScriptContext, Box<dyn std::error::Error>> {
+    let mut context = ScriptContext::default();
+
+    for entry in WalkDir::new(student_dir) {
+        let entry = entry?;
+        let path = entry.path();
+        let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+
+        if !path.is_file() || (extension != "js" && extension != "html") {
+            continue;
+        }
+
+        if let Ok(content) = encoding::read_to_string(path) {
+            if extension == "js" {
+                context.add_script(&content);
+            } else {
+                for block in inline::extract_inline_blocks(&content) {
+                    if block.kind == InlineKind::Script {
+                        context.add_script(&block.content);
+                    }
+                }
+            }
+            context.add_source(&content);
+        }
+    }
+
+    Ok(context)
 }
 
 async fn validate_file(
+    student_dir: &Path,
     file_path: &Path,
     client: &Client,
-) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let mut validation_issues = Vec::new();
-
+    options: &ValidationOptions,
+    script_context: &ScriptContext,
+) -> Result<Vec<Issue>, Box<dyn std::error::Error>> {
     let filename = file_path.to_str().unwrap();
+    let relative_path = file_path.strip_prefix(student_dir).unwrap_or(file_path);
 
     // Determine content type based on file extension
     let content_type = if filename.ends_with(".html") {
@@ -81,12 +305,32 @@ async fn validate_file(
     } else if filename.ends_with(".js") {
         "text/javascript"
     } else {
-        return Ok(validation_issues);
+        return Ok(Vec::new());
     };
 
+    let text = encoding::read_text(file_path)?;
+    let mut validation_issues: Vec<Issue> = encoding::check_encoding(relative_path, &text)
+        .into_iter()
+        .collect();
+    let content = text.content;
+
+    if content_type == "text/css" {
+        let (issues, response) = validate_css(relative_path, &content, client, options).await?;
+        if let Some(response) = response {
+            write_response(file_path, &response)?;
+        }
+        validation_issues.extend(issues);
+        return Ok(validation_issues);
+    }
+
+    if content_type == "text/javascript" {
+        println!("> Analysing script: {}", filename);
+        validation_issues.extend(js::validate_js(relative_path, &content, script_context));
+        return Ok(validation_issues);
+    }
+
     println!("> Posting file to W3 Validator: {}", filename);
 
-    let content = fs::read_to_string(file_path)?;
     let response = client
         .post("https://validator.w3.org/nu/?out=json")
         .header("Content-Type", format!("{}; charset=utf-8", content_type))
@@ -97,85 +341,223 @@ async fn validate_file(
         .text()
         .await?;
 
-    let validate_file_path = file_path.with_extension("json");
-    let mut file = File::create(&validate_file_path)?;
-    file.write_all(response.as_bytes())?;
-
-    println!("> Wrote response to {:?}", validate_file_path);
+    write_response(file_path, &response)?;
 
     // Parse the validation response and collect issues
     let validation_result: ValidationResult = serde_json::from_str(&response)?;
 
-    for message in validation_result.messages {
-        if message.message_type == "error" || message.subtype.as_deref() == Some("warning") {
-            validation_issues.push(format!("{}", message.message));
+    validation_issues.extend(
+        validation_result
+            .messages
+            .into_iter()
+            .map(|message| Issue::from_nu_message(relative_path, message)),
+    );
+
+    validation_issues.extend(
+        validate_inline_blocks(relative_path, &content, client, options, script_context).await,
+    );
+
+    Ok(validation_issues)
+}
+
+/// Function to validate a stylesheet with the configured CSS backend.
+/// Also returns the raw validator response, if the backend produced one.
+async fn validate_css(
+    file: &Path,
+    content: &str,
+    client: &Client,
+    options: &ValidationOptions,
+) -> Result<(Vec<Issue>, Option<String>), Box<dyn std::error::Error>> {
+    match options.css_backend {
+        CssBackend::W3c => {
+            let (issues, response) = css::validate_css_w3c(file, content, client).await?;
+            Ok((issues, Some(response)))
         }
+        CssBackend::Builtin => Ok((css::validate_css_builtin(file, content), None)),
     }
+}
 
-    Ok(validation_issues)
+/// Function to validate the `<style>` elements, inline `<script>` elements and `style` attributes of an HTML file.
+/// Issues are reported against the line they appear on in the HTML file.
+/// A block the validator can't be reached for is reported as an issue, so the rest of the file is still validated.
+async fn validate_inline_blocks(
+    file: &Path,
+    html: &str,
+    client: &Client,
+    options: &ValidationOptions,
+    script_context: &ScriptContext,
+) -> Vec<Issue> {
+    let mut validation_issues = Vec::new();
+
+    let (attributes, blocks): (Vec<_>, Vec<_>) = inline::extract_inline_blocks(html)
+        .into_iter()
+        .partition(|block| block.kind == InlineKind::StyleAttribute);
+
+    for block in blocks {
+        let issues = match block.kind {
+            InlineKind::Style => match validate_css(file, &block.content, client, options).await {
+                Ok((issues, _)) => issues,
+                Err(e) => vec![unvalidated_block(file, "<style> element", e)],
+            },
+            InlineKind::Script => js::validate_js(file, &block.content, script_context),
+            InlineKind::StyleAttribute => continue,
+        };
+
+        validation_issues.extend(
+            issues
+                .into_iter()
+                .map(|issue| inline::map_to_host(issue, file, html, &block)),
+        );
+    }
+
+    // Every style attribute of the file is validated in one request
+    if !attributes.is_empty() {
+        let sheet = inline::AttributeSheet::new(&attributes);
+        match validate_css(file, &sheet.css, client, options).await {
+            Ok((issues, _)) => validation_issues.extend(issues.into_iter().filter_map(|issue| {
+                let (attribute, issue) = sheet.locate(issue)?;
+                Some(inline::map_to_host(issue, file, html, attribute))
+            })),
+            Err(e) => validation_issues.push(inline::map_to_host(
+                unvalidated_block(file, "style attributes", e),
+                file,
+                html,
+                &attributes[0],
+            )),
+        }
+    }
+
+    validation_issues
 }
 
-async fn validate_with_ai(
+/// Function to report an inline block that couldn't be validated.
+fn unvalidated_block(file: &Path, block: &str, error: Box<dyn std::error::Error>) -> Issue {
+    eprintln!(
+        "> Could not validate the {} of {}: {}",
+        block,
+        file.display(),
+        error
+    );
+    Issue::new(
+        IssueSource::Css,
+        Severity::Warning,
+        file,
+        format!("Could not validate the {}: {}", block, error),
+    )
+}
+
+/// Function to store a raw validator response next to the validated file.
+fn write_response(file_path: &Path, response: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let validate_file_path = file_path.with_extension("json");
+    let mut file = File::create(&validate_file_path)?;
+    file.write_all(response.as_bytes())?;
+
+    println!("> Wrote response to {:?}", validate_file_path);
+
+    Ok(())
+}
+
+/// Function to write the validation report for a student to `validation.json`.
+fn write_report(
     project_dir: &Path,
-    validation_issues: &Vec<String>,
+    report: &ValidationReport,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let client = OpenAIClient::new(env::var("OPENAI_API_KEY")?.to_string());
+    let report_file_path = project_dir.join("validation.json");
+    fs::write(&report_file_path, serde_json::to_string_pretty(report)?)?;
 
-    let feedback_file_path = project_dir.join("validate.txt");
+    println!("> Wrote validation report to {:?}", report_file_path);
 
-    let feedback = if validation_issues.is_empty() {
-        "Ingen formelle feil funnet.".to_string()
-    } else {
-        // Construct the AI prompt
-        let prompt = format!(
-            "Du har mottatt en liste med HTML/CSS/JS-valideringsfeil og advarsler fra en W3C Validator. \
-            For hver feilmelding, vennligst gi en kort forklaring på hva feilen betyr og et eksempel på hvordan man kan fikse det. \
-            Forklaringen skal IKKE formuleres som 'Feilmeldingen indikerer at ...', men heller en direkte, kort og konsis forklaring. \
-            Når du omtaler begreper innen HTML, CSS, og JS, som for eksempel 'table' etc., sørg for å bruke de engelske begrepene. \
-            IKKE gjenfortell feilmeldingen. Forklar kun hva feilen betyr. \
-            IKKE list opp feilene som en punktliste, men skriv en sammenhengende tekst med nye linjer mellom feil. \
-            DERSOM det ikke er noen feilmeldinger, skriv 'Ingen formelle feil funnet'. \
-            Hold eksempelet så kort som mulig (maks 1-5 linjer). \
-            Bruk 'de' og 'dere' i flertall for å referere til studentene, ikke 'studentene'. \
-            Avslutt svaret ditt med følgende setning: 'Det anbefales å bruke W3 Validator for å sjekke at HTML, CSS og JS oppfyller beste praksis.'. \
-            Svarene skal være på norsk.\n\nFeilmeldinger:\n\n{}",
-            validation_issues.join("\n\n")
-        );
+    Ok(())
+}
 
-        let req = ChatCompletionRequest::new(
-            GPT4_O_MINI.to_string(),
-            vec![ChatCompletionMessage {
-                role: MessageRole::user,
-                content: chat_completion::Content::Text(prompt),
-                name: None,
-                tool_calls: None,
-                tool_call_id: None,
-            }],
-        );
+/// Function to explain every distinct validation message once, and assemble each student's `validate.txt` from the explanations.
+/// Only messages that haven't been explained in a previous run are sent to the model.
+async fn validate_with_ai(
+    reports: &[(PathBuf, ValidationReport)],
+    options: &ValidationOptions,
+    llm: &Llm,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut cache = ExplanationCache::load(&options.explanation_cache)?;
 
-        let result = client.chat_completion(req).await?;
-        result
-            .choices
-            .get(0)
-            .and_then(|choice| choice.message.content.as_deref())
-            .unwrap_or("Ingen formelle feil funnet.")
-            .to_string()
-    };
+    let mut templates: Vec<String> = reports
+        .iter()
+        .flat_map(|(_, report)| {
+            report
+                .issues
+                .iter()
+                .map(|issue| explain::template(&issue.message))
+        })
+        .collect();
+    templates.sort();
+    templates.dedup();
 
-    // Write the feedback to the student's feedback file
-    let mut feedback_file = File::create(&feedback_file_path)?;
-    writeln!(
-        feedback_file,
-        "Tilbakemelding om validering: \n\n{}",
-        feedback
-    )?;
+    cache
+        .explain_missing(llm, &options.prompts.explanation, &templates)
+        .await?;
+
+    for (project_dir, report) in reports {
+        let feedback = assemble_feedback(&report.issues, &cache, options.prompts.phrases());
+        let feedback = options.prompts.validation_feedback.render(&[
+            ("student", &prompts::student_name(project_dir)),
+            ("assignment", &prompts::assignment_name(project_dir)),
+            ("feedback", &feedback),
+        ]);
 
-    // Also write the same feedback to final.txt (overwriting if it exists)
-    let final_file_path = project_dir.join("final.txt");
-    let mut final_file = File::create(&final_file_path)?;
-    writeln!(final_file, "Tilbakemelding om validering: \n\n{}", feedback)?;
+        // Write the feedback to the student's feedback file
+        let feedback_file_path = project_dir.join("validate.txt");
+        let mut feedback_file = File::create(&feedback_file_path)?;
+        writeln!(feedback_file, "{}", feedback)?;
 
-    println!("Feedback written to {:?}", &feedback_file_path);
+        // Also write the same feedback to final.txt (overwriting if it exists)
+        let final_file_path = project_dir.join("final.txt");
+        let mut final_file = File::create(&final_file_path)?;
+        writeln!(final_file, "{}", feedback)?;
+
+        println!("Feedback written to {:?}", &feedback_file_path);
+    }
 
     Ok(())
 }
+
+/// Function to assemble the validation feedback for a student, with one explanation per distinct message.
+fn assemble_feedback(
+    validation_issues: &[Issue],
+    cache: &ExplanationCache,
+    phrases: &Phrases,
+) -> String {
+    if validation_issues.is_empty() {
+        return phrases.no_issues.to_string();
+    }
+
+    // Group the issues by template, in the order they were first found
+    let mut groups: Vec<(String, Vec<&Issue>)> = Vec::new();
+    for issue in validation_issues {
+        let template = explain::template(&issue.message);
+        match groups.iter_mut().find(|(t, _)| *t == template) {
+            Some((_, issues)) => issues.push(issue),
+            None => groups.push((template, vec![issue])),
+        }
+    }
+
+    let mut sections = Vec::new();
+    for (template, issues) in groups {
+        let explanation = cache.get(&template).unwrap_or(&issues[0].message);
+        let mut locations: Vec<String> = issues
+            .iter()
+            .map(|issue| match issue.line {
+                Some(line) => format!("{} ({} {})", issue.file.display(), phrases.line, line),
+                None => issue.file.display().to_string(),
+            })
+            .collect();
+        locations.dedup();
+
+        sections.push(format!(
+            "{}\n{}: {}",
+            explanation,
+            phrases.applies_to,
+            locations.join(", ")
+        ));
+    }
+
+    sections.join("\n\n")
+}
@@ -0,0 +1,447 @@
+use crate::config::VendorConfig;
+use base64::Engine;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Names of common libraries, which students include rather than write.
+const LIBRARIES: &[&str] = &[
+    "jquery",
+    "bootstrap",
+    "font-awesome",
+    "fontawesome",
+    "popper",
+    "lodash",
+];
+
+/// Names of common libraries that students also give their own files, e.g. `chart.js` or `reset.css`.
+/// They are only taken as libraries with a version number, e.g. `normalize-8.0.1.css`.
+const GENERIC_LIBRARIES: &[&str] = &["normalize", "reset", "chart"];
+
+/// Subresource integrity hashes of common jQuery, Bootstrap and Font Awesome builds, as served by their CDNs.
+/// Copies of them are taken as libraries whatever they are named.
+const KNOWN_LIBRARIES: &[(&str, &str)] = &[
+    ("jQuery 3.7.1", "sha256-/JqT3SQfawRcv/BIHPThkBvs0OEvtFFmqPF/lYI/Cxo="),
+    ("jQuery 3.7.1 slim", "sha256-kmHvs0B+OpCW5GVHUNjv9rOmY0IvSIRcf7zGUDTDQM8="),
+    ("jQuery 3.7.0", "sha256-2Pmvv0kuTBOenSvLm6bvfBSSHrUJ+3A7x6P5Ebd07/g="),
+    ("jQuery 3.6.4", "sha256-oP6HI9z1XaZNBrJURtCoUT5SUnxFr8s3BzRl+cbzUq8="),
+    ("jQuery 3.6.3", "sha256-pvPw+upLPUjgMXY0G+8O0xUf+/Im1MZjXxxgOcBQBXU="),
+    ("jQuery 3.6.1", "sha256-o88AwQnZB+VDvE9tvIXrMQaPlFFSUTR+nldQm1LuPXQ="),
+    ("jQuery 3.6.0", "sha256-/xUj+3OJU5yExlq6GSYGSHk7tPXikynS7ogEvDej/m4="),
+    ("jQuery 3.6.0 slim", "sha256-u7e5khyithlIdTpu22PHhENmPcRdFiHRjhAuHcs05RI="),
+    ("jQuery 3.5.1", "sha256-9/aliU8dGd2tb6OSsuzixeV4y/faTqgFtohetphbbj0="),
+    ("jQuery 3.5.1 slim", "sha384-DfXdz2htPH0lsSSs5nCTpuj/zy4C+OGpamoFVy38MVBnE+IbbVYUew+OrCXaRkfj"),
+    ("jQuery 3.4.1", "sha256-CSXorXvZcTkaix6Yvo6HppcZGetbYMGWSFlBw8HfCJo="),
+    ("jQuery 3.3.1", "sha256-FgpCb/KJQlLNfOu91ta32o/NMZxltwRo8QtmkMRdAu8="),
+    ("jQuery 3.3.1 slim", "sha384-q8i/X+965DzO0rT7abK41JStQIAqVgRVzpbzo5smXKp4YfRvH+8abtTE1Pi6jizo"),
+    ("jQuery 3.2.1", "sha256-hwg4gsxgFZhOsEEamdOYGBf13FyQuiTwlAQgxVSNgt4="),
+    ("jQuery 3.2.1 slim", "sha384-KJ3o2DKtIkvYIK3UENzmM7KCkRr/rE9/Qpg6aAZGJwFDMVNA/GpGFF93hXpG5KkN"),
+    ("Bootstrap 5.3.3 CSS", "sha384-QWTKZyjpPEjISv5WaRU9OFeRpok6YctnYmDr5pNlyT2bRjXh0JMhjY6hW+ALEwIH"),
+    ("Bootstrap 5.3.3 JS bundle", "sha384-YvpcrYf0tY3lHB60NNkmXc5s9fDVZLESaAA55NDzOxhy9GkcIdslK1eN7N6jIeHz"),
+    ("Bootstrap 5.3.2 CSS", "sha384-T3c6CoIi6uLrA9TneNEoa7RxnatzjcDSCmG1MXxSR1GAsXEV/Dwwykc2MPK8M2HN"),
+    ("Bootstrap 5.3.2 JS bundle", "sha384-C6RzsynM9kWDrMNeT87bh95OGNyZPhcTNXj1NW7RuBCsyN/o0jlpcV8Qyq46cDfL"),
+    ("Bootstrap 5.3.1 CSS", "sha384-4bw+/aepP/YC94hEpVNVgiZdgIC5+VKNBQNGCHeKRQN+PtmoHDEXuppvnDJzQIu9"),
+    ("Bootstrap 5.3.1 JS bundle", "sha384-HwwvtgBNo3bZJJLYd8oVXjrBZt8cqVSpeBNS5n7C8IVInixGAoxmnlMuBnhbgrkm"),
+    ("Bootstrap 5.3.0 CSS", "sha384-9ndCyUaIbzAi2FUVXJi0CjmCapSmO7SnpJef0486qhLnuZ2cdeRhO02iuK6FUUVM"),
+    ("Bootstrap 5.3.0 JS bundle", "sha384-geWF76RCwLtnZ8qwWowPQNguL3RmwHVBC9FhGdlKrxdiJJigb/j/68SIy3Te4Bkz"),
+    ("Bootstrap 5.2.3 CSS", "sha384-rbsA2VBKQhggwzxH7pPCaAqO46MgnOM80zW1RWuH61DGLwZJEdK2Kadq2F9CUG65"),
+    ("Bootstrap 5.2.3 JS bundle", "sha384-kenU1KFdBIe4zVF0s0G1M5b4hcpxyD9F7jL+jjXkk+Q2h455rYXK/7HAuoJl+0I4"),
+    ("Bootstrap 5.1.3 CSS", "sha384-1BmE4kWBq78iYhFldvKuhfTAU6auU8tT94WrHftjDbrCEXSU1oBoqyl2QvZ6jIW3"),
+    ("Bootstrap 5.1.3 JS bundle", "sha384-ka7Sk0Gln4gmtz2MlQnikT1wXgYsOg+OMhuP+IlRH9sENBO0LRn5q+8nbTov4+1p"),
+    ("Bootstrap 5.0.2 CSS", "sha384-EVSTQN3/azprG1Anm3QDgpJLIm9Nao0Yz1ztcQTwFspd3yD65VohhpuuCOmLASjC"),
+    ("Bootstrap 5.0.2 JS bundle", "sha384-MrcW6ZMFYlzcLA8Nl+NtUVF0sA7MsXsP1UyJoMp4YLEuNSfAP+JcXn/tWtIaxVXM"),
+    ("Bootstrap 4.6.2 CSS", "sha384-xOolHFLEh07PJGoPkLv1IbcEPTNtaed2xpHsD9ESMhqIYd0nLMwNLD69Npy4HI+N"),
+    ("Bootstrap 4.6.2 JS bundle", "sha384-Fy6S3B9q64WdZWQUiU+q4/2Lc9npb8tCaSX9FK7E8HnRr0Jz8D6OP9dO5Vg3Q9ct"),
+    ("Bootstrap 4.5.2 CSS", "sha384-JcKb8q3iqJ61gNV9KGb8thSsNjpSL0n8PARn9HuZOnIxN0hoP+VmmDGMN5t9UJ0Z"),
+    ("Bootstrap 4.3.1 CSS", "sha384-ggOyR0iXCbMQv3Xipma34MD+dH/1fQ784/j6cY/iJTQUOhcWr7x9JvoRxT2MZw1T"),
+    ("Bootstrap 4.3.1 JS", "sha384-JjSmVgyd0p3pXB1rRibZUAYoIIy6OrQ6VrjIEaFf/nJGzIxFDsf4x0xIM+B07jRM"),
+    ("Bootstrap 3.4.1 CSS", "sha384-HSMxcRTRxnN+Bdg0JdbxYKrThecOKuH5zCYotlSAcp1+c8xmyTe9GYg1l9a69psu"),
+    ("Bootstrap 3.4.1 JS", "sha384-aJ21OjlMXNL5UyIl/XNwTMqvzeRMZH2w8c5cRVpzpU8Y5bApTppSuUkhZXN0VxHd"),
+    ("Font Awesome 6.5.1 all.min.css", "sha512-DTOQO9RWCH3ppGqcWaEA1BIZOC6xxalwEsw9c2QQeAIftl+Vegovlnee1c9QX4TctnWMn13TZye+giMm8e2LwA=="),
+    ("Font Awesome 6.4.2 all.min.css", "sha512-z3gLpd7yknf1YoNbCzqRKc4qyor8gaKU1qmn+CShxbuBusANI9QpRohGBreCFkKxLhei6S9CQXFEbbKuqLg0DA=="),
+    ("Font Awesome 6.4.0 all.min.css", "sha512-iecdLmaskl7CVkqkXNQ/ZH/XLlvWZOJyj7Yy7tcenmpD1ypASozpmT/E0iPtmFIB46ZmdtAc9eNBvH0H/ZpiBw=="),
+    ("Font Awesome 6.0.0 all.min.css", "sha512-9usAa10IRO0HhonpyAIVpjrylPvoDwiPUiKdWk5t3PyolY1cOd4DSE0Ga+ri4AuTroPR5aQvXU9xC6qOPnzFeg=="),
+    ("Font Awesome 5.15.4 all.min.css", "sha512-1ycn6IcaQQ40/MKBW2W4Rhis/DbILU74C1vSrLJxCq57o941Ym01SwNsOMqvEBFlcgUa6xLiPY/NS5R+E6ztJQ=="),
+    ("Font Awesome 5.15.3 all.min.css", "sha512-iBBXm8fW90+nuLcSKlbmrPcLa0OT92xO1BIsZ+ywDWZCvqsWgccV3gFoRBv0z+8dLJgyAHIhR35VZc2oM/gI1w=="),
+    ("Font Awesome 4.7.0 font-awesome.min.css", "sha512-SfTiTlX6kk+qitfevl/7LibUOeJWlt9rbyDn92a1DqWOw9vWG2MFoays0sgObmWazO5BQPiFucnnEAjpAB+/Sw=="),
+];
+
+/// Folders holding third-party code.
+const VENDOR_DIRS: &[&str] = &["node_modules", "vendor", "vendors", "bower_components"];
+
+/// Files with longer lines than this on average are taken as minified.
+const MINIFIED_LINE_LENGTH: usize = 300;
+/// Smaller files are never taken as minified, so a short one-liner written by a student is kept.
+const MINIFIED_MIN_SIZE: usize = 1000;
+
+/// Why a file was taken as third-party code, rather than written by the student.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VendorReason {
+    /// Matched the ignore list in the config.
+    Ignored,
+    /// The content matches the hash of a known library build, or a hash from the config.
+    KnownHash,
+    /// In a folder like `node_modules` or `vendor`.
+    VendorDirectory,
+    /// Named like a common library, e.g. `jquery-3.6.0.js`.
+    LibraryName,
+    /// Starts with the license banner of a released library.
+    LicenseBanner,
+    Minified,
+}
+
+impl fmt::Display for VendorReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            VendorReason::Ignored => "in the ignore list",
+            VendorReason::KnownHash => "known library",
+            VendorReason::VendorDirectory => "vendor folder",
+            VendorReason::LibraryName => "library name",
+            VendorReason::LicenseBanner => "library license banner",
+            VendorReason::Minified => "minified",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// A file that is neither validated nor graded, and why.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExcludedFile {
+    /// Path of the file, relative to the student directory.
+    pub file: PathBuf,
+    pub reason: VendorReason,
+}
+
+/// Tells vendored libraries and minified files apart from code written by the student.
+#[derive(Debug, Clone)]
+pub struct VendorDetector {
+    ignore: GlobSet,
+    /// Hashes of known library files.
+    hashes: HashSet<LibraryHash>,
+    detect: bool,
+}
+
+impl VendorDetector {
+    pub fn new(config: &VendorConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut ignore = GlobSetBuilder::new();
+        for pattern in &config.ignore {
+            ignore.add(
+                Glob::new(pattern)
+                    .map_err(|e| format!("Invalid ignore pattern “{}”: {}", pattern, e))?,
+            );
+        }
+
+        let mut hashes: HashSet<LibraryHash> = config
+            .hashes
+            .iter()
+            .map(|hash| parse_hash(hash))
+            .collect::<Result<_, _>>()?;
+        if config.detect {
+            for (_, hash) in KNOWN_LIBRARIES {
+                hashes.insert(parse_hash(hash)?);
+            }
+        }
+
+        Ok(VendorDetector {
+            ignore: ignore.build()?,
+            hashes,
+            detect: config.detect,
+        })
+    }
+
+    /// Function to check whether a file is third-party code. `relative_path` is relative to the student directory.
+    pub fn detect(&self, relative_path: &Path, content: &[u8]) -> Option<VendorReason> {
+        if self.ignore.is_match(relative_path) {
+            return Some(VendorReason::Ignored);
+        }
+        if self.is_known(content) {
+            return Some(VendorReason::KnownHash);
+        }
+        if !self.detect {
+            return None;
+        }
+
+        let in_vendor_dir = relative_path
+            .components()
+            .any(|c| VENDOR_DIRS.contains(&c.as_os_str().to_string_lossy().as_ref()));
+        if in_vendor_dir {
+            return Some(VendorReason::VendorDirectory);
+        }
+
+        // HTML pages are written by the student, even when they include a library
+        let extension = relative_path
+            .extension()
+            .map(|s| s.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if extension != "js" && extension != "css" {
+            return None;
+        }
+
+        if is_library_name(relative_path) {
+            return Some(VendorReason::LibraryName);
+        }
+        let content = String::from_utf8_lossy(content);
+        if has_license_banner(&content) {
+            return Some(VendorReason::LicenseBanner);
+        }
+        if is_minified(&content) {
+            return Some(VendorReason::Minified);
+        }
+        None
+    }
+
+    /// Function to check the content against the known hashes, with each algorithm they use.
+    fn is_known(&self, content: &[u8]) -> bool {
+        [
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Sha384,
+            HashAlgorithm::Sha512,
+        ]
+        .into_iter()
+        .filter(|algorithm| self.hashes.iter().any(|hash| hash.algorithm == *algorithm))
+        .any(|algorithm| {
+            self.hashes.contains(&LibraryHash {
+                algorithm,
+                digest: algorithm.digest(content),
+            })
+        })
+    }
+}
+
+fn is_library_name(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let version = Regex::new(r"^[.-]v?\d+\.\d+").unwrap();
+
+    name.contains(".min.")
+        || LIBRARIES.iter().any(|library| {
+            name.strip_prefix(library)
+                .is_some_and(|rest| rest.starts_with(['.', '-']))
+        })
+        || GENERIC_LIBRARIES.iter().any(|library| {
+            name.strip_prefix(library)
+                .is_some_and(|rest| version.is_match(rest))
+        })
+}
+
+/// Function to check for the banner libraries keep at the top of their files, e.g.
+/// `/*! jQuery v3.6.0 | (c) OpenJS Foundation and other contributors | jquery.org/license */`.
+/// A version number is required, so a student's own copyright notice isn't mistaken for one.
+fn has_license_banner(content: &str) -> bool {
+    let banner = Regex::new(r"(?s)^\s*/\*[!*](.{0,1000}?)\*/").unwrap();
+    let notice = Regex::new(r"(?i)licen[cs]e|\(c\)|copyright|©").unwrap();
+    let version = Regex::new(r"\bv?\d+\.\d+\.\d+").unwrap();
+
+    banner
+        .captures(content)
+        .is_some_and(|captures| notice.is_match(&captures[1]) && version.is_match(&captures[1]))
+}
+
+fn is_minified(content: &str) -> bool {
+    let lines = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .count();
+    content.len() >= MINIFIED_MIN_SIZE && lines > 0 && content.len() / lines > MINIFIED_LINE_LENGTH
+}
+
+/// The hash algorithms used for subresource integrity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum HashAlgorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "sha384" => Some(HashAlgorithm::Sha384),
+            "sha512" => Some(HashAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    /// The length of a digest, in bytes.
+    fn len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 => 32,
+            HashAlgorithm::Sha384 => 48,
+            HashAlgorithm::Sha512 => 64,
+        }
+    }
+
+    fn digest(&self, content: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Sha256 => Sha256::digest(content).to_vec(),
+            HashAlgorithm::Sha384 => Sha384::digest(content).to_vec(),
+            HashAlgorithm::Sha512 => Sha512::digest(content).to_vec(),
+        }
+    }
+}
+
+/// The hash of a known library file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct LibraryHash {
+    algorithm: HashAlgorithm,
+    digest: Vec<u8>,
+}
+
+/// Function to read a hash from the config, as a subresource integrity value, e.g. `sha384-<base64>`,
+/// or as hex, where the length tells the algorithm apart.
+fn parse_hash(hash: &str) -> Result<LibraryHash, Box<dyn std::error::Error>> {
+    let hash = hash.trim();
+    let invalid = |reason: &str| format!("Invalid library hash “{}”: {}", hash, reason);
+
+    let (algorithm, digest) = match hash.split_once('-') {
+        Some((name, encoded)) => {
+            let algorithm = HashAlgorithm::from_name(name)
+                .ok_or_else(|| invalid("expected sha256, sha384 or sha512"))?;
+            let digest = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|e| invalid(&e.to_string()))?;
+            (algorithm, digest)
+        }
+        None if hash.len().is_multiple_of(2) && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+            let digest: Vec<u8> = (0..hash.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hash[i..i + 2], 16).unwrap())
+                .collect();
+            let algorithm = [
+                HashAlgorithm::Sha256,
+                HashAlgorithm::Sha384,
+                HashAlgorithm::Sha512,
+            ]
+            .into_iter()
+            .find(|algorithm| algorithm.len() == digest.len())
+            .ok_or_else(|| invalid("not a SHA-256, SHA-384 or SHA-512 hash"))?;
+            (algorithm, digest)
+        }
+        None => {
+            return Err(invalid(
+                "expected a SHA-256, SHA-384 or SHA-512 hash as hex, or as “sha384-<base64>”",
+            )
+            .into())
+        }
+    };
+
+    if digest.len() != algorithm.len() {
+        return Err(invalid("the hash has the wrong length").into());
+    }
+    Ok(LibraryHash { algorithm, digest })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(path: &str, content: &str) -> Option<VendorReason> {
+        VendorDetector::new(&VendorConfig::default())
+            .unwrap()
+            .detect(Path::new(path), content.as_bytes())
+    }
+
+    #[test]
+    fn libraries_are_detected_by_name_folder_and_banner() {
+        let code = "function hei() {}\n";
+        assert_eq!(
+            detect("js/jquery.js", code),
+            Some(VendorReason::LibraryName)
+        );
+        assert_eq!(
+            detect("css/normalize-8.0.1.css", code),
+            Some(VendorReason::LibraryName)
+        );
+        assert_eq!(
+            detect("js/app.min.js", code),
+            Some(VendorReason::LibraryName)
+        );
+        assert_eq!(
+            detect("node_modules/x/index.js", code),
+            Some(VendorReason::VendorDirectory)
+        );
+        assert_eq!(
+            detect(
+                "js/lib.js",
+                "/*! Lib v1.2.3 | (c) Someone | MIT license */\nvar x;"
+            ),
+            Some(VendorReason::LicenseBanner)
+        );
+        assert_eq!(
+            detect("js/app.js", &"var a=1;".repeat(200)),
+            Some(VendorReason::Minified)
+        );
+    }
+
+    #[test]
+    fn student_files_with_generic_names_are_kept() {
+        let code = "/* Laget av Ola (c) 2024 */\nfunction tegn() {}\n";
+        assert_eq!(detect("js/chart.js", code), None);
+        assert_eq!(detect("css/reset.css", code), None);
+        assert_eq!(detect("css/normalize.css", code), None);
+        assert_eq!(detect("js/app.js", code), None);
+        assert_eq!(detect("jquery.html", code), None);
+    }
+
+    #[test]
+    fn known_hashes_and_ignored_files_are_detected() {
+        let content = b"function hei() {}\n";
+        let detector = VendorDetector::new(&VendorConfig {
+            detect: false,
+            ignore: vec!["**/lib/**".to_string()],
+            hashes: vec![Sha256::digest(content)
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect()],
+        })
+        .unwrap();
+
+        assert_eq!(
+            detector.detect(Path::new("js/app.js"), content),
+            Some(VendorReason::KnownHash)
+        );
+        assert_eq!(
+            detector.detect(Path::new("js/lib/x.js"), b""),
+            Some(VendorReason::Ignored)
+        );
+        assert_eq!(detector.detect(Path::new("js/jquery.js"), b""), None);
+    }
+
+    #[test]
+    fn copies_of_known_library_builds_are_detected_whatever_their_name() {
+        let content = b"/* Bootstrap */";
+        let sri = format!(
+            "sha384-{}",
+            base64::engine::general_purpose::STANDARD.encode(Sha384::digest(content))
+        );
+        let detector = VendorDetector::new(&VendorConfig {
+            hashes: vec![sri],
+            ..VendorConfig::default()
+        })
+        .unwrap();
+        assert_eq!(
+            detector.detect(Path::new("css/stil.css"), content),
+            Some(VendorReason::KnownHash)
+        );
+        assert_eq!(
+            detector.detect(Path::new("css/stil.css"), b"/* Egen */"),
+            None
+        );
+
+        // The built-in hashes are all valid
+        for (name, hash) in KNOWN_LIBRARIES {
+            assert!(parse_hash(hash).is_ok(), "{}", name);
+        }
+        assert!(parse_hash("sha1-AAAA").is_err());
+        assert!(parse_hash("abc").is_err());
+    }
+}